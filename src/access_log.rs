@@ -0,0 +1,280 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::http::{percent_encode_path, Request};
+use crate::http_date::{rfc3339, UtcTime};
+use crate::json::json_string;
+use crate::transfer;
+
+/// One served request, logged as a single line JSON object by [Entry::to_json].
+/// The key names are stable, log pipelines depend on them.
+pub struct Entry<'a> {
+    pub timestamp: SystemTime,
+    pub request: &'a Request,
+    pub status: u16,
+
+    /// Bytes written for the response, head included
+    pub bytes: u64,
+
+    /// Bytes of the response body alone
+    pub body_bytes: u64,
+    pub duration: Duration,
+    pub client_ip: Option<IpAddr>,
+}
+
+impl Entry<'_> {
+    pub fn to_json(&self) -> String {
+        let headers = &self.request.headers;
+        let fields = [
+            ("timestamp", json_string(&rfc3339(self.timestamp))),
+            ("method", json_string(headers.method.as_str())),
+            ("path", json_string(&headers.resource)),
+            ("query", json_optional(headers.query.as_deref())),
+            ("status", self.status.to_string()),
+            ("bytes", self.bytes.to_string()),
+            ("duration_ms", self.duration.as_millis().to_string()),
+            (
+                "client_ip",
+                json_optional(self.client_ip.map(|ip| ip.to_string()).as_deref()),
+            ),
+            ("user_agent", json_optional(headers.get("user-agent"))),
+            ("referer", json_optional(headers.get("referer"))),
+            ("protocol", json_string(headers.version.as_str())),
+        ];
+
+        let fields = fields
+            .iter()
+            .map(|(key, value)| format!("\"{key}\":{value}"))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", fields.join(","))
+    }
+
+    /// The entry in NCSA Common Log Format,
+    /// `host - - [10/Oct/2000:13:55:36 +0000] "GET /a.gif HTTP/1.1" 200 2326`.
+    /// The path is percent-encoded again, so the quoted request line stays
+    /// one field. A response without a body logs its size as `-`.
+    pub fn to_common_log(&self) -> String {
+        let headers = &self.request.headers;
+        let host = self
+            .client_ip
+            .map_or_else(|| "-".to_string(), |ip| ip.to_string());
+        let path = percent_encode_path(&headers.resource);
+        let query = headers
+            .query
+            .as_deref()
+            .map(|query| format!("?{}", query.replace('\\', "\\\\").replace('"', "\\\"")))
+            .unwrap_or_default();
+        let size = match self.body_bytes {
+            0 => "-".to_string(),
+            size => size.to_string(),
+        };
+
+        format!(
+            "{host} - - [{}] \"{} {path}{query} {}\" {} {size}",
+            common_log_date(self.timestamp),
+            headers.method.as_str(),
+            headers.version.as_str(),
+            self.status,
+        )
+    }
+}
+
+/// File the [Entry::to_common_log] lines are appended to. Shared by every
+/// connection, each line is written whole under the lock so lines of
+/// concurrent workers do not interleave.
+pub struct AccessLogFile {
+    file: Mutex<File>,
+}
+
+impl AccessLogFile {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn append(&self, entry: &Entry) -> io::Result<()> {
+        let line = format!("{}\n", entry.to_common_log());
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())
+    }
+}
+
+/// Passes a response through to `inner`, noting its status code and size
+pub struct ResponseRecorder<'a, W: Write> {
+    inner: &'a mut W,
+
+    /// Start of the status line, up to the status code
+    head: Vec<u8>,
+    bytes: u64,
+
+    /// The last four bytes of the head written so far
+    window: u32,
+
+    /// Length of the head, once its blank line was written
+    head_bytes: Option<u64>,
+}
+
+/// Length of `HTTP/1.1 200`
+const STATUS_LINE_PREFIX: usize = 12;
+
+impl<'a, W: Write> ResponseRecorder<'a, W> {
+    pub fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            head: Vec::with_capacity(STATUS_LINE_PREFIX),
+            bytes: 0,
+            window: 0,
+            head_bytes: None,
+        }
+    }
+
+    /// Status code of the response, once its status line was written
+    pub fn status(&self) -> Option<u16> {
+        std::str::from_utf8(self.head.get(9..STATUS_LINE_PREFIX)?)
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Bytes written after the head
+    pub fn body_bytes(&self) -> u64 {
+        self.head_bytes
+            .map_or(0, |head_bytes| self.bytes - head_bytes)
+    }
+}
+
+impl<W: Write> Write for ResponseRecorder<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+
+        let missing = STATUS_LINE_PREFIX - self.head.len();
+        self.head.extend_from_slice(&buf[..written.min(missing)]);
+
+        if self.head_bytes.is_none() {
+            if let Some(end) = transfer::find_end_of_head(&mut self.window, &buf[..written]) {
+                self.head_bytes = Some(self.bytes + end as u64 + 1);
+            }
+        }
+        self.bytes += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn json_optional(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Formats `time` the way Common Log Format does, in UTC:
+/// `10/Oct/2000:13:55:36 +0000`
+fn common_log_date(time: SystemTime) -> String {
+    let utc = UtcTime::from(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        utc.day,
+        utc.month_name(),
+        utc.year,
+        utc.hours,
+        utc.minutes,
+        utc.seconds,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    pub fn entry_as_json() {
+        let request = Request::parse(
+            b"GET /docs/a\"b.html?lang=en HTTP/1.1\r\nHost: example.com\r\n\
+              User-Agent: curl/8.0\r\n\r\n",
+        )
+        .unwrap();
+        let entry = Entry {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            request: &request,
+            status: 200,
+            bytes: 512,
+            body_bytes: 400,
+            duration: Duration::from_micros(2_500),
+            client_ip: Some("203.0.113.7".parse().unwrap()),
+        };
+
+        assert_eq!(
+            entry.to_json(),
+            "{\"timestamp\":\"2023-11-14T22:13:20.123Z\",\"method\":\"GET\",\
+             \"path\":\"/docs/a\\\"b.html\",\"query\":\"lang=en\",\"status\":200,\
+             \"bytes\":512,\"duration_ms\":2,\"client_ip\":\"203.0.113.7\",\
+             \"user_agent\":\"curl/8.0\",\"referer\":null,\"protocol\":\"HTTP/1.1\"}"
+        );
+    }
+
+    #[test]
+    pub fn recorder_notes_status_and_size() {
+        let mut output = Vec::new();
+        let mut recorder = ResponseRecorder::new(&mut output);
+        assert_eq!(recorder.status(), None);
+
+        recorder.write_all(b"HTTP/1.1 4").unwrap();
+        recorder.write_all(b"04 Not Found\r\n\r\n").unwrap();
+
+        assert_eq!(recorder.status(), Some(404));
+        assert_eq!(recorder.bytes(), 26);
+        assert_eq!(recorder.body_bytes(), 0);
+
+        recorder.write_all(b"missing").unwrap();
+        assert_eq!(recorder.body_bytes(), 7);
+        assert_eq!(output, b"HTTP/1.1 404 Not Found\r\n\r\nmissing");
+
+        // The blank line ending the head may be split across writes
+        let mut output = Vec::new();
+        let mut recorder = ResponseRecorder::new(&mut output);
+        recorder.write_all(b"HTTP/1.1 200 OK\r\n\r").unwrap();
+        recorder.write_all(b"\nbody").unwrap();
+        assert_eq!(recorder.body_bytes(), 4);
+    }
+
+    #[test]
+    pub fn entry_in_common_log_format() {
+        let request =
+            Request::parse(b"GET /my%20file.html?q=\"x\" HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .unwrap();
+        let mut entry = Entry {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            request: &request,
+            status: 200,
+            bytes: 612,
+            body_bytes: 512,
+            duration: Duration::from_micros(2_500),
+            client_ip: Some("203.0.113.7".parse().unwrap()),
+        };
+        assert_eq!(
+            entry.to_common_log(),
+            "203.0.113.7 - - [14/Nov/2023:22:13:20 +0000] \
+             \"GET /my%20file.html?q=\\\"x\\\" HTTP/1.1\" 200 512"
+        );
+
+        entry.client_ip = None;
+        entry.status = 304;
+        entry.body_bytes = 0;
+        assert!(entry.to_common_log().starts_with("- - - ["));
+        assert!(entry.to_common_log().ends_with("\" 304 -"));
+    }
+}
@@ -0,0 +1,338 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::mime;
+
+/// Media types compressed unless configured otherwise. An entry ending in `/*`
+/// covers every subtype.
+const DEFAULT_COMPRESSIBLE_TYPES: &[&str] = &[
+    "text/*",
+    "application/javascript",
+    "application/json",
+    "application/xml",
+];
+
+/// Content coding chosen for a response after looking at `Accept-Encoding`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// The content type is never compressed, so the response does not depend
+    /// on the client's `Accept-Encoding`
+    Identity,
+
+    /// The content type is compressible but the client does not accept gzip
+    NegotiatedIdentity,
+
+    /// The body is sent gzipped
+    Gzip,
+}
+
+impl Encoding {
+    /// Whether the choice depended on `Accept-Encoding`, which caches must be
+    /// told through `Vary`
+    pub fn negotiated(self) -> bool {
+        self != Encoding::Identity
+    }
+
+    /// Entity tag of the representation sent with this encoding, each coding
+    /// of the same file needs a distinct tag
+    pub fn etag(self, etag: String) -> String {
+        match self {
+            Encoding::Gzip => format!("{}-gzip\"", etag.trim_end_matches('"')),
+            _ => etag,
+        }
+    }
+}
+
+/// Decides which responses are worth compressing, based on their content type
+#[derive(Debug, Clone, Default)]
+pub struct CompressionPolicy {
+    /// Types compressed on top of [DEFAULT_COMPRESSIBLE_TYPES]
+    compressible: Vec<String>,
+
+    /// Types never compressed, even when listed as compressible
+    incompressible: Vec<String>,
+}
+
+impl CompressionPolicy {
+    pub fn new(compressible: Vec<String>, incompressible: Vec<String>) -> Self {
+        Self {
+            compressible,
+            incompressible,
+        }
+    }
+
+    /// Whether a response with the given `Content-Type` should be compressed
+    pub fn is_compressible(&self, content_type: &str) -> bool {
+        let media_type = mime::essence(content_type);
+
+        if self
+            .incompressible
+            .iter()
+            .any(|pattern| type_matches(pattern, media_type))
+        {
+            return false;
+        }
+
+        DEFAULT_COMPRESSIBLE_TYPES
+            .iter()
+            .copied()
+            .chain(self.compressible.iter().map(String::as_str))
+            .any(|pattern| type_matches(pattern, media_type))
+    }
+}
+
+/// Matches a media type against `type/subtype` or a `type/*` wildcard
+fn type_matches(pattern: &str, media_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => media_type
+            .split_once('/')
+            .is_some_and(|(kind, _)| kind.eq_ignore_ascii_case(prefix)),
+        None => pattern.eq_ignore_ascii_case(media_type),
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows a gzip encoded response.
+/// Codings with `q=0` are refused, `*` stands for any coding not listed.
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut wildcard = false;
+
+    for coding in accept_encoding.split(',') {
+        let mut parts = coding.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let refused = parts.any(|parameter| {
+            parameter
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+
+        if name.eq_ignore_ascii_case("gzip") || name.eq_ignore_ascii_case("x-gzip") {
+            return !refused;
+        }
+
+        if name == "*" {
+            wildcard = !refused;
+        }
+    }
+
+    wildcard
+}
+
+/// Compresses response bodies.
+///
+/// The server goes through this trait whenever it gzips a body, which lets
+/// tests stand in a compressor that fails.
+pub trait Compressor: Send + Sync {
+    /// Compresses `content` into a gzip stream
+    fn gzip(&self, content: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// [Compressor] backed by flate2
+#[derive(Debug, Default)]
+pub struct Flate2Compressor;
+
+impl Compressor for Flate2Compressor {
+    fn gzip(&self, content: &[u8]) -> io::Result<Vec<u8>> {
+        gzip(content)
+    }
+}
+
+/// Compressed bodies of served files, reused until the file is modified.
+///
+/// Holds at most `max_bytes` of compressed content, the entries cached first
+/// are evicted first.
+pub struct CompressedCache {
+    max_bytes: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+/// A file and the coding of its cached body
+type CacheKey = (PathBuf, Encoding);
+
+#[derive(Default)]
+struct CacheEntries {
+    bodies: HashMap<CacheKey, (SystemTime, Arc<Vec<u8>>)>,
+    inserted: VecDeque<CacheKey>,
+    bytes: usize,
+}
+
+impl CompressedCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The `encoding` body of the file at `path` last modified at `modified`,
+    /// from the cache or else produced by `compress` and cached
+    pub fn get_or_compress(
+        &self,
+        path: &Path,
+        modified: SystemTime,
+        encoding: Encoding,
+        compress: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<Vec<u8>>> {
+        if let Some(body) = self.get(path, modified, encoding) {
+            return Ok(body);
+        }
+
+        // Compressed without holding the lock, other files can be served
+        // from the cache meanwhile
+        let body = Arc::new(compress()?);
+        if body.len() <= self.max_bytes {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            let key = (path.to_path_buf(), encoding);
+            entries.insert(key, modified, Arc::clone(&body), self.max_bytes);
+        }
+
+        Ok(body)
+    }
+
+    /// The cached `encoding` body of the file at `path`, if it was cached
+    /// since the file was last modified at `modified`
+    pub fn get(
+        &self,
+        path: &Path,
+        modified: SystemTime,
+        encoding: Encoding,
+    ) -> Option<Arc<Vec<u8>>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (path.to_path_buf(), encoding);
+        entries
+            .bodies
+            .get(&key)
+            .filter(|(cached_modified, _)| *cached_modified == modified)
+            .map(|(_, body)| Arc::clone(body))
+    }
+}
+
+impl CacheEntries {
+    fn insert(
+        &mut self,
+        key: CacheKey,
+        modified: SystemTime,
+        body: Arc<Vec<u8>>,
+        max_bytes: usize,
+    ) {
+        self.remove(&key);
+        while self.bytes + body.len() > max_bytes {
+            let Some(oldest) = self.inserted.pop_front() else {
+                break;
+            };
+            if let Some((_, evicted)) = self.bodies.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+
+        self.bytes += body.len();
+        self.inserted.push_back(key.clone());
+        self.bodies.insert(key, (modified, body));
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((_, body)) = self.bodies.remove(key) {
+            self.bytes -= body.len();
+            self.inserted.retain(|inserted| inserted != key);
+        }
+    }
+}
+
+/// Compresses `content` into a gzip stream
+pub fn gzip(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(content)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    pub fn default_and_overridden_types() {
+        let default = CompressionPolicy::default();
+        assert!(default.is_compressible("text/html; charset=UTF-8"));
+        assert!(default.is_compressible("application/json"));
+        assert!(!default.is_compressible("image/svg+xml"));
+        assert!(!default.is_compressible("image/png"));
+
+        let policy = CompressionPolicy::new(
+            vec![
+                "image/svg+xml".to_string(),
+                "application/ld+json".to_string(),
+            ],
+            vec!["text/csv".to_string()],
+        );
+        assert!(policy.is_compressible("image/svg+xml"));
+        assert!(policy.is_compressible("application/ld+json"));
+        assert!(!policy.is_compressible("text/csv; charset=UTF-8"));
+        assert!(policy.is_compressible("text/plain"));
+    }
+
+    #[test]
+    pub fn gzip_representation_has_its_own_etag() {
+        assert_eq!(
+            Encoding::Gzip.etag("W/\"1-2\"".to_string()),
+            "W/\"1-2-gzip\""
+        );
+        assert_eq!(Encoding::Identity.etag("\"abc\"".to_string()), "\"abc\"");
+    }
+
+    #[test]
+    pub fn accept_encoding_negotiation() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, gzip;q=0.5, br"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("*, gzip;q=0"));
+        assert!(!accepts_gzip("br, deflate"));
+        assert!(!accepts_gzip(""));
+    }
+
+    #[test]
+    pub fn gzip_round_trip() {
+        let content = b"turbine turbine turbine turbine".repeat(10);
+        let compressed = gzip(&content).unwrap();
+        assert!(compressed.len() < content.len());
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, content);
+    }
+
+    #[test]
+    pub fn compressed_cache_is_bounded() {
+        let cache = CompressedCache::new(10);
+        let modified = SystemTime::UNIX_EPOCH;
+        let get = |path: &str, body: &[u8]| {
+            let body = body.to_vec();
+            cache
+                .get_or_compress(Path::new(path), modified, Encoding::Gzip, || Ok(body))
+                .unwrap()
+        };
+
+        assert_eq!(*get("a", b"aaaaaa"), b"aaaaaa");
+        assert_eq!(*get("a", b"other"), b"aaaaaa");
+
+        // `b` does not fit next to `a`, which is evicted
+        assert_eq!(*get("b", b"bbbbbb"), b"bbbbbb");
+        assert_eq!(*get("a", b"AAAAAA"), b"AAAAAA");
+
+        // Too large to be cached at all
+        assert_eq!(*get("c", b"ccccccccccc"), b"ccccccccccc");
+        assert_eq!(*get("c", b"C"), b"C");
+    }
+}
@@ -1,44 +1,935 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::Deserialize;
 
 use clap::Parser;
 
+use crate::etag::EtagMode;
+use crate::listing::ListingFormat;
+use crate::resolver::DEFAULT_MAX_SYMLINK_DEPTH;
+
 #[derive(Parser, Debug)]
 pub struct Args {
+    /// Path of the toml config file, `-` reads it from stdin
     #[clap(short, long, default_value = "turbine.toml")]
     pub config_file: PathBuf,
+
+    /// Port to listen on, replacing the one of `listen_addr` in the config file.
+    /// Rejected when the config file sets `listeners`.
+    #[clap(short, long)]
+    pub port: Option<u16>,
 }
 
+/// Server configuration, read from a toml file.
+///
+/// Every key is optional, but unknown keys are rejected so a typo does not
+/// silently fall back to the default. The file is the only source layered over
+/// the defaults: a key present in it, lists included, replaces the default as a
+/// whole.
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub document_root: PathBuf,
+
+    /// Address and port connections are accepted on, e.g. `127.0.0.1:8080`
+    /// to only accept local connections
+    pub listen_addr: SocketAddr,
+
+    /// Addresses connections are accepted on, see [Listener]. A single public
+    /// listener on `listen_addr` when empty.
+    pub listeners: Vec<Listener>,
+
+    /// How long an idle keep-alive connection is held open, in seconds.
+    /// Also advertised to clients through the `Keep-Alive` response header.
+    pub keep_alive_timeout_secs: u64,
+
+    /// Serve hidden files and directories, whose name starts with a dot. Off by
+    /// default since they tend to hold secrets like `.env` or `.git/config`.
+    pub serve_hidden: bool,
+
+    /// Hidden path prefixes served even when `serve_hidden` is off, by default
+    /// `/.well-known/` so ACME challenges and other well-known URIs work
+    pub allowed_hidden_paths: Vec<String>,
+
+    /// Paths never served, answered with a 404 as if missing: a prefix such as
+    /// `/private/`, or a pattern where `*` stands for any run of characters
+    /// such as `*.bak`. Checked on the resolved path, symlinks followed.
+    pub deny: Vec<String>,
+
+    /// Most symlinks followed resolving a request path, a longer chain or a
+    /// loop is answered with a 403. 0 serves nothing behind a symlink.
+    pub max_symlink_depth: usize,
+
+    /// Answer HTTP/1.1 requests without a `Host` header with a 400, as the
+    /// protocol requires
+    pub require_host: bool,
+
+    /// Answer a method that is not all uppercase, like `get`, with a 400 as
+    /// methods are case-sensitive. When off it is uppercased instead.
+    pub strict_method_case: bool,
+
+    /// Longest accepted request line (`GET /path HTTP/1.1`), in bytes
+    pub max_request_line_bytes: usize,
+
+    /// How long, in seconds, a client may take to complete the request line
+    /// once it started sending it. Guards against clients dribbling bytes to
+    /// hold the connection open.
+    pub request_line_timeout_secs: u64,
+
+    /// How long, in seconds, a read may wait for more of a request once it
+    /// started arriving. A client stalling longer is answered with a 408.
+    /// Must be at least 1.
+    pub read_timeout_secs: u64,
+
+    /// Longest accepted block of header lines following the request line, in
+    /// bytes. Larger ones are answered with a 431.
+    pub max_header_bytes: usize,
+
+    /// How many requests a single connection may serve before it is closed.
+    pub keep_alive_max_requests: usize,
+
+    /// Longest time, in seconds, a connection is kept open, however busy. The
+    /// request arriving after it is answered and the connection closed, so
+    /// load balancers get to spread clients anew. 0 means no limit.
+    pub keep_alive_max_lifetime_secs: u64,
+
+    /// How connections are distributed over threads, see [ConcurrencyModel]
+    pub concurrency_model: ConcurrencyModel,
+
+    /// With a thread pool, how many accepted connections may wait for a free
+    /// worker. Beyond that new connections are answered with a 503 right away
+    /// instead of piling up. Unbounded when unset.
+    pub max_queued_connections: Option<usize>,
+
+    /// Requests each client IP may make, see [RateLimit]. Unlimited when unset.
+    pub rate_limit: Option<RateLimit>,
+
+    /// Debugging aid: leave `Content-Length` out and close the connection after
+    /// every response, so the body is delimited by the connection closing
+    pub close_delimited_responses: bool,
+
+    /// What happens to requests a client pipelined after one whose response
+    /// closes the connection. They are never served, see [PipelinedAfterClose]
+    pub pipelined_after_close: PipelinedAfterClose,
+
+    /// How long, in seconds, writing a response may go without progress before
+    /// the connection is given up on
+    pub write_timeout_secs: u64,
+
+    /// For how long, in seconds, connections still arriving once a shutdown
+    /// started are answered with a 503 rather than left unanswered when the
+    /// listener closes
+    pub shutdown_reject_secs: u64,
+
+    /// Most files read from disk at once, to spare slow or constrained
+    /// storage under bursty load. Unbounded when not set, at least 1 otherwise.
+    pub max_concurrent_reads: Option<usize>,
+
+    /// How long, in seconds, a request waits for one of the
+    /// `max_concurrent_reads` before it is answered with a 503
+    pub read_slot_timeout_secs: u64,
+
+    /// Files at least this large are sent straight from disk in chunks rather
+    /// than read into memory first, unless the response needs their whole
+    /// content: compression, a strong ETag, a digest, a transformation,
+    /// substitution or a range
+    pub stream_threshold_bytes: u64,
+
+    /// Load the whole document root into memory at startup and serve every
+    /// request from that snapshot, never touching the disk afterwards
+    pub memory_snapshot: bool,
+
+    /// Upper bound on the total size of the files loaded by `memory_snapshot`
+    pub memory_snapshot_max_bytes: u64,
+
+    /// Files served for a request to a directory, e.g. `["index.html",
+    /// "index.json"]`. The first one present in the directory wins.
+    pub index_files: Vec<String>,
+
+    /// Path prefixes (e.g. `/downloads/`) under which a directory without an
+    /// index file is answered with a generated listing of its entries
+    pub listing_paths: Vec<String>,
+
+    /// Answer to a request for a directory without an index file that is not
+    /// listed, see [NoIndexStatus]
+    pub directory_no_index_status: NoIndexStatus,
+
+    /// Answer a request for a zero-byte file with 204 No Content instead of a
+    /// 200 with an empty body, for clients expecting it
+    pub empty_file_as_204: bool,
+
+    /// Answer `/` with a page telling turbine is running while the document
+    /// root holds no files, instead of a 404
+    pub empty_root_landing_page: bool,
+
+    /// Most entries shown on a generated directory listing, the page notes how
+    /// many more were left out
+    pub max_listing_entries: usize,
+
+    /// Format of a generated listing when the client's `Accept` header asks
+    /// for neither HTML nor JSON
+    pub listing_format: ListingFormat,
+
+    /// Answer a request whose `Accept` header rules out the type of what would
+    /// be served with 406 Not Acceptable. Off by default, serving it anyway.
+    pub strict_accept: bool,
+
+    /// Name of the cookie selecting one of the `variants`, e.g. `variant`
+    pub variant_cookie: Option<String>,
+
+    /// Maps a `variant_cookie` value to a directory inside the document root
+    /// whose files take precedence over the default ones, e.g. `b = "variants/b"`
+    pub variants: HashMap<String, PathBuf>,
+
+    /// Maps a host name, as sent in the `Host` header without its port, to
+    /// the document root serving it, e.g. `"blog.example.com" = "/srv/blog"`.
+    /// Names are compared ignoring case.
+    pub virtual_hosts: HashMap<String, PathBuf>,
+
+    /// Serve requests for a host missing from `virtual_hosts`, or without a
+    /// `Host` header, from `document_root`. When off they are answered with
+    /// 400 Bad Request.
+    pub virtual_host_fallback: bool,
+
+    /// Pages answering error statuses in place of the built-in ones, keyed
+    /// by 4xx or 5xx status code and relative to the document root, e.g.
+    /// `error_pages = { 404 = "404.html", 503 = "50x.html" }`. The pages are
+    /// read once at startup, a page that cannot be read then falls back to
+    /// the built-in one.
+    pub error_pages: HashMap<String, PathBuf>,
+
+    /// Largest request body accepted on any request, in bytes
+    pub max_body_bytes: usize,
+
+    /// Most query string parameters accepted on a request, more are refused
+    /// with a 400
+    pub max_query_params: usize,
+
+    /// Paths redirected elsewhere before any file is resolved, see [Redirect]
+    pub redirects: Vec<Redirect>,
+
+    /// Paths whose POSTed bodies are stored instead of serving a file
+    pub forms: Vec<FormHandler>,
+
+    /// Status refusing `CONNECT` requests, see [ConnectResponse]
+    pub connect_response: ConnectResponse,
+
+    /// Path of one of the `forms` that POSTs targeting a directory are routed
+    /// to. Without it such requests are refused with a 405.
+    pub directory_post_form: Option<String>,
+
+    /// Content type of files without an extension, so clean URLs such as
+    /// `/about` can be extensionless HTML pages
+    pub extensionless_content_type: String,
+
+    /// Sanity limit on the number of headers of a response. A response going
+    /// over it, most likely because of a misconfiguration, is logged.
+    pub max_response_headers: usize,
+
+    /// Drop the headers going over `max_response_headers` instead of only
+    /// logging them. The status line, `Content-Length` and connection headers
+    /// are always kept.
+    pub truncate_response_headers: bool,
+
+    /// Header names, e.g. `["Content-Type", "Content-Length"]`, sent first and
+    /// in this order, for proxies sensitive to the order. Other headers follow
+    /// in the usual, fixed, order.
+    pub response_header_order: Vec<String>,
+
+    /// `weak`, `strong` or `off`, see [EtagMode]
+    pub etag_mode: EtagMode,
+
+    /// Mixed into every ETag, e.g. a deploy or build id, so a deploy
+    /// invalidates cached copies even of files with unchanged size and mtime
+    pub etag_salt: String,
+
+    /// Send a `Digest: sha-256=...` header (RFC 3230) with served files, so
+    /// clients can verify what they downloaded. Digests of files are cached
+    /// until the file is modified.
+    pub digest_header: bool,
+
+    /// Environment variables, e.g. `TURBINE_ENV_API_URL`, whose `${NAME}`
+    /// placeholders in served text files are replaced by their value at startup.
+    /// Only listed variables are substituted so no secret leaks by accident.
+    pub substituted_env_vars: Vec<String>,
+
+    /// Gzip responses of compressible types for clients accepting it
+    pub compression: bool,
+
+    /// Cache the gzipped bodies of served files, up to this many bytes, so a
+    /// file is only compressed again once modified. 0 disables the cache.
+    pub compressed_cache_max_bytes: usize,
+
+    /// Keep the contents of served files in memory, up to this many bytes, so
+    /// a file is only read from disk again once modified. The files used
+    /// least recently are evicted first. 0 disables the cache.
+    #[serde(alias = "cache_size")]
+    pub file_cache_max_bytes: usize,
+
+    /// Media types compressed on top of the built-in text types, e.g.
+    /// `image/svg+xml`. `type/*` covers every subtype.
+    pub compressible_types: Vec<String>,
+
+    /// Media types never compressed, even if they are text, e.g. `text/csv`
+    pub incompressible_types: Vec<String>,
+
+    /// How often, in seconds, the watchdog checks that the document root is
+    /// still accessible. 0 disables the watchdog.
+    pub document_root_watchdog_secs: u64,
+
+    /// What the watchdog does once the document root becomes inaccessible
+    pub document_root_lost: DocumentRootLost,
+
+    /// Body served as `/robots.txt`, ahead of any file of that name. Disabled
+    /// when unset.
+    pub robots_txt: Option<String>,
+
+    /// Scheme and host, e.g. `https://example.com`, of the page URLs in a
+    /// generated `/sitemap.xml` listing every HTML file of the document root.
+    /// Disabled when unset.
+    pub sitemap_base_url: Option<String>,
+
+    /// How long, in seconds, a generated sitemap is reused before the document
+    /// root is walked again
+    pub sitemap_cache_secs: u64,
+
+    /// Log every answered request as a single line JSON object with its
+    /// timestamp, method, path, query, status, bytes, duration_ms, client_ip,
+    /// user_agent, referer and protocol, instead of a `GET /path 200 512 bytes`
+    /// line. Either is logged under the `access` target, at `warn` for a 4xx
+    /// and `error` for a 5xx.
+    pub access_log_json: bool,
+
+    /// File every answered request is appended to in NCSA Common Log Format,
+    /// e.g. `/var/log/turbine/access.log`. Disabled when unset.
+    pub access_log: Option<PathBuf>,
+
+    /// Where log messages are written, see [LogTarget]. Which ones is set by
+    /// the `RUST_LOG` environment variable, `info` and above by default.
+    pub log_target: LogTarget,
+
+    /// Path, e.g. `/version`, answering with the build version, git hash and
+    /// uptime as JSON. Disabled when unset.
+    pub version_endpoint: Option<String>,
+
+    /// Path, e.g. `/metrics`, answering with connection and request counters in
+    /// the Prometheus text format. Disabled when unset.
+    pub metrics_endpoint: Option<String>,
+}
+
+/// An address connections are accepted on. Connections of every listener are
+/// served by the same workers.
+///
+/// In the config file:
+/// ```toml
+/// [[listeners]]
+/// bind = "0.0.0.0:8080"
+///
+/// [[listeners]]
+/// bind = "127.0.0.1:8081"
+/// admin_only = true
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Listener {
+    /// Address and port, e.g. `127.0.0.1:8081`
+    pub bind: String,
+
+    /// Serve only the version and metrics endpoints, answering any other
+    /// request with a 404. Once a listener is admin only, the endpoints are no
+    /// longer served by the others.
+    pub admin_only: bool,
+
+    /// Replaces the `document_root` for this listener
+    pub document_root: Option<PathBuf>,
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:12345".to_string(),
+            admin_only: false,
+            document_root: None,
+        }
+    }
+}
+
+/// Threading strategy for serving connections.
+///
+/// In the config file: `concurrency_model = { model = "thread_pool", size = 16 }`,
+/// `concurrency_model = { model = "elastic_pool", min = 4, max = 1000,
+/// idle_timeout_secs = 60 }` or `concurrency_model = { model = "thread_per_connection" }`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case")]
+pub enum ConcurrencyModel {
+    /// A fixed set of `size` worker threads; further connections wait in a queue.
+    /// The default, with four threads per available CPU.
+    ThreadPool { size: usize },
+
+    /// Starts with `min` worker threads and adds more, up to `max`, while they
+    /// are all busy. Threads beyond `min` stop after `idle_timeout_secs` idle.
+    ElasticPool {
+        min: usize,
+        max: usize,
+        idle_timeout_secs: u64,
+    },
+
+    /// A new thread for every accepted connection, simple and good for debugging
+    ThreadPerConnection,
+}
+
+impl Default for ConcurrencyModel {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+        ConcurrencyModel::ThreadPool { size: cpus * 4 }
+    }
+}
+
+impl ConcurrencyModel {
+    /// Rejects a pool that could never run a connection
+    fn validate(&self) -> Result<()> {
+        match *self {
+            ConcurrencyModel::ThreadPool { size: 0 } => {
+                anyhow::bail!("concurrency_model thread_pool size must be at least 1")
+            }
+            ConcurrencyModel::ElasticPool { max: 0, .. } => {
+                anyhow::bail!("concurrency_model elastic_pool max must be at least 1")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Status of a request for a directory without an index file and no listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NoIndexStatus {
+    /// 404, hiding that the directory exists
+    #[default]
+    NotFound,
+
+    /// 403, telling the directory exists but may not be listed
+    Forbidden,
+}
+
+/// Handling of the requests left unread when a connection is closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelinedAfterClose {
+    /// Close right away. Unread data may make the connection reset before
+    /// the client read the last response.
+    #[default]
+    Discard,
+
+    /// Stop sending, then read and drop whatever the client still sends for
+    /// up to a second before closing, so the last response arrives intact
+    Drain,
+}
+
+/// How `CONNECT` requests, asking for a tunnel, are refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectResponse {
+    /// 405, the method is known but not allowed on any resource
+    #[default]
+    MethodNotAllowed,
+
+    /// 501, the server does not implement the method at all
+    NotImplemented,
+}
+
+/// Destination of the log messages.
+///
+/// In the config file: `log_target = { target = "stderr" }`,
+/// `log_target = { target = "file", path = "/var/log/turbine.log" }` or
+/// `log_target = { target = "syslog" }`
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(tag = "target", rename_all = "snake_case")]
+pub enum LogTarget {
+    #[default]
+    Stdout,
+
+    Stderr,
+
+    /// Appended to the file at `path`
+    File {
+        path: PathBuf,
+    },
+
+    /// Sent to the local syslog daemon, or journald, through its unix socket,
+    /// `/dev/log` unless configured otherwise. Log levels map to syslog
+    /// severities.
+    Syslog {
+        #[serde(default = "default_syslog_socket")]
+        socket: PathBuf,
+    },
+}
+
+fn default_syslog_socket() -> PathBuf {
+    PathBuf::from("/dev/log")
+}
+
+/// Reaction to the document root disappearing while the server runs,
+/// e.g. because the volume holding it was unmounted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentRootLost {
+    /// Answer every request with 503 until the document root is back
+    #[default]
+    Maintenance,
+
+    /// Stop the server
+    Shutdown,
+}
+
+/// Token bucket limiting the requests of a client IP, e.g.
+/// `rate_limit = { requests_per_sec = 10, burst = 50 }`. Requests beyond it are
+/// answered with 429 Too Many Requests.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Rate the bucket refills at
+    pub requests_per_sec: f64,
+
+    /// Requests a client may make at once after being idle, the bucket size
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// Rejects a limit no request could ever pass
+    fn validate(&self) -> Result<()> {
+        if !(self.requests_per_sec > 0.0 && self.requests_per_sec.is_finite()) {
+            anyhow::bail!("rate_limit requests_per_sec must be a positive number");
+        }
+        if self.burst == 0 {
+            anyhow::bail!("rate_limit burst must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// A rule sending requests for `from` to the `to` location, e.g. for URLs
+/// that moved during a migration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Redirect {
+    /// Request path the rule applies to, e.g. `/old/`. Without
+    /// `prefix_rewrite` it must match the path exactly, trailing slash
+    /// included: `/old/` does not cover `/old`.
+    pub from: String,
+
+    /// Location the client is sent to, e.g. `/`
+    pub to: String,
+
+    /// Answer with 301 Moved Permanently rather than 302 Found
+    #[serde(default)]
+    pub permanent: bool,
+
+    /// Apply the rule to every path under `from` as well, replacing the `from`
+    /// prefix with `to` and keeping the rest of the path and the query string.
+    /// With `from = "/old/"` and `to = "/"`, `/old/foo?a=1` goes to `/foo?a=1`.
+    #[serde(default)]
+    pub prefix_rewrite: bool,
+
+    /// Only apply the rule to requests whose `Host` matches, `*` matching any
+    /// run of characters. With `host_pattern = "*.example.org"`, `from = "/"`,
+    /// `to = "https://www.example.com/"` and `prefix_rewrite`, every alias
+    /// domain is sent to the same path on the main one.
+    #[serde(default)]
+    pub host_pattern: Option<String>,
+}
+
+/// A path, typically the target of a static site's contact form, whose POST
+/// submissions are appended to a file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormHandler {
+    /// Request path the form posts to, e.g. `/contact`
+    pub path: String,
+
+    /// File every submission is appended to, one submission per line
+    pub submissions_file: PathBuf,
+
+    /// Largest accepted submission in bytes, bigger ones are refused with a 413
+    pub max_body_bytes: usize,
+
+    /// Location the client is redirected to (303) once the submission is stored
+    pub success_redirect: Option<String>,
+
+    /// Resource served once the submission is stored when no redirect is set,
+    /// e.g. `/thanks.html`. A built-in page is used when neither is configured.
+    pub success_page: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             document_root: PathBuf::from("web_resources"),
+            listen_addr: SocketAddr::from(([0, 0, 0, 0], 12345)),
+            listeners: Vec::new(),
+            keep_alive_timeout_secs: 5,
+            serve_hidden: false,
+            max_symlink_depth: DEFAULT_MAX_SYMLINK_DEPTH,
+            allowed_hidden_paths: vec!["/.well-known/".to_string()],
+            deny: Vec::new(),
+            require_host: true,
+            strict_method_case: true,
+            max_request_line_bytes: 8 * 1024,
+            request_line_timeout_secs: 10,
+            read_timeout_secs: 30,
+            max_header_bytes: 8 * 1024,
+            keep_alive_max_requests: 100,
+            keep_alive_max_lifetime_secs: 0,
+            concurrency_model: ConcurrencyModel::default(),
+            max_queued_connections: None,
+            rate_limit: None,
+            close_delimited_responses: false,
+            pipelined_after_close: PipelinedAfterClose::default(),
+            write_timeout_secs: 30,
+            shutdown_reject_secs: 2,
+            max_concurrent_reads: None,
+            read_slot_timeout_secs: 30,
+            stream_threshold_bytes: 1024 * 1024,
+            memory_snapshot: false,
+            memory_snapshot_max_bytes: 64 * 1024 * 1024,
+            index_files: vec!["index.html".to_string()],
+            listing_paths: Vec::new(),
+            directory_no_index_status: NoIndexStatus::default(),
+            empty_file_as_204: false,
+            empty_root_landing_page: false,
+            max_listing_entries: 1000,
+            listing_format: ListingFormat::default(),
+            strict_accept: false,
+            variant_cookie: None,
+            variants: HashMap::new(),
+            virtual_hosts: HashMap::new(),
+            virtual_host_fallback: true,
+            error_pages: HashMap::new(),
+            max_body_bytes: 1024 * 1024,
+            max_query_params: 100,
+            redirects: Vec::new(),
+            forms: Vec::new(),
+            connect_response: ConnectResponse::default(),
+            directory_post_form: None,
+            extensionless_content_type: "text/html; charset=UTF-8".to_string(),
+            max_response_headers: 100,
+            truncate_response_headers: false,
+            response_header_order: Vec::new(),
+            etag_mode: EtagMode::default(),
+            etag_salt: String::new(),
+            digest_header: false,
+            substituted_env_vars: Vec::new(),
+            compression: true,
+            compressed_cache_max_bytes: 0,
+            file_cache_max_bytes: 0,
+            compressible_types: Vec::new(),
+            incompressible_types: Vec::new(),
+            document_root_watchdog_secs: 0,
+            document_root_lost: DocumentRootLost::default(),
+            robots_txt: None,
+            sitemap_base_url: None,
+            sitemap_cache_secs: 300,
+            access_log_json: false,
+            access_log: None,
+            log_target: LogTarget::default(),
+            version_endpoint: None,
+            metrics_endpoint: None,
         }
     }
 }
 
 impl Config {
     pub fn new(config_file: PathBuf) -> Result<Self> {
-        if !config_file.exists() || config_file.ends_with("toml") {
-            return Err(anyhow::anyhow!(
-                "Config file does not exist or is not a toml file"
-            ));
+        if config_file == Path::new("-") {
+            return Config::from_reader(std::io::stdin().lock(), Path::new("<stdin>"));
+        }
+
+        if !config_file.exists() {
+            anyhow::bail!("Config file {} does not exist", config_file.display());
         }
+        if config_file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            != Some("toml")
+        {
+            anyhow::bail!("Config file {} is not a toml file", config_file.display());
+        }
+
+        let content = std::fs::read_to_string(&config_file)
+            .with_context(|| format!("Failed to read config file {}", config_file.display()))?;
 
-        let content = std::fs::read_to_string(config_file)?;
+        Config::parse(&content, &config_file)
+    }
 
-        let config = match toml::from_str(&content) {
-            Ok(config) => config,
-            Err(_e) => Config::default(),
-        };
+    /// Reads and parses a whole toml config from `reader`, `name` is only used
+    /// to refer to it in errors
+    fn from_reader(mut reader: impl Read, name: &Path) -> Result<Self> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .with_context(|| format!("Failed to read config from {}", name.display()))?;
+
+        Config::parse(&content, name)
+    }
+
+    /// Rejects an `error_pages` key that is not an error status code
+    fn validate_error_pages(&self) -> Result<()> {
+        for status in self.error_pages.keys() {
+            let is_error = status.len() == 3
+                && status
+                    .parse::<u16>()
+                    .is_ok_and(|code| (400..=599).contains(&code));
+            if !is_error {
+                anyhow::bail!("error_pages key {status:?} is not a 4xx or 5xx status code");
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects limits that cannot be applied
+    fn validate_limits(&self) -> Result<()> {
+        if self.read_timeout_secs == 0 {
+            anyhow::bail!("read_timeout_secs must be at least 1");
+        }
+        if self.max_concurrent_reads == Some(0) {
+            anyhow::bail!("max_concurrent_reads must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Parses the toml `content` of `config_file`, which is only used to name
+    /// the file in errors
+    fn parse(content: &str, config_file: &Path) -> Result<Self> {
+        let config: Config = toml::from_str(content)
+            .with_context(|| format!("Invalid config file {}", config_file.display()))?;
+        config
+            .concurrency_model
+            .validate()
+            .with_context(|| format!("Invalid config file {}", config_file.display()))?;
+        if let Some(rate_limit) = &config.rate_limit {
+            rate_limit
+                .validate()
+                .with_context(|| format!("Invalid config file {}", config_file.display()))?;
+        }
+        config
+            .validate_error_pages()
+            .with_context(|| format!("Invalid config file {}", config_file.display()))?;
+        config
+            .validate_limits()
+            .with_context(|| format!("Invalid config file {}", config_file.display()))?;
 
         Ok(config)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let error =
+            Config::parse("documnet_root = \"site\"", Path::new("turbine.toml")).unwrap_err();
+        let message = format!("{error:#}");
+
+        assert!(message.contains("turbine.toml"), "{message}");
+        assert!(message.contains("documnet_root"), "{message}");
+    }
+
+    #[test]
+    fn unknown_form_key_is_rejected() {
+        let content = r#"
+            [[forms]]
+            path = "/contact"
+            submissions_file = "contact.log"
+            max_body_bytes = 1024
+            succes_page = "/thanks.html"
+        "#;
+        let error = Config::parse(content, Path::new("turbine.toml")).unwrap_err();
+
+        assert!(format!("{error:#}").contains("succes_page"));
+    }
+
+    #[test]
+    fn config_file_checks() {
+        let dir = std::env::temp_dir().join(format!("turbine-{}-config", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let message = |path: PathBuf| format!("{:#}", Config::new(path).unwrap_err());
+
+        assert!(message(dir.join("missing.toml")).contains("does not exist"));
+
+        std::fs::write(dir.join("turbine.yaml"), "max_body_bytes = 10").unwrap();
+        assert!(message(dir.join("turbine.yaml")).contains("is not a toml file"));
+
+        std::fs::write(dir.join("broken.toml"), "max_body_bytes = ").unwrap();
+        assert!(message(dir.join("broken.toml")).contains("Invalid config file"));
+
+        std::fs::write(dir.join("turbine.toml"), "max_body_bytes = 10").unwrap();
+        assert_eq!(
+            Config::new(dir.join("turbine.toml"))
+                .unwrap()
+                .max_body_bytes,
+            10
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn error_pages_by_status() {
+        let config = Config::parse(
+            "error_pages = { 404 = \"404.html\", 503 = \"errors/50x.html\" }",
+            Path::new("turbine.toml"),
+        )
+        .unwrap();
+        assert_eq!(config.error_pages["404"], Path::new("404.html"));
+        assert_eq!(config.error_pages["503"], Path::new("errors/50x.html"));
+
+        for content in [
+            "error_pages = { 200 = \"ok.html\" }",
+            "error_pages = { missing = \"404.html\" }",
+            "error_pages = { 0404 = \"404.html\" }",
+        ] {
+            assert!(
+                Config::parse(content, Path::new("turbine.toml")).is_err(),
+                "{content}"
+            );
+        }
+    }
+
+    #[test]
+    fn rate_limit_is_validated() {
+        let config = Config::parse(
+            "rate_limit = { requests_per_sec = 10, burst = 50 }",
+            Path::new("turbine.toml"),
+        )
+        .unwrap();
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimit {
+                requests_per_sec: 10.0,
+                burst: 50
+            })
+        );
+
+        for content in [
+            "rate_limit = { requests_per_sec = 0, burst = 5 }",
+            "rate_limit = { requests_per_sec = 0.5, burst = 0 }",
+        ] {
+            assert!(
+                Config::parse(content, Path::new("turbine.toml")).is_err(),
+                "{content}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_pools_are_rejected() {
+        for content in [
+            "concurrency_model = { model = \"thread_pool\", size = 0 }",
+            "concurrency_model = { model = \"elastic_pool\", min = 0, max = 0, idle_timeout_secs = 1 }",
+        ] {
+            let error = Config::parse(content, Path::new("turbine.toml")).unwrap_err();
+            assert!(format!("{error:#}").contains("must be at least 1"), "{content}");
+        }
+
+        let config = Config::parse(
+            "concurrency_model = { model = \"thread_pool\", size = 8 }",
+            Path::new("turbine.toml"),
+        )
+        .unwrap();
+        assert_eq!(
+            config.concurrency_model,
+            ConcurrencyModel::ThreadPool { size: 8 }
+        );
+    }
+
+    #[test]
+    fn zero_read_timeout_is_rejected() {
+        let error = Config::parse("read_timeout_secs = 0", Path::new("turbine.toml")).unwrap_err();
+        assert!(format!("{error:#}").contains("read_timeout_secs must be at least 1"));
+
+        let config = Config::parse("read_timeout_secs = 1", Path::new("turbine.toml")).unwrap();
+        assert_eq!(config.read_timeout_secs, 1);
+    }
+
+    #[test]
+    fn zero_concurrent_reads_are_rejected() {
+        let error =
+            Config::parse("max_concurrent_reads = 0", Path::new("turbine.toml")).unwrap_err();
+        assert!(format!("{error:#}").contains("max_concurrent_reads must be at least 1"));
+
+        let config = Config::parse("max_concurrent_reads = 1", Path::new("turbine.toml")).unwrap();
+        assert_eq!(config.max_concurrent_reads, Some(1));
+    }
+
+    #[test]
+    fn cache_size_is_an_alias() {
+        let config = Config::parse("cache_size = 4096", Path::new("turbine.toml")).unwrap();
+        assert_eq!(config.file_cache_max_bytes, 4096);
+    }
+
+    #[test]
+    fn missing_keys_use_defaults() {
+        let config =
+            Config::parse("keep_alive_max_requests = 7", Path::new("turbine.toml")).unwrap();
+
+        assert_eq!(config.keep_alive_max_requests, 7);
+        assert_eq!(config.document_root, PathBuf::from("web_resources"));
+    }
+
+    #[test]
+    fn listen_addr() {
+        let config =
+            Config::parse("listen_addr = \"127.0.0.1:8080\"", Path::new("t.toml")).unwrap();
+        assert_eq!(config.listen_addr, SocketAddr::from(([127, 0, 0, 1], 8080)));
+
+        assert_eq!(Config::default().listen_addr.port(), 12345);
+    }
+
+    #[test]
+    fn listeners() {
+        let config = Config::parse(
+            "[[listeners]]\nbind = \"0.0.0.0:8080\"\n\n\
+             [[listeners]]\nbind = \"127.0.0.1:8081\"\nadmin_only = true\n",
+            Path::new("turbine.toml"),
+        )
+        .unwrap();
+
+        assert_eq!(config.listeners.len(), 2);
+        assert!(!config.listeners[0].admin_only);
+        assert_eq!(config.listeners[1].bind, "127.0.0.1:8081");
+        assert!(config.listeners[1].admin_only);
+        assert_eq!(config.listeners[1].document_root, None);
+    }
+
+    #[test]
+    fn config_from_reader() {
+        let content = "document_root = \"site\"\nmax_body_bytes = 10\n";
+        let config = Config::from_reader(content.as_bytes(), Path::new("<stdin>")).unwrap();
+
+        assert_eq!(config.document_root, PathBuf::from("site"));
+        assert_eq!(config.max_body_bytes, 10);
+
+        let error = Config::from_reader("port =".as_bytes(), Path::new("<stdin>")).unwrap_err();
+        assert!(format!("{error:#}").contains("<stdin>"));
+    }
+
+    #[test]
+    fn log_target_defaults_syslog_socket() {
+        let config =
+            Config::parse("log_target = { target = \"syslog\" }", Path::new("t.toml")).unwrap();
+
+        assert_eq!(
+            config.log_target,
+            LogTarget::Syslog {
+                socket: PathBuf::from("/dev/log")
+            }
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+/// Value of a `Digest` header (RFC 3230) for `body`, e.g. `sha-256=47DEQ...=`
+pub fn sha256(body: &[u8]) -> String {
+    format!("sha-256={}", STANDARD.encode(Sha256::digest(body)))
+}
+
+/// Digests of served files, remembered until the file is modified
+#[derive(Default)]
+pub struct DigestCache {
+    /// Keyed by path and whether the body was gzipped, the digest covers the
+    /// bytes actually sent
+    digests: Mutex<HashMap<(PathBuf, bool), (SystemTime, String)>>,
+}
+
+impl DigestCache {
+    /// Digest of `body`, the content of the file at `path` last modified at
+    /// `modified`, gzipped or not
+    pub fn get(&self, path: &Path, modified: SystemTime, gzipped: bool, body: &[u8]) -> String {
+        let key = (path.to_path_buf(), gzipped);
+        let mut digests = self.digests.lock().unwrap_or_else(|e| e.into_inner());
+
+        match digests.get(&key) {
+            Some((cached_modified, digest)) if *cached_modified == modified => digest.clone(),
+            _ => {
+                let digest = sha256(body);
+                digests.insert(key, (modified, digest.clone()));
+                digest
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    pub fn sha256_of_known_content() {
+        assert_eq!(
+            sha256(b""),
+            "sha-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+        );
+        assert_eq!(
+            sha256(b"hello"),
+            "sha-256=LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+        );
+    }
+
+    #[test]
+    pub fn cache_is_refreshed_on_modification() {
+        let cache = DigestCache::default();
+        let path = Path::new("/site/index.html");
+        let modified = SystemTime::UNIX_EPOCH;
+
+        assert_eq!(cache.get(path, modified, false, b"hello"), sha256(b"hello"));
+        // Same modification time, the cached digest is reused
+        assert_eq!(cache.get(path, modified, false, b"other"), sha256(b"hello"));
+        assert_eq!(
+            cache.get(path, modified + Duration::from_secs(1), false, b"other"),
+            sha256(b"other")
+        );
+        assert_eq!(cache.get(path, modified, true, b"gzip"), sha256(b"gzip"));
+    }
+}
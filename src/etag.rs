@@ -0,0 +1,148 @@
+use serde::Deserialize;
+
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+/// How entity tags are generated for served files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EtagMode {
+    /// `W/"..."` derived from the file size and modification time. Cheap, but
+    /// two copies of a file with preserved mtimes cannot be told apart.
+    #[default]
+    Weak,
+
+    /// `"..."` derived from a hash of the content. Accurate but costs a pass
+    /// over the content on every request.
+    Strong,
+
+    /// No `ETag` header is sent and `If-None-Match` is ignored
+    Off,
+}
+
+/// Generates the entity tag for a file, `None` when ETags are turned off.
+///
+/// A non-empty `salt` is mixed in, so changing it changes every tag at once.
+pub fn generate(mode: EtagMode, salt: &str, metadata: &Metadata, content: &[u8]) -> Option<String> {
+    match mode {
+        EtagMode::Weak => {
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_nanos())
+                .unwrap_or_default();
+            if salt.is_empty() {
+                Some(format!("W/\"{:x}-{:x}\"", metadata.len(), modified))
+            } else {
+                let salt = fnv1a(salt.as_bytes());
+                Some(format!("W/\"{:x}-{modified:x}-{salt:x}\"", metadata.len()))
+            }
+        }
+        EtagMode::Strong => {
+            // An empty salt leaves the hash of the content as it is
+            let hash = fnv1a_from(fnv1a(salt.as_bytes()), content);
+            Some(format!("\"{hash:016x}\""))
+        }
+        EtagMode::Off => None,
+    }
+}
+
+/// Weak entity tag for content that is a fingerprint of the representation
+/// (e.g. a directory's entries) rather than its exact bytes
+pub fn weak_from_content(fingerprint: &[u8]) -> String {
+    format!("W/\"{:016x}\"", fnv1a(fingerprint))
+}
+
+/// Evaluates an `If-None-Match` header against the current entity tag.
+///
+/// Uses the weak comparison mandated for `If-None-Match` (RFC 7232 section
+/// 3.2): two tags match when their opaque parts are equal, regardless of
+/// either being weak.
+pub fn if_none_match(header: &str, etag: &str) -> bool {
+    let header = header.trim();
+    if header == "*" {
+        return true;
+    }
+
+    header
+        .split(',')
+        .any(|candidate| opaque_tag(candidate.trim()) == opaque_tag(etag))
+}
+
+/// Strips the weakness indicator from an entity tag
+fn opaque_tag(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// 64-bit FNV-1a, a fast and stable (across builds and platforms) content hash
+fn fnv1a(content: &[u8]) -> u64 {
+    fnv1a_from(0xcbf29ce484222325, content)
+}
+
+/// Continues an FNV-1a `hash` over more `content`
+fn fnv1a_from(hash: u64, content: &[u8]) -> u64 {
+    content.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    #[test]
+    pub fn weak_and_strong_generation() {
+        let metadata = fs::metadata("web_resources/index.html").unwrap();
+        let content = fs::read("web_resources/index.html").unwrap();
+
+        let weak = generate(EtagMode::Weak, "", &metadata, &content).unwrap();
+        assert!(weak.starts_with("W/\""));
+        assert!(weak.contains(&format!("{:x}-", metadata.len())));
+
+        let strong = generate(EtagMode::Strong, "", &metadata, &content).unwrap();
+        assert!(strong.starts_with('"') && strong.ends_with('"'));
+        assert_eq!(
+            strong,
+            generate(EtagMode::Strong, "", &metadata, &content).unwrap()
+        );
+        assert_ne!(
+            strong,
+            generate(EtagMode::Strong, "", &metadata, b"other").unwrap()
+        );
+
+        assert!(generate(EtagMode::Off, "", &metadata, &content).is_none());
+    }
+
+    #[test]
+    pub fn salt_changes_tags() {
+        let metadata = fs::metadata("web_resources/index.html").unwrap();
+        let content = fs::read("web_resources/index.html").unwrap();
+
+        for mode in [EtagMode::Weak, EtagMode::Strong] {
+            let unsalted = generate(mode, "", &metadata, &content).unwrap();
+            let first = generate(mode, "build-1", &metadata, &content).unwrap();
+            let second = generate(mode, "build-2", &metadata, &content).unwrap();
+
+            assert_ne!(unsalted, first);
+            assert_ne!(first, second);
+            assert_eq!(
+                first,
+                generate(mode, "build-1", &metadata, &content).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    pub fn if_none_match_uses_weak_comparison() {
+        assert!(if_none_match("\"abc\"", "\"abc\""));
+        assert!(if_none_match("W/\"abc\"", "\"abc\""));
+        assert!(if_none_match("\"abc\"", "W/\"abc\""));
+        assert!(if_none_match("\"xyz\", W/\"abc\"", "W/\"abc\""));
+        assert!(if_none_match("*", "\"abc\""));
+        assert!(!if_none_match("\"abcd\"", "\"abc\""));
+        assert!(!if_none_match("W/\"xyz\"", "W/\"abc\""));
+    }
+}
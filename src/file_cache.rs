@@ -0,0 +1,224 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Contents of served files kept in memory, keyed by the resolved path and
+/// reused while the file's modification time and length are unchanged.
+///
+/// Holds at most `max_bytes` of content, the entries used least recently are
+/// evicted first.
+pub struct FileCache {
+    max_bytes: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+/// What identifies a version of a file: its modification time and length.
+/// The length catches a rewrite within the resolution of the timestamp.
+type Version = (SystemTime, u64);
+
+struct CacheEntry {
+    version: Version,
+    content: Arc<Vec<u8>>,
+    /// Number of the latest use, see [CacheEntries::used]
+    last_use: u64,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    contents: HashMap<PathBuf, CacheEntry>,
+
+    /// Uses of cached paths, the least recent first. A path used again is
+    /// pushed anew rather than moved, its earlier uses are stale and skipped
+    /// when evicting, so both stay O(1).
+    used: VecDeque<(PathBuf, u64)>,
+    uses: u64,
+    bytes: usize,
+}
+
+impl FileCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The content of the file at `path`, last modified at `modified` and
+    /// `len` bytes long, from the cache or else produced by `read` and cached
+    pub fn get_or_read(
+        &self,
+        path: &Path,
+        modified: SystemTime,
+        len: u64,
+        read: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Arc<Vec<u8>>> {
+        let version = (modified, len);
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = entries.contents.get(path) {
+                if entry.version == version {
+                    let content = Arc::clone(&entry.content);
+                    entries.touch(path);
+                    return Ok(content);
+                }
+            }
+        }
+
+        // Read without holding the lock, other files can be served from the
+        // cache meanwhile
+        let content = Arc::new(read()?);
+        if content.len() <= self.max_bytes {
+            let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.insert(path, version, Arc::clone(&content), self.max_bytes);
+        }
+
+        Ok(content)
+    }
+
+    /// Whether the content of `path` is cached, whatever its version
+    #[cfg(test)]
+    fn contains(&self, path: &Path) -> bool {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.contents.contains_key(path)
+    }
+}
+
+impl CacheEntries {
+    fn insert(&mut self, path: &Path, version: Version, content: Arc<Vec<u8>>, max_bytes: usize) {
+        self.remove(path);
+        while self.bytes + content.len() > max_bytes {
+            let Some((least_used, used)) = self.used.pop_front() else {
+                break;
+            };
+            if self.is_latest_use(&least_used, used) {
+                self.remove(&least_used);
+            }
+        }
+
+        self.bytes += content.len();
+        self.contents.insert(
+            path.to_path_buf(),
+            CacheEntry {
+                version,
+                content,
+                last_use: 0,
+            },
+        );
+        self.touch(path);
+    }
+
+    /// Records a use of `path`, making it the most recently used
+    fn touch(&mut self, path: &Path) {
+        let Some(entry) = self.contents.get_mut(path) else {
+            return;
+        };
+        self.uses += 1;
+        entry.last_use = self.uses;
+        self.used.push_back((path.to_path_buf(), self.uses));
+
+        // Stale uses are dropped once they outnumber the live ones, which
+        // keeps the queue bounded at an amortized O(1) per use
+        if self.used.len() > 2 * self.contents.len() + 16 {
+            let contents = &self.contents;
+            self.used.retain(|(path, used)| {
+                contents
+                    .get(path)
+                    .is_some_and(|entry| entry.last_use == *used)
+            });
+        }
+    }
+
+    fn is_latest_use(&self, path: &Path, used: u64) -> bool {
+        self.contents
+            .get(path)
+            .is_some_and(|entry| entry.last_use == used)
+    }
+
+    /// Its uses left in the queue are stale from now on
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.contents.remove(path) {
+            self.bytes -= entry.content.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    pub fn least_recently_used_are_evicted_first() {
+        let cache = FileCache::new(10);
+        let modified = SystemTime::UNIX_EPOCH;
+        let get = |path: &str, content: &[u8]| {
+            let content = content.to_vec();
+            cache
+                .get_or_read(Path::new(path), modified, 4, || Ok(content))
+                .unwrap()
+        };
+
+        assert_eq!(*get("a", b"aaaa"), b"aaaa");
+        assert_eq!(*get("b", b"bbbb"), b"bbbb");
+        // Using `a` again makes `b` the one evicted for `c`
+        assert_eq!(*get("a", b"AAAA"), b"aaaa");
+        assert_eq!(*get("c", b"cccc"), b"cccc");
+        assert!(cache.contains(Path::new("a")));
+        assert!(!cache.contains(Path::new("b")));
+
+        // Too large to be cached at all
+        assert_eq!(*get("d", b"ddddddddddd"), b"ddddddddddd");
+        assert!(!cache.contains(Path::new("d")));
+    }
+
+    #[test]
+    pub fn repeated_uses_keep_the_queue_bounded() {
+        let cache = FileCache::new(8);
+        let modified = SystemTime::UNIX_EPOCH;
+        let get = |path: &str| {
+            cache
+                .get_or_read(Path::new(path), modified, 4, || Ok(b"data".to_vec()))
+                .unwrap()
+        };
+
+        get("a");
+        get("b");
+        for _ in 0..1000 {
+            get("b");
+        }
+        get("a");
+        assert!(cache.entries.lock().unwrap().used.len() < 100);
+
+        // `b` was used long ago and often, `a` last
+        get("c");
+        assert!(cache.contains(Path::new("a")));
+        assert!(!cache.contains(Path::new("b")));
+    }
+
+    #[test]
+    pub fn changed_files_are_read_again() {
+        let cache = FileCache::new(100);
+        let path = Path::new("/site/index.html");
+        let modified = SystemTime::UNIX_EPOCH;
+        let get = |modified, len, content: &[u8]| {
+            let content = content.to_vec();
+            cache
+                .get_or_read(path, modified, len, || Ok(content))
+                .unwrap()
+        };
+
+        assert_eq!(*get(modified, 5, b"hello"), b"hello");
+        assert_eq!(*get(modified, 5, b"other"), b"hello");
+        assert_eq!(
+            *get(modified + Duration::from_secs(1), 5, b"later"),
+            b"later"
+        );
+        assert_eq!(
+            *get(modified + Duration::from_secs(1), 6, b"longer"),
+            b"longer"
+        );
+    }
+}
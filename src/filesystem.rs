@@ -0,0 +1,43 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+/// Access to the files being served.
+///
+/// The server goes through this trait whenever it opens a resolved resource,
+/// which lets tests stand in a shim that simulates filesystem behavior such as
+/// files disappearing between resolution and opening.
+pub trait FileSystem: Send + Sync {
+    /// Reads the whole content of the file at `path`
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Opens the file at `path` to be read in pieces, for files too large to
+    /// hold in memory at once
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SeekableRead>>;
+
+    /// Queries the metadata (size, modification time, ...) of the file at `path`
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+}
+
+/// An opened file, read from any offset to send part of it
+pub trait SeekableRead: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> SeekableRead for T {}
+
+/// [FileSystem] backed by the operating system
+#[derive(Debug, Default)]
+pub struct OsFileSystem;
+
+impl FileSystem for OsFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn SeekableRead>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path)
+    }
+}
@@ -24,25 +24,145 @@ pub enum ParseError {
     #[error("Unknown or unsupported http method : {0}")]
     InvalidMethod(String),
 
+    #[error("Http method {0} is not supported")]
+    UnsupportedMethod(String),
+
     #[error("Path {0} is invalid")]
     InvalidPath(PathBuf),
+
+    #[error("Header line {0:?} is not a `name: value` pair")]
+    InvalidHeaderLine(String),
+
+    #[error("Content-Length {0:?} is not a valid length")]
+    InvalidContentLength(String),
+
+    #[error("Request body of {0} bytes exceeds the limit of {1} bytes")]
+    BodyTooLarge(usize, usize),
+
+    #[error("Query string has more than {0} parameters")]
+    TooManyQueryParams(usize),
+
+    #[error("Request line {0:?} contains control or non-ASCII characters")]
+    InvalidRequestLine(String),
+
+    #[error("Request line exceeds the limit of {0} bytes")]
+    RequestLineTooLong(usize),
+
+    #[error("Request line was not completed within {0:?}")]
+    RequestLineTimeout(std::time::Duration),
+
+    #[error("Path {0:?} is not validly percent-encoded")]
+    InvalidPercentEncoding(String),
+
+    #[error("Request headers exceed the limit of {0} bytes")]
+    HeadersTooLarge(usize),
+
+    #[error("Request was not received within {0:?}")]
+    RequestTimeout(std::time::Duration),
+
+    #[error("Http version {0:?} is not supported")]
+    UnsupportedVersion(String),
+
+    #[error("{0:?} is not an http version")]
+    InvalidVersion(String),
 }
 
+/// Standard methods (RFC 9110) that are not implemented. Unlike an unknown
+/// token they are refused as not allowed rather than as a malformed request.
+const UNSUPPORTED_METHODS: &[&str] = &["PUT", "DELETE", "PATCH", "OPTIONS", "TRACE"];
+
 /// Supported HTTP methods
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Method {
     Get,
+
+    /// Answered like [Method::Get], without the body
+    Head,
+
     Post,
+
+    /// Only recognized to be refused, tunneling makes no sense for a file
+    /// server. Its target is `host:port` rather than a path.
+    Connect,
+}
+
+const CONTINUE_EXPECTATION: &str = "100-continue";
+
+impl Method {
+    /// Whether the method retrieves the resource, GET or HEAD
+    pub fn is_retrieval(self) -> bool {
+        matches!(self, Method::Get | Method::Head)
+    }
+
+    /// The method as written in the request line
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Connect => "CONNECT",
+        }
+    }
+}
+
+/// Supported HTTP versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// Closes the connection after each response unless the client asks
+    /// for `Connection: keep-alive`
+    Http10,
+
+    Http11,
+}
+
+impl HttpVersion {
+    /// Parses the version of a request line. A well formed version other
+    /// than 1.0 or 1.1, such as `HTTP/2.0`, is unsupported rather than
+    /// invalid.
+    fn parse(version: &str) -> Result<Self, ParseError> {
+        match version {
+            "HTTP/1.0" => Ok(HttpVersion::Http10),
+            "HTTP/1.1" => Ok(HttpVersion::Http11),
+            _ => {
+                let well_formed = version.strip_prefix("HTTP/").is_some_and(|number| {
+                    let (major, minor) = number.split_once('.').unwrap_or((number, "0"));
+                    [major, minor].iter().all(|digits| {
+                        !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit())
+                    })
+                });
+                Err(if well_formed {
+                    ParseError::UnsupportedVersion(version.to_string())
+                } else {
+                    ParseError::InvalidVersion(version.to_string())
+                })
+            }
+        }
+    }
+
+    /// The version as written in the request and status lines
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+        }
+    }
 }
 
 /// Representation of HTTP headers
 #[derive(Debug)]
 pub struct Headers {
     pub method: Method,
+
+    /// The path of the request target, without the query string and with
+    /// its percent escapes decoded
     pub resource: String,
-    pub version: String,
 
-    // All the possible http headers will be stored here
+    /// The raw query string of the request target, without the leading `?`
+    pub query: Option<String>,
+
+    pub version: HttpVersion,
+
+    // All the possible http headers will be stored here, keyed by lowercased name
     pub other_headers: HashMap<String, String>,
 }
 
@@ -56,23 +176,161 @@ impl Headers {
 
         let method = match headers[0] {
             "GET" => Method::Get,
+            "HEAD" => Method::Head,
             "POST" => Method::Post,
+            "CONNECT" => Method::Connect,
+            known if UNSUPPORTED_METHODS.contains(&known) => {
+                return Err(ParseError::UnsupportedMethod(known.to_string()))
+            }
             unknown => return Err(ParseError::InvalidMethod(unknown.to_string())),
         };
 
-        let resource = headers[1].to_string();
-        let version = headers[2].to_string();
+        let (path, query) = match headers[1].split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (headers[1], None),
+        };
+        // Decoded before the path is resolved, so an encoded `..` is caught
+        // by the same containment check as a plain one
+        let resource = percent_decode(path)
+            .ok_or_else(|| ParseError::InvalidPercentEncoding(path.to_string()))?;
+        let version = HttpVersion::parse(headers[2])?;
 
-        // TODO: Parse other headers
+        // Filled in by [Request::parse] from the lines following the request line
         let other_headers = HashMap::new();
 
         Ok(Headers {
             method,
             resource,
+            query,
             version,
             other_headers,
         })
     }
+
+    /// Stores a `name: value` header line.
+    ///
+    /// Names are lowercased. A repeated header is combined with the earlier
+    /// value into a comma separated list, as allowed by RFC 7230 section 3.2.2.
+    fn insert_header_line(&mut self, line: &str) -> Result<(), ParseError> {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| ParseError::InvalidHeaderLine(line.to_string()))?;
+
+        let name = name.trim().to_ascii_lowercase();
+        let value = value.trim();
+
+        self.other_headers
+            .entry(name)
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+
+        Ok(())
+    }
+
+    /// Parses the query string into a map of its `key=value` parameters.
+    /// A key without `=` maps to an empty value, the last of repeated keys wins.
+    /// Keys and values are decoded, `+` standing for a space, and kept as sent
+    /// when not validly encoded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error, before building the map, when there are more than
+    /// `max_params` parameters
+    pub fn query_params(&self, max_params: usize) -> Result<HashMap<String, String>, ParseError> {
+        let Some(query) = &self.query else {
+            return Ok(HashMap::new());
+        };
+
+        let params = query.split('&').filter(|param| !param.is_empty());
+        if params.clone().count() > max_params {
+            return Err(ParseError::TooManyQueryParams(max_params));
+        }
+
+        let decode = |text: &str| {
+            let text = text.replace('+', " ");
+            percent_decode(&text).unwrap_or(text)
+        };
+
+        Ok(params
+            .map(|param| {
+                let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                (decode(key), decode(value))
+            })
+            .collect())
+    }
+
+    /// Case-insensitive lookup of a header value
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.other_headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Value of the cookie `name` sent in the `Cookie` header
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.get("cookie")?
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.trim_matches('"'))
+    }
+
+    /// Expectation of the `Expect` header other than `100-continue`, which
+    /// is the only one defined
+    pub fn unsupported_expectation(&self) -> Option<&str> {
+        self.get("expect")
+            .filter(|expect| !expect.eq_ignore_ascii_case(CONTINUE_EXPECTATION))
+    }
+
+    /// The `Host` header without its port, lowercased
+    pub fn host_name(&self) -> Option<String> {
+        let host = self.get("host")?;
+        // The colons of a bracketed IPv6 address are not followed by a port
+        let name = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        Some(name.to_ascii_lowercase())
+    }
+
+    /// Whether this is an HTTP/1.1 request without the `Host` header it must
+    /// carry. HTTP/1.0 did not require it.
+    pub fn missing_host(&self) -> bool {
+        self.version == HttpVersion::Http11 && self.get("host").is_none()
+    }
+
+    /// Whether the connection is to be closed after the response: asked for
+    /// with `Connection: close`, or HTTP/1.0 without `Connection: keep-alive`
+    pub fn wants_close(&self) -> bool {
+        let has_option = |name: &str| {
+            self.get("connection").is_some_and(|connection| {
+                connection
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case(name))
+            })
+        };
+
+        has_option("close") || (self.version == HttpVersion::Http10 && !has_option("keep-alive"))
+    }
+
+    /// Whether the client waits for a `100 Continue` before sending the body
+    pub fn expects_continue(&self) -> bool {
+        self.get("expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case(CONTINUE_EXPECTATION))
+    }
+
+    /// Length of the body announced by the `Content-Length` header, 0 when absent
+    pub fn content_length(&self) -> Result<usize, ParseError> {
+        match self.get("content-length") {
+            Some(length) => length
+                .parse()
+                .map_err(|_| ParseError::InvalidContentLength(length.to_string())),
+            None => Ok(0),
+        }
+    }
 }
 
 /// Representation of a HTTP request
@@ -83,26 +341,153 @@ pub struct Request {
 }
 
 impl Request {
-    pub fn new(request: String) -> Result<Request, ParseError> {
-        let lines: Vec<_> = request.split("\r\n").collect();
+    /// Parses the head of a request, from the request line up to the blank
+    /// line, straight from the received bytes.
+    ///
+    /// Lines are borrowed from `head` and only copied when they are not valid
+    /// UTF-8, in which case the invalid sequences are replaced.
+    pub fn parse(head: &[u8]) -> Result<Request, ParseError> {
+        let mut lines = crlf_lines(head);
 
-        let first_line = lines.first().ok_or(ParseError::EmptyRequest)?;
+        let first_line = lines.next().ok_or(ParseError::EmptyRequest)?;
+        // Only printable ASCII may appear between the delimiting CRLFs, a stray
+        // CR or LF could smuggle in a header
+        if !first_line.iter().all(|byte| (b' '..=b'~').contains(byte)) {
+            return Err(ParseError::InvalidRequestLine(
+                String::from_utf8_lossy(first_line).to_string(),
+            ));
+        }
+        let first_line = String::from_utf8_lossy(first_line);
 
         let words = first_line.split_whitespace().collect::<Vec<_>>();
 
-        let headers = Headers::new(words)?;
+        let mut headers = Headers::new(words)?;
 
-        // todo: Extract the body when we're dealing with POST requests
+        for line in lines.filter(|line| !line.is_empty()) {
+            headers.insert_header_line(&String::from_utf8_lossy(line))?;
+        }
+
+        // The body follows the headers on the stream, it is read separately
+        // once the Content-Length is known
         let body = Vec::new();
 
         Ok(Request { headers, body })
     }
 }
 
+/// Splits `bytes` on every `\r\n`, like [str::split] would
+fn crlf_lines(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = Some(bytes);
+
+    std::iter::from_fn(move || {
+        let current = rest?;
+        match current.windows(2).position(|window| window == b"\r\n") {
+            Some(end) => {
+                rest = Some(&current[end + 2..]);
+                Some(&current[..end])
+            }
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+/// Percent-encodes everything but the unreserved URI characters (RFC 3986),
+/// making `segment` safe to use as a single path segment of a link
+pub fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    encoded
+}
+
+/// Percent-encodes each segment of the decoded `path`, keeping its slashes
+pub fn percent_encode_path(path: &str) -> String {
+    path.split('/')
+        .map(percent_encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Decodes the `%XX` escapes of a request path, `None` when an escape is
+/// malformed or the decoded path is not UTF-8 or holds a control character.
+/// A decoded CR or LF must never reach a header or a log line.
+pub fn percent_decode(path: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+
+        let hex = [bytes.next()?, bytes.next()?];
+        let byte = u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?;
+        if byte.is_ascii_control() {
+            return None;
+        }
+        decoded.push(byte);
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
 /// Specifies a valid HTTP path after parsing
 #[derive(Debug)]
 pub struct HttpPath(PathBuf);
 
+/// Index file looked for in a directory unless configured otherwise
+pub const DEFAULT_INDEX_FILE: &str = "index.html";
+
+impl HttpPath {
+    /// The index file of `directory`, which must already be canonicalized:
+    /// the first of `index_files` present in it, or the first one when there
+    /// is none
+    pub fn index_of<S: AsRef<str>>(directory: &Path, index_files: &[S]) -> Self {
+        let candidates = index_files.iter().map(|name| directory.join(name.as_ref()));
+        let first = candidates
+            .clone()
+            .next()
+            .unwrap_or_else(|| directory.join(DEFAULT_INDEX_FILE));
+
+        HttpPath(
+            candidates
+                .into_iter()
+                .find(|path| path.is_file())
+                .unwrap_or(first),
+        )
+    }
+
+    /// Canonicalizes `path`, mapping a directory to its index file, see
+    /// [HttpPath::index_of]
+    pub fn resolve<S: AsRef<str>>(path: PathBuf, index_files: &[S]) -> Result<Self, ParseError> {
+        // todo: we don't need to do this again right, since it happens
+        // right before we create the resolver.
+        let canonicalized_path = fs::canonicalize(path)?;
+
+        if canonicalized_path.is_file() {
+            return Ok(HttpPath(canonicalized_path));
+        }
+
+        if canonicalized_path.is_dir() {
+            return Ok(HttpPath::index_of(&canonicalized_path, index_files));
+        }
+
+        Err(ParseError::InvalidPath(canonicalized_path))
+    }
+}
+
 impl Deref for HttpPath {
     type Target = PathBuf;
 
@@ -119,26 +504,250 @@ impl AsRef<Path> for HttpPath {
 
 /// Usage:
 /// ```rust
-/// let path = HttpPath::try_from("/web_resources/index.html".to_string());
+/// # use std::path::PathBuf;
+/// # use turbine::http::HttpPath;
+/// let path = HttpPath::try_from(PathBuf::from("/web_resources/index.html"));
 /// ```
 impl TryFrom<PathBuf> for HttpPath {
     type Error = ParseError;
 
     fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
-        
-        // todo: we don't need to do this again right, since it happens
-        // right before we create the resolver.
-        let canonicalized_path = fs::canonicalize(path)?;
+        // assume index.html as the default file to look for when the path is a directory
+        HttpPath::resolve(path, &[DEFAULT_INDEX_FILE])
+    }
+}
 
-        if canonicalized_path.is_file() {
-            return Ok(HttpPath(canonicalized_path));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    /// The parser [Request::parse] replaced, converting the whole head to a
+    /// `String` before splitting it
+    fn parse_from_string(request: String) -> Result<Request, ParseError> {
+        let lines: Vec<_> = request.split("\r\n").collect();
+
+        let first_line = lines.first().ok_or(ParseError::EmptyRequest)?;
+
+        let words = first_line.split_whitespace().collect::<Vec<_>>();
+
+        let mut headers = Headers::new(words)?;
+
+        for line in lines.iter().skip(1).filter(|line| !line.is_empty()) {
+            headers.insert_header_line(line)?;
         }
 
-        // assume index.html as the default file to look for when the path is a directory
-        if canonicalized_path.is_dir() {
-            return Ok(HttpPath(canonicalized_path.join("index.html")));
+        Ok(Request {
+            headers,
+            body: Vec::new(),
+        })
+    }
+
+    fn parse_both(head: &[u8]) -> (Result<Request, ParseError>, Result<Request, ParseError>) {
+        (
+            Request::parse(head),
+            parse_from_string(String::from_utf8_lossy(head).to_string()),
+        )
+    }
+
+    const HEADS: &[&[u8]] = &[
+        b"GET / HTTP/1.1\r\n\r\n",
+        b"GET /index.html?a=1&b HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl\r\n\r\n",
+        b"POST /contact HTTP/1.1\r\nContent-Length: 3\r\naccept: a\r\nAccept: b\r\n\r\n",
+        b"GET /caf%C3%A9 HTTP/1.1\r\nX-Name: \xff\xfe\r\n\r\n",
+        b"GET / HTTP/1.1",
+        b"GET /  HTTP/1.1 extra\r\n\r\n",
+        b"DELETE / HTTP/1.1\r\n\r\n",
+        b"GET / HTTP/1.1\r\nno colon here\r\n\r\n",
+        b"",
+        b"\r\n\r\n",
+    ];
+
+    #[test]
+    pub fn parse_matches_string_parser() {
+        for head in HEADS {
+            match parse_both(head) {
+                (Ok(parsed), Ok(expected)) => {
+                    assert_eq!(parsed.headers.method, expected.headers.method);
+                    assert_eq!(parsed.headers.resource, expected.headers.resource);
+                    assert_eq!(parsed.headers.query, expected.headers.query);
+                    assert_eq!(parsed.headers.version, expected.headers.version);
+                    assert_eq!(parsed.headers.other_headers, expected.headers.other_headers);
+                }
+                (Err(parsed), Err(expected)) => {
+                    assert_eq!(parsed.to_string(), expected.to_string())
+                }
+                (parsed, expected) => {
+                    panic!(
+                        "{:?}: {parsed:?} != {expected:?}",
+                        String::from_utf8_lossy(head)
+                    )
+                }
+            }
         }
+    }
 
-        Err(ParseError::InvalidPath(canonicalized_path))
+    #[test]
+    pub fn header_lines_are_parsed() {
+        let request = Request::parse(
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nUSER-AGENT:  curl/8.0 \r\n\
+              Accept: text/html\r\naccept: */*\r\n\r\n",
+        )
+        .unwrap();
+        let headers = &request.headers;
+
+        assert_eq!(headers.get("Host"), Some("example.com"));
+        assert_eq!(headers.get("host"), Some("example.com"));
+        assert_eq!(headers.get("User-Agent"), Some("curl/8.0"));
+        // Repeated headers are combined rather than overwritten
+        assert_eq!(headers.get("Accept"), Some("text/html, */*"));
+        assert_eq!(headers.get("Referer"), None);
+
+        assert!(matches!(
+            Request::parse(b"GET / HTTP/1.1\r\nno colon here\r\n\r\n"),
+            Err(ParseError::InvalidHeaderLine(line)) if line == "no colon here"
+        ));
+    }
+
+    #[test]
+    pub fn control_and_non_ascii_request_lines_are_rejected() {
+        let heads: &[&[u8]] = &[
+            b"GET /a\0b HTTP/1.1\r\n\r\n",
+            b"GET /a\rSet-Cookie: x=1 HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\nHost: example.com\n\n",
+            b"GET /caf\xc3\xa9 HTTP/1.1\r\n\r\n",
+            b"GET /\x7f HTTP/1.1\r\n\r\n",
+            b"GET\t/ HTTP/1.1\r\n\r\n",
+        ];
+
+        for head in heads {
+            assert!(
+                matches!(Request::parse(head), Err(ParseError::InvalidRequestLine(_))),
+                "{:?}",
+                String::from_utf8_lossy(head)
+            );
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored parse_benchmark --nocapture`
+    #[test]
+    #[ignore]
+    pub fn parse_benchmark() {
+        let head = b"GET /assets/app.js?v=3 HTTP/1.1\r\nHost: example.com\r\n\
+            User-Agent: Mozilla/5.0 (X11; Linux x86_64)\r\nAccept: */*\r\n\
+            Accept-Encoding: gzip, deflate, br\r\nConnection: keep-alive\r\n\r\n";
+        let iterations = 200_000;
+
+        let started = Instant::now();
+        for _ in 0..iterations {
+            Request::parse(head).unwrap();
+        }
+        let from_bytes = started.elapsed();
+
+        let started = Instant::now();
+        for _ in 0..iterations {
+            parse_from_string(String::from_utf8_lossy(head).to_string()).unwrap();
+        }
+        let from_string = started.elapsed();
+
+        println!("{iterations} requests: from bytes {from_bytes:?}, from string {from_string:?}");
+    }
+
+    #[test]
+    pub fn paths_are_percent_decoded() {
+        let resource =
+            |target| Headers::new(vec!["GET", target, "HTTP/1.1"]).map(|headers| headers.resource);
+
+        assert_eq!(resource("/my%20file.html").unwrap(), "/my file.html");
+        assert_eq!(resource("/a%2Fb%2fc").unwrap(), "/a/b/c");
+        assert_eq!(resource("/caf%C3%A9?q=%20").unwrap(), "/café");
+        assert_eq!(resource("/%2E%2E/%2e%2e/etc").unwrap(), "/../../etc");
+
+        for invalid in [
+            "/%",
+            "/%2",
+            "/%zz",
+            "/%00",
+            "/%FF",
+            "/a%0D%0Ab",
+            "/%09",
+            "/%7F",
+        ] {
+            assert!(
+                matches!(
+                    resource(invalid),
+                    Err(ParseError::InvalidPercentEncoding(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn versions() {
+        let version = |head: &[u8]| Request::parse(head).map(|request| request.headers.version);
+
+        assert_eq!(
+            version(b"GET / HTTP/1.0\r\n\r\n").unwrap(),
+            HttpVersion::Http10
+        );
+        assert_eq!(
+            version(b"GET / HTTP/1.1\r\n\r\n").unwrap(),
+            HttpVersion::Http11
+        );
+        for unsupported in ["HTTP/2.0", "HTTP/0.9", "HTTP/3"] {
+            let head = format!("GET / {unsupported}\r\n\r\n");
+            assert!(
+                matches!(
+                    version(head.as_bytes()),
+                    Err(ParseError::UnsupportedVersion(_))
+                ),
+                "{unsupported}"
+            );
+        }
+        for invalid in ["http/1.1", "HTTP/1.x", "HTTP/", "FOO"] {
+            let head = format!("GET / {invalid}\r\n\r\n");
+            assert!(
+                matches!(version(head.as_bytes()), Err(ParseError::InvalidVersion(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn host_names() {
+        let host_name = |head: &[u8]| Request::parse(head).unwrap().headers.host_name();
+
+        assert_eq!(
+            host_name(b"GET / HTTP/1.1\r\nHost: Example.COM:8080\r\n\r\n").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(
+            host_name(b"GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n").as_deref(),
+            Some("[::1]")
+        );
+        assert_eq!(
+            host_name(b"GET / HTTP/1.1\r\nHost: [::1]\r\n\r\n").as_deref(),
+            Some("[::1]")
+        );
+        assert_eq!(host_name(b"GET / HTTP/1.0\r\n\r\n"), None);
+    }
+
+    #[test]
+    pub fn connection_persistence_by_version() {
+        let wants_close = |head: &[u8]| Request::parse(head).unwrap().headers.wants_close();
+
+        assert!(!wants_close(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!wants_close(
+            b"GET / HTTP/1.1\r\nConnection: keep-alive\r\n\r\n"
+        ));
+        assert!(wants_close(
+            b"GET / HTTP/1.1\r\nConnection: Upgrade, Close\r\n\r\n"
+        ));
+        assert!(wants_close(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(!wants_close(
+            b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n"
+        ));
     }
 }
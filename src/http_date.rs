@@ -0,0 +1,199 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// A time broken down into its UTC calendar date and time of day, for the
+/// date formats of headers and logs. The fraction of a second is dropped.
+pub struct UtcTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hours: u64,
+    pub minutes: u64,
+    pub seconds: u64,
+
+    /// Days since 1970-01-01
+    days: i64,
+}
+
+impl From<SystemTime> for UtcTime {
+    fn from(time: SystemTime) -> Self {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let days = (seconds / 86_400) as i64;
+        let (year, month, day) = civil_from_days(days);
+        let seconds_of_day = seconds % 86_400;
+
+        Self {
+            year,
+            month,
+            day,
+            hours: seconds_of_day / 3600,
+            minutes: seconds_of_day / 60 % 60,
+            seconds: seconds_of_day % 60,
+            days,
+        }
+    }
+}
+
+impl UtcTime {
+    /// English abbreviation of the month, such as `Nov`
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[self.month as usize - 1]
+    }
+
+    /// English abbreviation of the day of the week, such as `Sun`
+    fn weekday_name(&self) -> &'static str {
+        // 1970-01-01 was a Thursday
+        WEEKDAYS[(self.days + 4).rem_euclid(7) as usize]
+    }
+}
+
+/// Formats `time` as an IMF-fixdate, the form of HTTP header dates such as
+/// `Last-Modified` (RFC 9110 section 5.6.7): `Sun, 06 Nov 1994 08:49:37 GMT`.
+/// The fraction of a second is dropped.
+pub fn format(time: SystemTime) -> String {
+    let utc = UtcTime::from(time);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        utc.weekday_name(),
+        utc.day,
+        utc.month_name(),
+        utc.year,
+        utc.hours,
+        utc.minutes,
+        utc.seconds,
+    )
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with millisecond precision
+pub fn rfc3339(time: SystemTime) -> String {
+    let utc = UtcTime::from(time);
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_millis();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{millis:03}Z",
+        utc.year, utc.month, utc.day, utc.hours, utc.minutes, utc.seconds,
+    )
+}
+
+/// Parses an IMF-fixdate, `None` for anything else including the obsolete
+/// RFC 850 and asctime forms
+pub fn parse(date: &str) -> Option<SystemTime> {
+    let [weekday, day, month, year, time, zone] = date
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .try_into()
+        .ok()?;
+    if !WEEKDAYS.contains(&weekday.strip_suffix(',')?) || zone != "GMT" {
+        return None;
+    }
+
+    let number = |digits: &str, len: usize| {
+        (digits.len() == len && digits.bytes().all(|byte| byte.is_ascii_digit()))
+            .then(|| digits.parse::<u64>().ok())
+            .flatten()
+    };
+    let day = number(day, 2)?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let year = number(year, 4)?;
+    let [hours, minutes, seconds] = time.split(':').collect::<Vec<_>>().try_into().ok()?;
+    let (hours, minutes, seconds) = (number(hours, 2)?, number(minutes, 2)?, number(seconds, 2)?);
+    if !(1..=31).contains(&day) || year < 1970 || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let days = days_from_civil(year as i64, month as u32, day as u32);
+    let seconds = days as u64 * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) date, following
+/// Howard Hinnant's `civil_from_days` algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since 1970-01-01, the inverse of
+/// [civil_from_days] following Howard Hinnant's `days_from_civil` algorithm
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn format_and_parse_round_trip() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+
+        let now = SystemTime::now();
+        let whole_seconds =
+            UNIX_EPOCH + Duration::from_secs(now.duration_since(UNIX_EPOCH).unwrap().as_secs());
+        assert_eq!(parse(&format(now)), Some(whole_seconds));
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            parse("Tue, 29 Feb 2028 23:59:59 GMT")
+                .map(format)
+                .as_deref(),
+            Some("Tue, 29 Feb 2028 23:59:59 GMT")
+        );
+    }
+
+    #[test]
+    pub fn dates_around_leap_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+    }
+
+    #[test]
+    pub fn malformed_dates_are_rejected() {
+        for date in [
+            "",
+            "yesterday",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+        ] {
+            assert_eq!(parse(date), None, "{date}");
+        }
+    }
+}
@@ -0,0 +1,27 @@
+pub mod access_log;
+pub mod compression;
+pub mod config;
+pub mod digest;
+pub mod etag;
+pub mod file_cache;
+pub mod filesystem;
+pub mod http;
+pub mod http_date;
+pub mod json;
+pub mod listing;
+pub mod logging;
+pub mod metrics;
+pub mod mime;
+pub mod pool;
+pub mod range;
+pub mod rate_limit;
+pub mod redirect;
+pub mod resolver;
+pub mod semaphore;
+pub mod server;
+pub mod sitemap;
+pub mod snapshot;
+pub mod template;
+pub mod transfer;
+pub mod version;
+pub mod wildcard;
@@ -0,0 +1,276 @@
+use serde::Deserialize;
+
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::etag;
+use crate::http::percent_encode;
+use crate::http_date::rfc3339;
+use crate::json::json_string;
+use crate::mime;
+
+/// Representation of a generated listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListingFormat {
+    /// A page linking to every entry, see [render]
+    #[default]
+    Html,
+
+    /// An array of entry objects for programmatic clients, see [render_json]
+    Json,
+}
+
+impl ListingFormat {
+    /// The format the client's `Accept` header prefers, `default` when it
+    /// has no preference. `None` when it accepts neither.
+    pub fn negotiate(accept: Option<&str>, default: ListingFormat) -> Option<ListingFormat> {
+        let Some(accept) = accept else {
+            return Some(default);
+        };
+        let json = mime::accept_quality(accept, "application/json");
+        let html = mime::accept_quality(accept, "text/html");
+
+        if json == 0.0 && html == 0.0 {
+            None
+        } else if json > html {
+            Some(ListingFormat::Json)
+        } else if html > json {
+            Some(ListingFormat::Html)
+        } else {
+            Some(default)
+        }
+    }
+}
+
+/// A visible entry of a listed directory
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Reads the entries of `directory`, sorted by name.
+/// Hidden entries (dotfiles) are left out.
+pub fn entries(directory: &Path) -> std::io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        // Follows symlinks so a link to a directory is listed as one
+        let metadata = fs::metadata(entry.path())?;
+        entries.push(Entry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        });
+    }
+
+    entries.sort();
+
+    Ok(entries)
+}
+
+/// Synthetic weak entity tag for a listing, derived from the entries' names,
+/// sizes and modification times. It changes whenever an entry is added,
+/// removed or modified, and differs between the two formats.
+pub fn etag(entries: &[Entry], format: ListingFormat) -> String {
+    let mut fingerprint = format!("{format:?}\n").into_bytes();
+
+    for entry in entries {
+        let modified = entry
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_nanos())
+            .unwrap_or_default();
+
+        fingerprint.extend_from_slice(
+            format!(
+                "{}/{}/{}/{modified}\n",
+                entry.name, entry.is_dir, entry.size
+            )
+            .as_bytes(),
+        );
+    }
+
+    etag::weak_from_content(&fingerprint)
+}
+
+/// Renders an HTML page linking to every one of the `entries`.
+///
+/// `request_path` is the path the client asked for and is used as the base of
+/// the links. Subdirectories are suffixed with a slash. At most `max_entries`
+/// entries are listed, followed by a notice telling how many more were left out.
+pub fn render(entries: &[Entry], request_path: &str, max_entries: usize) -> String {
+    let omitted = entries.len().saturating_sub(max_entries);
+
+    let base = format!("{}/", request_path.trim_end_matches('/'));
+    let title = format!("Index of {}", escape_html(&base));
+    // The request path arrives decoded
+    let link_base = base
+        .split('/')
+        .map(percent_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let mut page = format!(
+        "<html>\n<head>\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n<ul>\n"
+    );
+
+    for entry in entries.iter().take(max_entries) {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        page.push_str(&format!(
+            "<li><a href=\"{}{}{suffix}\">{}{suffix}</a></li>\n",
+            escape_html(&link_base),
+            percent_encode(&entry.name),
+            escape_html(&entry.name),
+        ));
+    }
+
+    page.push_str("</ul>\n");
+
+    if omitted > 0 {
+        page.push_str(&format!("<p>{omitted} more entries are not shown.</p>\n"));
+    }
+
+    page.push_str("</body>\n</html>\n");
+
+    page
+}
+
+/// Renders a JSON array of `{name, size, is_dir, modified}` objects for the
+/// first `max_entries` of the `entries`. `modified` is an RFC 3339 timestamp,
+/// `null` when the file system does not record it.
+pub fn render_json(entries: &[Entry], max_entries: usize) -> String {
+    let objects = entries
+        .iter()
+        .take(max_entries)
+        .map(|entry| {
+            let modified = entry.modified.map_or_else(
+                || "null".to_string(),
+                |modified| json_string(&rfc3339(modified)),
+            );
+            format!(
+                "{{\"name\":{},\"size\":{},\"is_dir\":{},\"modified\":{modified}}}",
+                json_string(&entry.name),
+                entry.size,
+                entry.is_dir,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("[{}]", objects.join(","))
+}
+
+/// Escapes the characters that have a special meaning in HTML text and attributes
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("turbine-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    pub fn render_lists_visible_entries() {
+        let root = temp_directory("listing");
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        fs::write(root.join("<b>.txt"), "b").unwrap();
+        fs::write(root.join(".secret"), "hidden").unwrap();
+
+        let page = render(&entries(&root).unwrap(), "/downloads", 10);
+
+        assert!(page.contains("<title>Index of /downloads/</title>"));
+        assert!(page.contains("<a href=\"/downloads/a.txt\">a.txt</a>"));
+        assert!(page.contains("<a href=\"/downloads/sub%20dir/\">sub dir/</a>"));
+        assert!(page.contains("<a href=\"/downloads/%3Cb%3E.txt\">&lt;b&gt;.txt</a>"));
+
+        let page = render(&entries(&root).unwrap(), "/my downloads", 10);
+        assert!(page.contains("<title>Index of /my downloads/</title>"));
+        assert!(page.contains("<a href=\"/my%20downloads/a.txt\">a.txt</a>"));
+        assert!(!page.contains(".secret"));
+        assert!(!page.contains("not shown"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn render_caps_entries() {
+        let root = temp_directory("listing-cap");
+        for i in 0..5 {
+            fs::write(root.join(format!("file{i}.txt")), "").unwrap();
+        }
+
+        let page = render(&entries(&root).unwrap(), "/", 3);
+
+        assert_eq!(page.matches("<li>").count(), 3);
+        assert!(page.contains("file0.txt") && page.contains("file2.txt"));
+        assert!(!page.contains("file3.txt"));
+        assert!(page.contains("<p>2 more entries are not shown.</p>"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn etag_changes_with_entries() {
+        let root = temp_directory("listing-etag");
+        fs::write(root.join("a.txt"), "a").unwrap();
+
+        let first = etag(&entries(&root).unwrap(), ListingFormat::Html);
+        assert!(first.starts_with("W/\""));
+        assert_eq!(first, etag(&entries(&root).unwrap(), ListingFormat::Html));
+        assert_ne!(first, etag(&entries(&root).unwrap(), ListingFormat::Json));
+
+        fs::write(root.join("b.txt"), "b").unwrap();
+        assert_ne!(first, etag(&entries(&root).unwrap(), ListingFormat::Html));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn listing_format_negotiation() {
+        let negotiate = |accept| ListingFormat::negotiate(accept, ListingFormat::Html);
+        assert_eq!(
+            negotiate(Some("application/json")),
+            Some(ListingFormat::Json)
+        );
+        assert_eq!(
+            negotiate(Some("application/json;q=0.9, */*;q=0.1")),
+            Some(ListingFormat::Json)
+        );
+        assert_eq!(
+            negotiate(Some("text/html,application/xhtml+xml")),
+            Some(ListingFormat::Html)
+        );
+        assert_eq!(negotiate(Some("*/*")), Some(ListingFormat::Html));
+        assert_eq!(negotiate(None), Some(ListingFormat::Html));
+        assert_eq!(negotiate(Some("application/xml")), None);
+        assert_eq!(
+            ListingFormat::negotiate(Some("*/*"), ListingFormat::Json),
+            Some(ListingFormat::Json)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::config::LogTarget;
+
+/// Syslog facility of the messages, `daemon`
+const SYSLOG_FACILITY: u8 = 3;
+/// Target of the access log records, written as they are so every line is
+/// one entry in the configured access log format
+const ACCESS_TARGET: &str = "access";
+
+/// Where log lines are written
+enum Sink {
+    Stdout,
+    Stderr,
+    File(File),
+    Syslog(UnixDatagram),
+}
+
+/// Which records are logged, read from a `RUST_LOG` style spec such as `warn`
+/// or `info,access=warn,turbine::server=debug`: a default level, then levels for
+/// the targets starting with a prefix. The longest matching prefix wins, a bare
+/// prefix enables every level for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    /// Directives with an unknown level are skipped
+    pub fn parse(spec: &str) -> Self {
+        let mut filter = Filter::default();
+
+        for directive in spec.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Ok(level) = level.trim().parse() {
+                        filter.targets.push((target.trim().to_string(), level));
+                    }
+                }
+                None if directive.is_empty() => {}
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter
+                        .targets
+                        .push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+
+        filter
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    /// The most verbose level any target is logged at
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+/// Logger writing the records let through by its [Filter], `Info` and above
+/// by default, to the configured [LogTarget]
+pub struct Logger {
+    sink: Mutex<Sink>,
+    filter: Filter,
+}
+
+impl Logger {
+    pub fn new(target: &LogTarget) -> std::io::Result<Self> {
+        let sink = match target {
+            LogTarget::Stdout => Sink::Stdout,
+            LogTarget::Stderr => Sink::Stderr,
+            LogTarget::File { path } => {
+                Sink::File(OpenOptions::new().create(true).append(true).open(path)?)
+            }
+            LogTarget::Syslog { socket } => {
+                let datagram = UnixDatagram::unbound()?;
+                datagram.connect(socket)?;
+                Sink::Syslog(datagram)
+            }
+        };
+
+        Ok(Self {
+            sink: Mutex::new(sink),
+            filter: Filter::default(),
+        })
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// Installs a [Logger] for `target` as the global logger, filtered as the
+/// `RUST_LOG` environment variable tells
+pub fn init(target: &LogTarget) -> anyhow::Result<()> {
+    let filter = std::env::var("RUST_LOG")
+        .map(|spec| Filter::parse(&spec))
+        .unwrap_or_default();
+    let max_level = filter.max_level();
+
+    let logger = Logger::new(target)?.with_filter(filter);
+    log::set_boxed_logger(Box::new(logger))?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+/// Syslog severity matching a log level (RFC 5424 section 6.2.1)
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = if record.target() == ACCESS_TARGET {
+            record.args().to_string()
+        } else {
+            format!("{} {}: {}", record.level(), record.target(), record.args())
+        };
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+
+        // A logger has nowhere to report its own failures
+        let _ = match &mut *sink {
+            Sink::Stdout => writeln!(std::io::stdout(), "{line}"),
+            Sink::Stderr => writeln!(std::io::stderr(), "{line}"),
+            Sink::File(file) => writeln!(file, "{line}"),
+            Sink::Syslog(datagram) => {
+                let priority = SYSLOG_FACILITY * 8 + syslog_severity(record.level());
+                let message = format!(
+                    "<{priority}>turbine[{}]: {}",
+                    std::process::id(),
+                    record.args()
+                );
+                datagram.send(message.as_bytes()).map(|_| ())
+            }
+        };
+    }
+
+    fn flush(&self) {
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = match &mut *sink {
+            Sink::Stdout => std::io::stdout().flush(),
+            Sink::Stderr => std::io::stderr().flush(),
+            Sink::File(file) => file.flush(),
+            Sink::Syslog(_) => Ok(()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("turbine-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn log(logger: &Logger, level: Level, message: &str) {
+        log_to(logger, level, "turbine", message);
+    }
+
+    fn log_to(logger: &Logger, level: Level, target: &str, message: &str) {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{message}"))
+                .build(),
+        );
+    }
+
+    #[test]
+    pub fn file_target() {
+        let path = temp_path("file-target.log");
+        let logger = Logger::new(&LogTarget::File { path: path.clone() }).unwrap();
+
+        log(&logger, Level::Warn, "disk is getting full");
+        log(&logger, Level::Debug, "not at the default level");
+        logger.flush();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "WARN turbine: disk is getting full\n"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    pub fn syslog_target() {
+        let socket = temp_path("syslog.sock");
+        let receiver = UnixDatagram::bind(&socket).unwrap();
+        let logger = Logger::new(&LogTarget::Syslog {
+            socket: socket.clone(),
+        })
+        .unwrap();
+
+        log(&logger, Level::Error, "document root is gone");
+
+        let mut buffer = [0; 256];
+        let length = receiver.recv(&mut buffer).unwrap();
+        let message = String::from_utf8_lossy(&buffer[..length]);
+        // daemon facility (3) * 8 + error severity (3)
+        assert_eq!(
+            message,
+            format!("<27>turbine[{}]: document root is gone", std::process::id())
+        );
+        fs::remove_file(socket).unwrap();
+    }
+
+    #[test]
+    pub fn filter_spec() {
+        let filter =
+            Filter::parse("warn, access=info,turbine::server=debug,turbine::pool, bogus=loud");
+        assert_eq!(filter.level_for("turbine::http"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("access"), LevelFilter::Info);
+        assert_eq!(filter.level_for("turbine::server"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("turbine::pool"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("bogus"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        assert_eq!(Filter::parse(""), Filter::default());
+        assert_eq!(Filter::parse("OFF").max_level(), LevelFilter::Off);
+    }
+
+    #[test]
+    pub fn filtered_file_target() {
+        let path = temp_path("filtered-target.log");
+        let logger = Logger::new(&LogTarget::File { path: path.clone() })
+            .unwrap()
+            .with_filter(Filter::parse("error,access=info"));
+
+        log(&logger, Level::Warn, "not at the configured level");
+        log_to(&logger, Level::Info, "access", "GET / 200 5 bytes");
+        logger.flush();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "GET / 200 5 bytes\n");
+        fs::remove_file(path).unwrap();
+    }
+}
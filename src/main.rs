@@ -1,18 +1,23 @@
-mod config;
-mod http;
-mod resolver;
-mod server;
-
-use anyhow::Result as AnyhowResult;
+use anyhow::{bail, Result as AnyhowResult};
 use clap::Parser;
 
-use crate::config::{Args, Config};
-use crate::server::Server;
+use turbine::config::{Args, Config};
+use turbine::logging;
+use turbine::server::Server;
 
 fn main() -> AnyhowResult<()> {
     let args = Args::parse();
-    println!("{:?}", args);
-    let config = Config::new(args.config_file)?;
+    let mut config = Config::new(args.config_file.clone())?;
+    if let Some(port) = args.port {
+        // Only `listen_addr` has a port to replace, the listeners each bind
+        // their own
+        if !config.listeners.is_empty() {
+            bail!("--port cannot be used with a config file that sets listeners");
+        }
+        config.listen_addr.set_port(port);
+    }
+    logging::init(&config.log_target)?;
+    log::debug!("{:?}", args);
 
     let server = Server::new(config)?;
 
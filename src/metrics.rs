@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Counters shared by every listener, served in the Prometheus text format by
+/// the metrics endpoint
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    connections: AtomicU64,
+    requests: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn connection_accepted(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn request_received(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        format!(
+            "# TYPE turbine_connections_total counter\n\
+             turbine_connections_total {}\n\
+             # TYPE turbine_requests_total counter\n\
+             turbine_requests_total {}\n\
+             # TYPE turbine_uptime_seconds gauge\n\
+             turbine_uptime_seconds {}\n",
+            self.connections.load(Ordering::Relaxed),
+            self.requests.load(Ordering::Relaxed),
+            self.started.elapsed().as_secs(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn counters_are_rendered() {
+        let metrics = Metrics::default();
+        metrics.connection_accepted();
+        metrics.request_received();
+        metrics.request_received();
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("\nturbine_connections_total 1\n"));
+        assert!(rendered.contains("\nturbine_requests_total 2\n"));
+        assert!(rendered.contains("\nturbine_uptime_seconds 0\n"));
+    }
+}
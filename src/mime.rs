@@ -0,0 +1,132 @@
+use std::path::Path;
+
+/// Content type sent for files whose extension is not recognized
+pub const DEFAULT_MIME: &str = "application/octet-stream";
+
+/// Maps a file to its MIME type based on its extension (case-insensitive),
+/// falling back to [DEFAULT_MIME]
+pub fn mime_for_path(path: &Path) -> &'static str {
+    let Some(extension) = path.extension().and_then(|extension| extension.to_str()) else {
+        return DEFAULT_MIME;
+    };
+
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=UTF-8",
+        "css" => "text/css; charset=UTF-8",
+        "js" | "mjs" => "application/javascript; charset=UTF-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=UTF-8",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "svg" => "image/svg+xml",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        _ => DEFAULT_MIME,
+    }
+}
+
+/// Whether content of `content_type` is text, as opposed to binary
+pub fn is_text(content_type: &str) -> bool {
+    let essence = essence(content_type);
+    essence.starts_with("text/")
+        || matches!(
+            essence,
+            "application/javascript" | "application/json" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// The media type of a `Content-Type` value, without parameters such as charset
+pub fn essence(content_type: &str) -> &str {
+    content_type.split(';').next().unwrap_or_default().trim()
+}
+
+/// Quality, from 0 to 1, the `Accept` header value `accept` gives to
+/// `content_type`. The most specific matching range decides: `text/html` over
+/// `text/*` over `*/*`. 0 when no range matches or the match has `q=0`.
+pub fn accept_quality(accept: &str, content_type: &str) -> f32 {
+    let essence = essence(content_type);
+    let main_type = essence.split('/').next().unwrap_or_default();
+    let mut best: Option<(u8, f32)> = None;
+
+    for range in accept.split(',') {
+        let mut parameters = range.split(';');
+        let media_range = parameters.next().unwrap_or_default().trim();
+        let specificity = if media_range.eq_ignore_ascii_case(essence) {
+            3
+        } else if media_range
+            .strip_suffix("/*")
+            .is_some_and(|range_type| range_type.eq_ignore_ascii_case(main_type))
+        {
+            2
+        } else if media_range == "*/*" {
+            1
+        } else {
+            continue;
+        };
+
+        let quality = parameters
+            .find_map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+                name.trim().eq_ignore_ascii_case("q").then_some(value)
+            })
+            .and_then(|quality| quality.trim().parse().ok())
+            .unwrap_or(1.0);
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, quality));
+        }
+    }
+
+    best.map_or(0.0, |(_, quality)| quality)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn common_extensions() {
+        let mime = |path| mime_for_path(Path::new(path));
+
+        assert_eq!(mime("index.html"), "text/html; charset=UTF-8");
+        assert_eq!(mime("css/site.css"), "text/css; charset=UTF-8");
+        assert_eq!(mime("app.js"), "application/javascript; charset=UTF-8");
+        assert_eq!(mime("data.json"), "application/json");
+        assert_eq!(mime("logo.PNG"), "image/png");
+        assert_eq!(mime("photo.jpg"), "image/jpeg");
+        assert_eq!(mime("icon.svg"), "image/svg+xml");
+        assert_eq!(mime("font.woff2"), "font/woff2");
+        assert_eq!(mime("notes.txt"), "text/plain; charset=UTF-8");
+    }
+
+    #[test]
+    pub fn unknown_extensions_fall_back() {
+        assert_eq!(mime_for_path(Path::new("archive.xyz")), DEFAULT_MIME);
+        assert_eq!(mime_for_path(Path::new("Makefile")), DEFAULT_MIME);
+        assert_eq!(DEFAULT_MIME, "application/octet-stream");
+    }
+
+    #[test]
+    pub fn essence_drops_parameters() {
+        assert_eq!(essence("text/html; charset=UTF-8"), "text/html");
+        assert_eq!(essence("image/png"), "image/png");
+    }
+
+    #[test]
+    pub fn accept_quality_prefers_specific_ranges() {
+        let html = "text/html; charset=UTF-8";
+        assert_eq!(accept_quality("text/html", html), 1.0);
+        assert_eq!(accept_quality("text/*;q=0.5, */*;q=0.1", html), 0.5);
+        assert_eq!(accept_quality("application/json, */*;q=0.2", html), 0.2);
+        assert_eq!(accept_quality("text/html;q=0, */*", html), 0.0);
+        assert_eq!(accept_quality("application/xml", html), 0.0);
+        assert_eq!(accept_quality("TEXT/HTML", html), 1.0);
+        assert_eq!(accept_quality("text/html;Q=0, */*", html), 0.0);
+        assert_eq!(accept_quality("text/html; q = 0.3", html), 0.3);
+    }
+}
@@ -0,0 +1,290 @@
+use threadpool::ThreadPool;
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::ConcurrencyModel;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Worker threads connections are handed to, as set by the [ConcurrencyModel]
+#[derive(Clone)]
+pub enum Pool {
+    Fixed(ThreadPool),
+    Elastic(ElasticPool),
+}
+
+impl Pool {
+    /// The pool of `model`, `None` when every connection gets its own thread
+    pub fn new(model: ConcurrencyModel) -> Option<Self> {
+        match model {
+            ConcurrencyModel::ThreadPool { size } => {
+                Some(Pool::Fixed(ThreadPool::new(size.max(1))))
+            }
+            ConcurrencyModel::ElasticPool {
+                min,
+                max,
+                idle_timeout_secs,
+            } => Some(Pool::Elastic(ElasticPool::new(
+                min,
+                max,
+                Duration::from_secs(idle_timeout_secs),
+            ))),
+            ConcurrencyModel::ThreadPerConnection => None,
+        }
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        match self {
+            Pool::Fixed(pool) => pool.execute(job),
+            Pool::Elastic(pool) => pool.execute(job),
+        }
+    }
+
+    /// Jobs waiting for a free worker
+    pub fn queued_count(&self) -> usize {
+        match self {
+            Pool::Fixed(pool) => pool.queued_count(),
+            Pool::Elastic(pool) => pool.queued_count(),
+        }
+    }
+
+    /// Waits until every queued and running job finished
+    pub fn join(&self) {
+        match self {
+            Pool::Fixed(pool) => pool.join(),
+            Pool::Elastic(pool) => pool.join(),
+        }
+    }
+}
+
+/// A pool starting with `min` threads that grows up to `max` while every
+/// thread is busy, and shrinks back as threads stay idle for `idle_timeout`
+#[derive(Clone)]
+pub struct ElasticPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    job_queued: Condvar,
+
+    /// Signalled whenever a thread goes idle or stops
+    worker_idle: Condvar,
+    min: usize,
+    max: usize,
+    idle_timeout: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+}
+
+impl ElasticPool {
+    pub fn new(min: usize, max: usize, idle_timeout: Duration) -> Self {
+        let max = max.max(1);
+        let pool = Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State::default()),
+                job_queued: Condvar::new(),
+                worker_idle: Condvar::new(),
+                min: min.min(max),
+                max,
+                idle_timeout,
+            }),
+        };
+
+        let mut state = pool.shared.lock();
+        for _ in 0..pool.shared.min {
+            pool.spawn_worker(&mut state);
+        }
+        drop(state);
+
+        pool
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        let mut state = self.shared.lock();
+        state.queue.push_back(Box::new(job));
+
+        // Idle threads take the queued jobs first, one each
+        if state.idle < state.queue.len() && state.threads < self.shared.max {
+            self.spawn_worker(&mut state);
+        }
+        drop(state);
+
+        self.shared.job_queued.notify_one();
+    }
+
+    /// Jobs waiting for a free worker
+    pub fn queued_count(&self) -> usize {
+        self.shared.lock().queue.len()
+    }
+
+    /// Waits until the queue is empty and every thread idle
+    pub fn join(&self) {
+        let mut state = self.shared.lock();
+        while !state.queue.is_empty() || state.idle < state.threads {
+            state = self
+                .shared
+                .worker_idle
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Threads currently running, busy or idle
+    #[cfg(test)]
+    pub fn thread_count(&self) -> usize {
+        self.shared.lock().threads
+    }
+
+    /// Waits until exactly `count` threads are running
+    #[cfg(test)]
+    fn wait_for_threads(&self, count: usize) {
+        let mut state = self.shared.lock();
+        while state.threads != count {
+            state = self
+                .shared
+                .worker_idle
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn spawn_worker(&self, state: &mut State) {
+        state.threads += 1;
+        let shared = Arc::clone(&self.shared);
+        thread::spawn(move || shared.work());
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Runs queued jobs until the thread was idle for `idle_timeout` while
+    /// more than `min` threads are running
+    fn work(&self) {
+        let mut state = self.lock();
+
+        loop {
+            let Some(job) = state.queue.pop_front() else {
+                state.idle += 1;
+                self.worker_idle.notify_all();
+                let (guard, wait) = self
+                    .job_queued
+                    .wait_timeout(state, self.idle_timeout)
+                    .unwrap_or_else(|e| e.into_inner());
+                state = guard;
+                state.idle -= 1;
+
+                if wait.timed_out() && state.queue.is_empty() && state.threads > self.min {
+                    state.threads -= 1;
+                    self.worker_idle.notify_all();
+                    return;
+                }
+                continue;
+            };
+            drop(state);
+
+            // A panicking job must not take the worker down with it
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+
+            state = self.lock();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Barrier, RwLock};
+
+    #[test]
+    pub fn elastic_pool_grows_and_shrinks() {
+        let pool = ElasticPool::new(1, 4, Duration::from_millis(10));
+        assert_eq!(pool.thread_count(), 1);
+
+        // Jobs hold their thread until the gate opens
+        let gate = Arc::new(RwLock::new(()));
+        let closed = gate.write().unwrap();
+
+        // Four jobs that all start before any finishes need four threads
+        let barrier = Arc::new(Barrier::new(5));
+        for _ in 0..4 {
+            let barrier = Arc::clone(&barrier);
+            let gate = Arc::clone(&gate);
+            pool.execute(move || {
+                barrier.wait();
+                drop(gate.read());
+            });
+        }
+        barrier.wait();
+        assert_eq!(pool.thread_count(), 4);
+
+        // Never beyond the maximum
+        for _ in 0..8 {
+            let gate = Arc::clone(&gate);
+            pool.execute(move || drop(gate.read()));
+        }
+        assert_eq!(pool.thread_count(), 4);
+        assert_eq!(pool.queued_count(), 8);
+
+        drop(closed);
+        pool.wait_for_threads(1);
+    }
+
+    #[test]
+    pub fn panicking_job_keeps_the_worker() {
+        let pool = ElasticPool::new(1, 1, Duration::from_secs(60));
+        pool.execute(|| panic!("job failed"));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        pool.execute(move || sender.send(()).unwrap());
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+        assert_eq!(pool.thread_count(), 1);
+    }
+
+    #[test]
+    pub fn thread_pool_has_the_configured_size() {
+        let Some(Pool::Fixed(pool)) = Pool::new(ConcurrencyModel::ThreadPool { size: 8 }) else {
+            panic!("thread_pool builds a fixed pool");
+        };
+        assert_eq!(pool.max_count(), 8);
+    }
+
+    #[test]
+    pub fn join_waits_for_running_jobs() {
+        for pool in [
+            Pool::new(ConcurrencyModel::ThreadPool { size: 2 }).unwrap(),
+            Pool::Elastic(ElasticPool::new(0, 2, Duration::from_secs(60))),
+        ] {
+            let finished = Arc::new(Mutex::new(0));
+            let (release, released) = std::sync::mpsc::channel::<()>();
+            let released = Arc::new(Mutex::new(released));
+            for _ in 0..4 {
+                let finished = Arc::clone(&finished);
+                let released = Arc::clone(&released);
+                pool.execute(move || {
+                    // Blocks until the sender is dropped
+                    let _ = released.lock().unwrap().recv();
+                    *finished.lock().unwrap() += 1;
+                });
+            }
+
+            // Released from elsewhere, likely while `join` already waits
+            thread::spawn(move || drop(release));
+            pool.join();
+            assert_eq!(*finished.lock().unwrap(), 4);
+        }
+    }
+}
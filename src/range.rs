@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+/// What a `Range` header asks of a representation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ByteRange {
+    /// The bytes at these offsets, to be answered with a 206
+    Satisfiable(Range<usize>),
+
+    /// A range starting past the end, to be answered with a 416
+    Unsatisfiable,
+
+    /// A header that is malformed, in another unit or asks for several
+    /// ranges. The whole representation is sent with a 200.
+    Ignored,
+}
+
+/// Parses a single `bytes` range (RFC 9110 section 14.1.2) against a
+/// representation of `len` bytes: `bytes=0-1023`, `bytes=1024-` or the
+/// suffix `bytes=-500` for the last 500 bytes
+pub fn parse(header: &str, len: usize) -> ByteRange {
+    let Some((unit, spec)) = header.split_once('=') else {
+        return ByteRange::Ignored;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+        return ByteRange::Ignored;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return ByteRange::Ignored;
+    };
+    let offset = |text: &str| {
+        (!text.is_empty() && text.bytes().all(|byte| byte.is_ascii_digit()))
+            .then(|| text.parse::<usize>().ok())
+            .flatten()
+    };
+
+    let range = match (first, last) {
+        ("", suffix) => match offset(suffix) {
+            Some(0) => return ByteRange::Unsatisfiable,
+            Some(suffix) => len.saturating_sub(suffix)..len,
+            None => return ByteRange::Ignored,
+        },
+        (first, "") => match offset(first) {
+            Some(first) => first..len,
+            None => return ByteRange::Ignored,
+        },
+        (first, last) => match (offset(first), offset(last)) {
+            (Some(first), Some(last)) if first <= last => first..last.saturating_add(1).min(len),
+            _ => return ByteRange::Ignored,
+        },
+    };
+
+    if range.start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Satisfiable(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn single_ranges() {
+        assert_eq!(parse("bytes=0-9", 100), ByteRange::Satisfiable(0..10));
+        assert_eq!(parse("bytes=90-", 100), ByteRange::Satisfiable(90..100));
+        assert_eq!(parse("bytes=-10", 100), ByteRange::Satisfiable(90..100));
+        assert_eq!(parse("Bytes = 5-5", 100), ByteRange::Satisfiable(5..6));
+        // Clamped to the end
+        assert_eq!(parse("bytes=50-1000", 100), ByteRange::Satisfiable(50..100));
+        assert_eq!(parse("bytes=-1000", 100), ByteRange::Satisfiable(0..100));
+    }
+
+    #[test]
+    pub fn unsatisfiable_ranges() {
+        assert_eq!(parse("bytes=100-", 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=200-300", 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=-0", 100), ByteRange::Unsatisfiable);
+        assert_eq!(parse("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    pub fn other_headers_are_ignored() {
+        for header in [
+            "bytes=0-9,20-29",
+            "items=0-9",
+            "bytes=9-0",
+            "bytes=a-b",
+            "bytes=-",
+            "bytes=+1-2",
+            "bytes",
+        ] {
+            assert_eq!(parse(header, 100), ByteRange::Ignored, "{header}");
+        }
+    }
+}
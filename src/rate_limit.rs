@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimit;
+
+/// How often buckets that refilled completely are dropped. A full bucket is no
+/// different from a client never seen, so dropping it changes nothing but the
+/// memory held.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Most clients tracked at once, so a flood from many addresses cannot grow
+/// the buckets without bound in between evictions
+const MAX_CLIENTS: usize = 100_000;
+
+/// Token buckets keyed by client IP, each holding up to `burst` tokens and
+/// refilled at `requests_per_sec`. A request takes one token.
+pub struct RateLimiter {
+    limit: RateLimit,
+    max_clients: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_eviction: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            max_clients: MAX_CLIENTS,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                last_eviction: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a token for a request from `ip` at `now`. When the bucket is
+    /// empty, returns how long until the next token is available instead.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let burst = f64::from(self.limit.burst);
+        let mut state = self.lock();
+
+        if now.saturating_duration_since(state.last_eviction) >= EVICTION_INTERVAL {
+            state.last_eviction = now;
+            let refill = |bucket: &Bucket| self.refill(bucket, now);
+            state.buckets.retain(|_, bucket| refill(bucket) < burst);
+        }
+
+        if state.buckets.len() >= self.max_clients && !state.buckets.contains_key(&ip) {
+            // The client closest to a full bucket loses the least by being
+            // forgotten, the ones being limited are kept
+            let fullest = state
+                .buckets
+                .iter()
+                .max_by(|(_, a), (_, b)| self.refill(a, now).total_cmp(&self.refill(b, now)))
+                .map(|(ip, _)| *ip);
+            if let Some(fullest) = fullest {
+                state.buckets.remove(&fullest);
+            }
+        }
+
+        let bucket = state.buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            refilled: now,
+        });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        // A tiny rate can put the wait beyond what a Duration holds
+        let wait = (1.0 - bucket.tokens) / self.limit.requests_per_sec;
+        Err(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+    }
+
+    /// Tokens `bucket` holds at `now`
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        (bucket.tokens + elapsed * self.limit.requests_per_sec).min(f64::from(self.limit.burst))
+    }
+
+    #[cfg(test)]
+    fn tracked(&self) -> usize {
+        self.lock().buckets.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests_per_sec: f64, burst: u32) -> RateLimiter {
+        RateLimiter::new(RateLimit {
+            requests_per_sec,
+            burst,
+        })
+    }
+
+    #[test]
+    pub fn burst_then_refill() {
+        let limiter = limiter(2.0, 3);
+        let client = IpAddr::from([203, 0, 113, 7]);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, now), Ok(()));
+        }
+        assert_eq!(limiter.check(client, now), Err(Duration::from_millis(500)));
+
+        // Other clients have buckets of their own
+        assert_eq!(limiter.check(IpAddr::from([203, 0, 113, 8]), now), Ok(()));
+
+        // Half a second buys one token back, never more than the burst
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check(client, later), Ok(()));
+        assert!(limiter.check(client, later).is_err());
+
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client, much_later), Ok(()));
+        }
+        assert!(limiter.check(client, much_later).is_err());
+    }
+
+    #[test]
+    pub fn refilled_buckets_are_evicted() {
+        let limiter = limiter(1.0, 2);
+        let now = Instant::now();
+        for last in 0..10 {
+            limiter
+                .check(IpAddr::from([198, 51, 100, last]), now)
+                .unwrap();
+        }
+        assert_eq!(limiter.tracked(), 10);
+
+        let later = now + EVICTION_INTERVAL;
+        limiter
+            .check(IpAddr::from([198, 51, 100, 200]), later)
+            .unwrap();
+        assert_eq!(limiter.tracked(), 1);
+    }
+
+    #[test]
+    pub fn tracked_clients_are_capped() {
+        let mut limiter = limiter(1.0, 2);
+        limiter.max_clients = 3;
+        let now = Instant::now();
+        let limited = IpAddr::from([198, 51, 100, 1]);
+        for _ in 0..2 {
+            limiter.check(limited, now).unwrap();
+        }
+
+        for last in 2..10 {
+            limiter
+                .check(IpAddr::from([198, 51, 100, last]), now)
+                .unwrap();
+            assert!(limiter.tracked() <= 3);
+        }
+        // Still out of tokens, others were forgotten first
+        assert!(limiter.check(limited, now).is_err());
+    }
+
+    #[test]
+    pub fn tiny_rates_wait_as_long_as_possible() {
+        let limiter = limiter(1e-20, 1);
+        let client = IpAddr::from([203, 0, 113, 9]);
+        let now = Instant::now();
+
+        assert_eq!(limiter.check(client, now), Ok(()));
+        assert_eq!(limiter.check(client, now), Err(Duration::MAX));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::config::Redirect;
+use crate::http::percent_encode_path;
+use crate::wildcard;
+
+/// Finds the first of the `rules` applying to `path` on `host`, the request's
+/// host name as told by [Headers::host_name](crate::http::Headers::host_name),
+/// and returns it together with the location to redirect to
+pub fn find<'a>(
+    rules: &'a [Redirect],
+    host: Option<&str>,
+    path: &str,
+    query: Option<&str>,
+) -> Option<(&'a Redirect, String)> {
+    rules
+        .iter()
+        .filter(|rule| match &rule.host_pattern {
+            Some(pattern) => host.is_some_and(|host| host_matches(pattern, host)),
+            None => true,
+        })
+        .find_map(|rule| location(rule, path, query).map(|location| (rule, location)))
+}
+
+/// Location of the directory at the decoded `path`, with the trailing slash
+/// it was requested without, encoded again and keeping the query string
+pub fn directory_location(path: &str, query: Option<&str>) -> String {
+    let mut location = percent_encode_path(path);
+    location.push('/');
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(query);
+    }
+    location
+}
+
+/// Whether the lowercased host name `host` matches `pattern`, ignoring its
+/// case, in which `*` stands for any run of characters, e.g. `*.example.org`
+fn host_matches(pattern: &str, host: &str) -> bool {
+    wildcard::matches(&pattern.to_ascii_lowercase(), host)
+}
+
+/// The location `rule` sends a request for `path` to, if it applies
+fn location(rule: &Redirect, path: &str, query: Option<&str>) -> Option<String> {
+    if !rule.prefix_rewrite {
+        return (path == rule.from).then(|| rule.to.clone());
+    }
+
+    // Compared without the trailing slash so `/old/` also covers `/old`, but
+    // only on segment boundaries, `/older` is not under `/old/`
+    let tail = path.strip_prefix(rule.from.trim_end_matches('/'))?;
+    if !(tail.is_empty() || tail.starts_with('/')) {
+        return None;
+    }
+
+    // The tail is decoded, encoded again so nothing in it can end the
+    // `Location` header
+    let mut location = format!(
+        "{}{}",
+        rule.to.trim_end_matches('/'),
+        percent_encode_path(tail)
+    );
+    if location.is_empty() {
+        location.push('/');
+    }
+    if let Some(query) = query {
+        location.push('?');
+        location.push_str(query);
+    }
+
+    Some(location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: &str, prefix_rewrite: bool) -> Redirect {
+        Redirect {
+            from: from.to_string(),
+            to: to.to_string(),
+            permanent: true,
+            prefix_rewrite,
+            host_pattern: None,
+        }
+    }
+
+    #[test]
+    pub fn exact_rules_match_the_whole_path() {
+        let rules = [rule("/old.html", "/new.html", false)];
+
+        assert_eq!(
+            find(&rules, None, "/old.html", Some("a=1")).map(|(_, location)| location),
+            Some("/new.html".to_string())
+        );
+        assert!(find(&rules, None, "/old.html/more", None).is_none());
+    }
+
+    #[test]
+    pub fn prefix_strip_keeps_the_tail() {
+        let rules = [rule("/old/", "/", true)];
+        let location = |path, query| find(&rules, None, path, query).map(|(_, location)| location);
+
+        assert_eq!(location("/old/foo", None), Some("/foo".to_string()));
+        assert_eq!(
+            location("/old/a/b.html", Some("x=1")),
+            Some("/a/b.html?x=1".to_string())
+        );
+        assert_eq!(location("/old", None), Some("/".to_string()));
+        assert_eq!(location("/older/foo", None), None);
+
+        // The decoded tail is encoded again, it cannot split the header
+        assert_eq!(
+            location("/old/my file\r\nSet-Cookie: x=1", None),
+            Some("/my%20file%0D%0ASet-Cookie%3A%20x%3D1".to_string())
+        );
+    }
+
+    #[test]
+    pub fn prefix_to_prefix_rewrite() {
+        let rules = [rule("/blog", "/articles/", true)];
+
+        assert_eq!(
+            find(&rules, None, "/blog/2023/post.html", Some("ref=feed"))
+                .map(|(_, location)| location),
+            Some("/articles/2023/post.html?ref=feed".to_string())
+        );
+    }
+
+    #[test]
+    pub fn wildcard_host_alias() {
+        let rules = [Redirect {
+            host_pattern: Some("*.example.org".to_string()),
+            ..rule("/", "https://www.example.com/", true)
+        }];
+        let location = |host, path| find(&rules, host, path, None).map(|(_, location)| location);
+
+        assert_eq!(
+            location(Some("shop.example.org"), "/cart/items"),
+            Some("https://www.example.com/cart/items".to_string())
+        );
+        assert_eq!(
+            location(Some("a.b.example.org"), "/"),
+            Some("https://www.example.com/".to_string())
+        );
+        assert_eq!(location(Some("www.example.com"), "/cart/items"), None);
+        assert_eq!(location(Some("example.org"), "/"), None);
+        assert_eq!(location(None, "/"), None);
+    }
+
+    #[test]
+    pub fn directory_locations() {
+        assert_eq!(directory_location("/blog", None), "/blog/");
+        assert_eq!(
+            directory_location("/my docs/2024", Some("page=2")),
+            "/my%20docs/2024/?page=2"
+        );
+    }
+
+    #[test]
+    pub fn host_patterns() {
+        assert!(host_matches("EXAMPLE.org", "example.org"));
+        assert!(!host_matches("example.org", "www.example.org"));
+        assert!(host_matches("www.*.org", "www.example.org"));
+        assert!(host_matches("*", "anything"));
+        assert!(!host_matches("*.example.org", "example.org.evil.com"));
+        assert!(host_matches("[::1]", "[::1]"));
+    }
+}
@@ -1,8 +1,12 @@
 use thiserror::Error;
 
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
 
-use crate::http::HttpPath;
+use crate::http::{Headers, HttpPath, ParseError, DEFAULT_INDEX_FILE};
+use crate::wildcard;
 
 /// Errors that can occur when parsing a http request
 #[derive(Error, Debug)]
@@ -13,18 +17,236 @@ pub enum ResolveError {
     #[error("Path {0} should start with a slash")]
     PathShouldStartWithSlash(String),
 
+    #[error("Resource {0} could not be found")]
+    NotFound(PathBuf),
+
+    #[error("Permission denied while accessing {0}")]
+    PermissionDenied(PathBuf),
+
+    #[error("Resolving {0} follows more than {1} symlinks")]
+    TooManySymlinks(PathBuf, usize),
+
+    #[error("IO error while accessing {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+
     #[error("HttpError: {0}")]
-    HttpPathError(#[from] crate::http::ParseError),
+    HttpPathError(ParseError),
 }
 
+impl ResolveError {
+    /// Converts an error from building an [HttpPath] for `path`, keeping the
+    /// [ErrorKind] of IO failures so callers can tell a missing file from a
+    /// forbidden one
+    pub fn from_http_path_error(error: ParseError, path: PathBuf) -> Self {
+        match error {
+            ParseError::IO(e) => match e.kind() {
+                ErrorKind::NotFound => ResolveError::NotFound(path),
+                ErrorKind::PermissionDenied => ResolveError::PermissionDenied(path),
+                _ => ResolveError::Io(path, e),
+            },
+            other => ResolveError::HttpPathError(other),
+        }
+    }
+}
+
+/// What a request path maps to
+#[derive(Debug)]
+pub enum ResolvedResource {
+    /// A file, read through the server's [crate::filesystem::FileSystem]
+    File(HttpPath),
+
+    /// Content produced by the backend itself. Only custom backends produce it.
+    Content {
+        content_type: String,
+        content: Vec<u8>,
+    },
+}
+
+/// Maps request paths to the content served for them, e.g. from a database or
+/// a content-addressed store. [Resolver] maps them to files of the document root.
+pub trait ResolverBackend: Send + Sync {
+    fn resolve(&self, path: &str, headers: &Headers) -> Result<ResolvedResource, ResolveError>;
+}
+
+/// Which hidden files and directories, those whose name starts with a dot,
+/// may be served. None by default, they tend to hold secrets like `.env` or
+/// `.git/config`.
+#[derive(Debug, Clone, Default)]
+pub struct HiddenFiles {
+    /// Serve every hidden file
+    serve: bool,
+
+    /// Document root relative prefixes served nonetheless, e.g. `.well-known`
+    allowed: Vec<PathBuf>,
+}
+
+impl HiddenFiles {
+    /// `allowed` are request path prefixes such as `/.well-known/`
+    pub fn new(serve: bool, allowed: &[String]) -> Self {
+        let allowed = allowed
+            .iter()
+            .map(|prefix| PathBuf::from(prefix.trim_matches('/')))
+            .collect();
+
+        Self { serve, allowed }
+    }
+
+    /// Whether the file or directory at `relative`, a path relative to the
+    /// document root, may be served
+    pub fn permits(&self, relative: &Path) -> bool {
+        let hidden = relative
+            .components()
+            .any(|component| component.as_os_str().to_string_lossy().starts_with('.'));
+
+        !hidden
+            || self.serve
+            || self
+                .allowed
+                .iter()
+                .any(|prefix| relative.starts_with(prefix))
+    }
+}
+
+/// Paths never served, whatever the [HiddenFiles] policy, configured as
+/// request paths. A pattern without a `*` denies that path and everything
+/// under it, e.g. `/private/`. One with a `*` must match the whole path, the
+/// `*` standing for any run of characters, e.g. `*.bak` or `/backups/*.sql`.
+#[derive(Debug, Clone, Default)]
+pub struct DeniedPaths {
+    prefixes: Vec<PathBuf>,
+    patterns: Vec<String>,
+}
+
+impl DeniedPaths {
+    pub fn new(deny: &[String]) -> Self {
+        let (patterns, prefixes): (Vec<_>, Vec<_>) =
+            deny.iter().partition(|pattern| pattern.contains('*'));
+
+        Self {
+            prefixes: prefixes
+                .into_iter()
+                .map(|prefix| PathBuf::from(prefix.trim_matches('/')))
+                .collect(),
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| format!("/{}", pattern.trim_start_matches('/')))
+                .collect(),
+        }
+    }
+
+    /// Whether the file or directory at `relative`, a path relative to the
+    /// document root, is denied
+    pub fn denies(&self, relative: &Path) -> bool {
+        if self
+            .prefixes
+            .iter()
+            .any(|prefix| relative.starts_with(prefix))
+        {
+            return true;
+        }
+
+        let path = format!("/{}", relative.to_string_lossy());
+        self.patterns
+            .iter()
+            .any(|pattern| wildcard::matches(pattern, &path))
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Resolver {
     /// The canonicalized document root
     document_root: PathBuf,
+
+    hidden_files: HiddenFiles,
+
+    denied_paths: DeniedPaths,
+
+    /// Files served for a directory, the first one present wins
+    index_files: Vec<String>,
+
+    /// Most symlinks followed resolving a single path
+    max_symlink_depth: usize,
 }
 
+/// Same as the limit of Linux
+pub const DEFAULT_MAX_SYMLINK_DEPTH: usize = 40;
+
 impl Resolver {
     pub fn new(document_root: PathBuf) -> Self {
-        Self { document_root }
+        Self {
+            document_root,
+            hidden_files: HiddenFiles::default(),
+            denied_paths: DeniedPaths::default(),
+            index_files: vec![DEFAULT_INDEX_FILE.to_string()],
+            max_symlink_depth: DEFAULT_MAX_SYMLINK_DEPTH,
+        }
+    }
+
+    /// Replaces the default of following up to 40 symlinks per path, 0 serves
+    /// no file behind a symlink
+    pub fn with_max_symlink_depth(mut self, max_symlink_depth: usize) -> Self {
+        self.max_symlink_depth = max_symlink_depth;
+        self
+    }
+
+    /// Replaces the default of serving `index.html` for directories
+    pub fn with_index_files(mut self, index_files: Vec<String>) -> Self {
+        self.index_files = index_files;
+        self
+    }
+
+    /// Replaces the default of serving no hidden files
+    pub fn with_hidden_files(mut self, hidden_files: HiddenFiles) -> Self {
+        self.hidden_files = hidden_files;
+        self
+    }
+
+    /// Replaces the default of denying no paths beyond the hidden ones
+    pub fn with_denied_paths(mut self, denied_paths: DeniedPaths) -> Self {
+        self.denied_paths = denied_paths;
+        self
+    }
+
+    /// Whether the file or directory at `relative`, a path relative to the
+    /// document root, may be served according to the [HiddenFiles] policy and
+    /// the [DeniedPaths]
+    pub fn permits(&self, relative: &Path) -> bool {
+        self.hidden_files.permits(relative) && !self.denied_paths.denies(relative)
+    }
+
+    /// Whether `path`, an entry of a directory inside the document root, may
+    /// be served, going by both its name and the file it resolves to
+    pub fn permits_entry(&self, path: &Path) -> bool {
+        let Ok(canonical) = fs::canonicalize(path) else {
+            return false;
+        };
+
+        [path, canonical.as_path()].iter().all(|path| {
+            path.strip_prefix(&self.document_root)
+                .is_ok_and(|relative| self.permits(relative))
+        })
+    }
+
+    /// The canonicalized document root
+    pub fn document_root(&self) -> &Path {
+        &self.document_root
+    }
+
+    /// Whether the document root holds no files, at most empty directories.
+    /// Symlinks count as files and are not followed, so a loop is not walked.
+    pub fn is_empty(&self) -> bool {
+        fn holds_files(directory: &Path) -> bool {
+            let Ok(entries) = fs::read_dir(directory) else {
+                return false;
+            };
+
+            entries.flatten().any(|entry| match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => holds_files(&entry.path()),
+                _ => true,
+            })
+        }
+
+        !holds_files(&self.document_root)
     }
 
     /// Parses the request and returns the resource path as an absolute path
@@ -37,6 +259,10 @@ impl Resolver {
     /// Returns an error if the path
     /// - cannot be converted to an `HttpPath`
     /// - is outside the document root
+    /// - follows more than `max_symlink_depth` symlinks
+    /// - is hidden and not permitted by the [HiddenFiles] policy, or is one of
+    ///   the [DeniedPaths], reported as [ResolveError::NotFound] so its
+    ///   existence is not revealed
     pub fn resolve(&self, resource: String) -> Result<HttpPath, ResolveError> {
         if !resource.starts_with('/') {
             return Err(ResolveError::PathShouldStartWithSlash(resource));
@@ -45,16 +271,375 @@ impl Resolver {
         // Absolute paths replace the document root
         // Therefore we need to remove the leading slash
         let trimmed = resource.trim_start_matches('/');
-        let resource = self.document_root.join(trimmed);
 
-        // this is an absolute path
-        let http_path = HttpPath::try_from(resource)?;
+        let http_path = if trimmed.is_empty() {
+            // The document root is canonicalized already, only its index file
+            // may be a symlink and is checked like any other path
+            let index = HttpPath::index_of(&self.document_root, &self.index_files);
+            if let Ok(relative) = index.strip_prefix(&self.document_root) {
+                self.check_symlink_depth(relative)?;
+            }
+            index
+        } else {
+            self.check_symlink_depth(Path::new(trimmed))?;
+            let resource = self.document_root.join(trimmed);
 
-        // check if the absolute path file is inside the document root
-        if !http_path.starts_with(&self.document_root) {
+            // this is an absolute path
+            HttpPath::resolve(resource.clone(), &self.index_files)
+                .map_err(|e| ResolveError::from_http_path_error(e, resource))?
+        };
+
+        // The single containment check, on the path with every `..` and
+        // symlink resolved. That includes an index file that is a symlink itself,
+        // which canonicalizing the directory leaves alone.
+        let canonical = fs::canonicalize(&http_path).unwrap_or_else(|_| http_path.to_path_buf());
+        let Ok(relative) = canonical.strip_prefix(&self.document_root) else {
             return Err(ResolveError::PathOutsideDocumentRoot(http_path));
+        };
+
+        // Checked on the canonicalized path, so `/./.env` or a link to a hidden
+        // file is caught as well
+        if !self.permits(relative) {
+            return Err(ResolveError::NotFound(http_path.to_path_buf()));
         }
 
         Ok(http_path)
     }
+
+    /// Returns the canonicalized directory a resource points to, if it is a
+    /// directory inside the document root
+    pub fn resolve_directory(&self, resource: &str) -> Option<PathBuf> {
+        let trimmed = resource.strip_prefix('/')?.trim_start_matches('/');
+        self.check_symlink_depth(Path::new(trimmed)).ok()?;
+        let directory = fs::canonicalize(self.document_root.join(trimmed)).ok()?;
+
+        let relative = directory.strip_prefix(&self.document_root).ok()?;
+        if directory.is_dir() && self.permits(relative) {
+            return Some(directory);
+        }
+
+        None
+    }
+
+    /// Whether `resource` names a directory without the trailing slash that
+    /// relative links in its index or listing are resolved against
+    pub fn lacks_trailing_slash(&self, resource: &str) -> bool {
+        !resource.ends_with('/') && self.resolve_directory(resource).is_some()
+    }
+
+    /// Walks `relative` from the document root the way the OS resolves it,
+    /// counting the symlinks followed so a chain longer than
+    /// `max_symlink_depth` is refused before canonicalizing. The walk stops
+    /// quietly at a missing component, canonicalizing reports it.
+    fn check_symlink_depth(&self, relative: &Path) -> Result<(), ResolveError> {
+        let owned = |component: Component| PathBuf::from(component.as_os_str());
+        let mut pending = relative.components().map(owned).collect::<VecDeque<_>>();
+        let mut resolved = self.document_root.clone();
+        let mut followed = 0;
+
+        while let Some(component) = pending.pop_front() {
+            let Some(component) = component.components().next() else {
+                continue;
+            };
+            match component {
+                Component::Prefix(_) | Component::RootDir => resolved = PathBuf::from(&component),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => {
+                    let candidate = resolved.join(name);
+                    match fs::symlink_metadata(&candidate) {
+                        Ok(metadata) if metadata.is_symlink() => {
+                            followed += 1;
+                            if followed > self.max_symlink_depth {
+                                return Err(ResolveError::TooManySymlinks(
+                                    self.document_root.join(relative),
+                                    self.max_symlink_depth,
+                                ));
+                            }
+
+                            let Ok(target) = fs::read_link(&candidate) else {
+                                return Ok(());
+                            };
+                            // The target is resolved from the link's directory,
+                            // or from the root when absolute
+                            for component in target.components().rev() {
+                                pending.push_front(owned(component));
+                            }
+                        }
+                        Ok(_) => resolved = candidate,
+                        Err(_) => return Ok(()),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ResolverBackend for Resolver {
+    fn resolve(&self, path: &str, _headers: &Headers) -> Result<ResolvedResource, ResolveError> {
+        Resolver::resolve(self, path.to_string()).map(ResolvedResource::File)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io;
+
+    fn resolver() -> Resolver {
+        Resolver::new(fs::canonicalize("web_resources").unwrap())
+    }
+
+    #[test]
+    pub fn root_resolves_to_index() {
+        let resolver = resolver();
+        let index = resolver.document_root().join("index.html");
+
+        assert_eq!(*resolver.resolve("/".to_string()).unwrap(), index);
+        assert_eq!(*resolver.resolve("//".to_string()).unwrap(), index);
+
+        // A denied or hidden root index is not served either
+        let denied = resolver
+            .clone()
+            .with_denied_paths(DeniedPaths::new(&["/index.html".to_string()]));
+        assert!(matches!(
+            denied.resolve("/".to_string()),
+            Err(ResolveError::NotFound(_))
+        ));
+
+        let hidden = Resolver::new(resolver.document_root().to_path_buf())
+            .with_index_files(vec![".index.html".to_string()]);
+        assert!(matches!(
+            hidden.resolve("/".to_string()),
+            Err(ResolveError::NotFound(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn root_index_behind_symlinked_document_root() {
+        let base =
+            std::env::temp_dir().join(format!("turbine-{}-symlink-root", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("site")).unwrap();
+        fs::write(base.join("site/index.html"), "home").unwrap();
+        std::os::unix::fs::symlink(base.join("site"), base.join("current")).unwrap();
+
+        let resolver = Resolver::new(fs::canonicalize(base.join("current")).unwrap());
+        let index = resolver.resolve("/".to_string()).unwrap();
+        assert_eq!(fs::read_to_string(&index).unwrap(), "home");
+        assert!(index.starts_with(resolver.document_root()));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    pub fn hidden_files_policy() {
+        let relative = |path| Path::new(path);
+
+        let default = HiddenFiles::default();
+        assert!(default.permits(relative("foo/index.html")));
+        assert!(!default.permits(relative(".env")));
+        assert!(!default.permits(relative(".git/config")));
+        assert!(!default.permits(relative("foo/.secret/page.html")));
+
+        let well_known = HiddenFiles::new(false, &["/.well-known/".to_string()]);
+        assert!(well_known.permits(relative(".well-known/acme-challenge/token")));
+        assert!(!well_known.permits(relative(".well-known-not/token")));
+        assert!(!well_known.permits(relative(".env")));
+
+        assert!(HiddenFiles::new(true, &[]).permits(relative(".env")));
+    }
+
+    #[test]
+    pub fn denied_paths_policy() {
+        let relative = |path| Path::new(path);
+        let denied = DeniedPaths::new(&[
+            "/private/".to_string(),
+            "*.bak".to_string(),
+            "/backups/*.sql".to_string(),
+        ]);
+
+        assert!(denied.denies(relative("private")));
+        assert!(denied.denies(relative("private/report.pdf")));
+        assert!(denied.denies(relative("notes.txt.bak")));
+        assert!(denied.denies(relative("docs/old/page.html.bak")));
+        assert!(denied.denies(relative("backups/2024/db.sql")));
+
+        assert!(!denied.denies(relative("private-not/report.pdf")));
+        assert!(!denied.denies(relative("docs/db.sql")));
+        assert!(!denied.denies(relative("index.html")));
+        assert!(!DeniedPaths::default().denies(relative("private/report.pdf")));
+    }
+
+    #[test]
+    pub fn missing_file_is_not_found() {
+        let result = resolver().resolve("/does-not-exist.html".to_string());
+        assert!(matches!(result, Err(ResolveError::NotFound(_))));
+    }
+
+    #[test]
+    pub fn io_error_kinds_surface_distinctly() {
+        let path = PathBuf::from("/some/file");
+        let error = |kind| ParseError::IO(io::Error::from(kind));
+
+        assert!(matches!(
+            ResolveError::from_http_path_error(error(ErrorKind::NotFound), path.clone()),
+            ResolveError::NotFound(p) if p == path
+        ));
+        assert!(matches!(
+            ResolveError::from_http_path_error(error(ErrorKind::PermissionDenied), path.clone()),
+            ResolveError::PermissionDenied(p) if p == path
+        ));
+        assert!(matches!(
+            ResolveError::from_http_path_error(error(ErrorKind::InvalidData), path.clone()),
+            ResolveError::Io(p, e) if p == path && e.kind() == ErrorKind::InvalidData
+        ));
+        assert!(matches!(
+            ResolveError::from_http_path_error(ParseError::InvalidPath(path.clone()), path),
+            ResolveError::HttpPathError(ParseError::InvalidPath(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn symlink_chains_are_bounded() {
+        let base =
+            std::env::temp_dir().join(format!("turbine-{}-symlink-chain", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("links")).unwrap();
+        fs::write(base.join("page.html"), "page").unwrap();
+
+        // link3 -> link2 -> link1 -> ../page.html
+        std::os::unix::fs::symlink("../page.html", base.join("links/link1")).unwrap();
+        std::os::unix::fs::symlink("link1", base.join("links/link2")).unwrap();
+        std::os::unix::fs::symlink(base.join("links/link2"), base.join("links/link3")).unwrap();
+        // A loop never resolves
+        std::os::unix::fs::symlink("loop", base.join("loop")).unwrap();
+
+        let root = fs::canonicalize(&base).unwrap();
+        let resolver = Resolver::new(root.clone()).with_max_symlink_depth(3);
+        let page = resolver.resolve("/links/link3".to_string()).unwrap();
+        assert_eq!(fs::read_to_string(page).unwrap(), "page");
+        assert!(matches!(
+            resolver.resolve("/loop".to_string()),
+            Err(ResolveError::TooManySymlinks(_, 3))
+        ));
+
+        let resolver = Resolver::new(root).with_max_symlink_depth(2);
+        assert!(matches!(
+            resolver.resolve("/links/link3".to_string()),
+            Err(ResolveError::TooManySymlinks(_, 2))
+        ));
+        assert!(resolver.resolve("/links/link2".to_string()).is_ok());
+        assert!(resolver.resolve("/page.html".to_string()).is_ok());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn emptiness_does_not_follow_symlinks() {
+        let base = std::env::temp_dir().join(format!("turbine-{}-empty-root", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("assets/images")).unwrap();
+
+        let resolver = Resolver::new(base.clone());
+        assert!(resolver.is_empty());
+
+        // A link back to the root is not walked into
+        std::os::unix::fs::symlink("..", base.join("assets/up")).unwrap();
+        assert!(!resolver.is_empty());
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn escapes_from_the_document_root_are_rejected() {
+        let base = std::env::temp_dir().join(format!("turbine-{}-escape", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("site/linked")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(base.join("outside/index.html"), "secret").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", base.join("site/passwd")).unwrap();
+        std::os::unix::fs::symlink(base.join("outside"), base.join("site/elsewhere")).unwrap();
+        // The directory is inside, its index file is not
+        std::os::unix::fs::symlink(
+            base.join("outside/index.html"),
+            base.join("site/linked/index.html"),
+        )
+        .unwrap();
+
+        let resolver = Resolver::new(fs::canonicalize(base.join("site")).unwrap());
+        for path in [
+            "/passwd",
+            "/../../../../../../etc/passwd",
+            "/elsewhere/",
+            "/elsewhere/index.html",
+            "/linked/",
+        ] {
+            assert!(
+                matches!(
+                    resolver.resolve(path.to_string()),
+                    Err(ResolveError::PathOutsideDocumentRoot(_))
+                ),
+                "{path}"
+            );
+        }
+
+        // Nor is the index file of the root itself
+        std::os::unix::fs::symlink(
+            base.join("outside/index.html"),
+            base.join("site/index.html"),
+        )
+        .unwrap();
+        for path in ["/", "//"] {
+            assert!(
+                matches!(
+                    resolver.resolve(path.to_string()),
+                    Err(ResolveError::PathOutsideDocumentRoot(_))
+                ),
+                "{path}"
+            );
+        }
+        assert!(matches!(
+            resolver.with_max_symlink_depth(0).resolve("/".to_string()),
+            Err(ResolveError::TooManySymlinks(..))
+        ));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    pub fn index_files_are_tried_in_order() {
+        let base = std::env::temp_dir().join(format!("turbine-{}-index-order", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(base.join("legacy")).unwrap();
+        fs::create_dir_all(base.join("both")).unwrap();
+        fs::write(base.join("legacy/index.htm"), "legacy").unwrap();
+        fs::write(base.join("both/index.htm"), "second").unwrap();
+        fs::write(base.join("both/index.html"), "first").unwrap();
+
+        let resolver = Resolver::new(fs::canonicalize(&base).unwrap()).with_index_files(vec![
+            "index.html".to_string(),
+            "index.htm".to_string(),
+            "default.html".to_string(),
+        ]);
+        let read = |path: &str| fs::read_to_string(resolver.resolve(path.to_string()).unwrap());
+        assert_eq!(read("/legacy/").unwrap(), "legacy");
+        assert_eq!(read("/legacy").unwrap(), "legacy");
+        assert_eq!(read("/both/").unwrap(), "first");
+
+        // Only the configured names count, the default one is not there
+        let resolver = Resolver::new(fs::canonicalize(&base).unwrap());
+        assert!(!resolver.resolve("/legacy/".to_string()).unwrap().is_file());
+
+        fs::remove_dir_all(base).unwrap();
+    }
 }
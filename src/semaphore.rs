@@ -0,0 +1,76 @@
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Counting semaphore bounding how many threads do something at once
+#[derive(Debug)]
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A slot taken from a [Semaphore], given back when dropped
+#[derive(Debug)]
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits up to `timeout` for a free slot, `None` when none freed up in time
+    pub fn acquire(&self, timeout: Duration) -> Option<Permit<'_>> {
+        let available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut available, _) = self
+            .released
+            .wait_timeout_while(available, timeout, |available| *available == 0)
+            .unwrap_or_else(|e| e.into_inner());
+
+        if *available == 0 {
+            return None;
+        }
+        *available -= 1;
+
+        Some(Permit { semaphore: self })
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut available = self
+            .semaphore
+            .available
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *available += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    pub fn permits_are_bounded_and_returned() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let permit = semaphore.acquire(Duration::ZERO).unwrap();
+        assert!(semaphore.acquire(Duration::from_millis(10)).is_none());
+
+        let waiting = {
+            let semaphore = Arc::clone(&semaphore);
+            thread::spawn(move || semaphore.acquire(Duration::from_secs(5)).is_some())
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(permit);
+        assert!(waiting.join().unwrap());
+        assert!(semaphore.acquire(Duration::ZERO).is_some());
+    }
+}
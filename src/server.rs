@@ -1,14 +1,51 @@
+use log::{debug, error, info, log, warn, Level};
+use signal_hook::consts::{SIGINT, SIGTERM};
 use thiserror::Error;
 
-use std::fs;
-use std::io::{Read, Write};
-use std::net::TcpListener;
-use std::net::TcpStream;
+use std::collections::HashMap;
+use std::fs::{self, Metadata, OpenOptions};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, SeekFrom, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::net::{Shutdown, TcpStream};
+use std::path::{Path, PathBuf};
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::config::Config;
-use crate::http::{HttpPath, ParseError, Request as HttpRequest};
-use crate::resolver::{ResolveError, Resolver};
+use crate::access_log::{self, AccessLogFile, ResponseRecorder};
+use crate::compression::{
+    self, CompressedCache, CompressionPolicy, Compressor, Encoding, Flate2Compressor,
+};
+use crate::config::{
+    Config, ConnectResponse, DocumentRootLost, FormHandler, Listener, NoIndexStatus,
+    PipelinedAfterClose,
+};
+use crate::digest::{self, DigestCache};
+use crate::etag;
+use crate::file_cache::FileCache;
+use crate::filesystem::{FileSystem, OsFileSystem, SeekableRead};
+use crate::http::{
+    percent_encode_path, HttpPath, HttpVersion, Method, ParseError, Request as HttpRequest,
+};
+use crate::http_date;
+use crate::listing::{self, ListingFormat};
+use crate::metrics::Metrics;
+use crate::mime;
+use crate::pool::Pool;
+use crate::range::{self, ByteRange};
+use crate::rate_limit::RateLimiter;
+use crate::redirect;
+use crate::resolver::{
+    DeniedPaths, HiddenFiles, ResolveError, ResolvedResource, Resolver, ResolverBackend,
+};
+use crate::semaphore::{Permit, Semaphore};
+use crate::sitemap::Sitemap;
+use crate::snapshot::{Snapshot, SnapshotError, Snapshots};
+use crate::template::EnvTemplate;
+use crate::transfer::{self, HeadOnly, ReadTimeout};
+use crate::version;
 
 #[derive(Error, Debug)]
 pub enum ServerError {
@@ -23,184 +60,5978 @@ pub enum ServerError {
 
     #[error("Resolving the request failed because: {0}")]
     ResolverError(#[from] ResolveError),
+
+    #[error("Snapshotting the document root failed because: {0}")]
+    Snapshot(#[from] SnapshotError),
+
+    #[error("Resource {0} is not part of the memory snapshot")]
+    NotInSnapshot(String),
+
+    #[error("Variant directory {0} is not inside the document root")]
+    VariantOutsideDocumentRoot(PathBuf),
+
+    #[error("The thread accepting connections on {0} panicked")]
+    ListenerPanicked(String),
 }
 
+/// How often a listener checks for a shutdown while no connection comes in
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Longest [PipelinedAfterClose::Drain] waits for the client to stop sending
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
 // Static lifetime is infered here
 const END_OF_CONTENT: &str = "\r\n\r\n";
-const HEADER_STATUS: &str = "HTTP/1.1 200 OK\r\n";
+/// Size of the pieces large files are sent in
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
 const HEADER_CONTENT_TYPE: &str = "Content-Type: text/html; charset=UTF-8\r\n";
+const HEADER_CONTENT_TYPE_JSON: &str = "Content-Type: application/json\r\n";
+const HEADER_CONTENT_TYPE_TEXT: &str = "Content-Type: text/plain; charset=UTF-8\r\n";
+const HEADER_CONTENT_TYPE_XML: &str = "Content-Type: application/xml\r\n";
 const NEW_LINE: &str = "\r\n";
+const LANDING_PAGE_BODY: &str = "<html><body><h1>turbine is running</h1>\
+<p>The document root holds no content yet. Add files to it to serve them.</p></body></html>";
+const FORBIDDEN_BODY: &str = "<html><body><h1>403 Forbidden</h1></body></html>";
+const NOT_FOUND_BODY: &str = "<html><body><h1>404 Not Found</h1></body></html>";
+const RANGE_NOT_SATISFIABLE_BODY: &str =
+    "<html><body><h1>416 Range Not Satisfiable</h1></body></html>";
+const NOT_ACCEPTABLE_BODY: &str = "<html><body><h1>406 Not Acceptable</h1></body></html>";
+const BAD_REQUEST_BODY: &str = "<html><body><h1>400 Bad Request</h1></body></html>";
+const EXPECTATION_FAILED_BODY: &str = "<html><body><h1>417 Expectation Failed</h1></body></html>";
+const NOT_IMPLEMENTED_BODY: &str = "<html><body><h1>501 Not Implemented</h1></body></html>";
+/// Methods listed in the `Allow` header of a 405
+const ALLOWED_METHODS: &str = "GET, HEAD, POST";
+const METHOD_NOT_ALLOWED_BODY: &str = "<html><body><h1>405 Method Not Allowed</h1></body></html>";
+const SERVICE_UNAVAILABLE_BODY: &str = "<html><body><h1>503 Service Unavailable</h1></body></html>";
+const PAYLOAD_TOO_LARGE_BODY: &str = "<html><body><h1>413 Payload Too Large</h1></body></html>";
+const TOO_MANY_REQUESTS_BODY: &str = "<html><body><h1>429 Too Many Requests</h1></body></html>";
+const FORM_RECEIVED_BODY: &str =
+    "<html><body><h1>Thank you, your submission was received</h1></body></html>";
+
+/// Rewrites the content of a served file, see [Server::transform]
+type Transform = Box<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// What is known about the client of a connection, passed along with each of
+/// its requests
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionInfo {
+    /// Address of the client, `None` when the transport has none to tell
+    pub peer: Option<SocketAddr>,
+}
+
+impl ConnectionInfo {
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.peer.map(|peer| peer.ip())
+    }
+}
+
+impl std::fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.peer {
+            Some(peer) => write!(f, "{peer}"),
+            None => write!(f, "an unknown client"),
+        }
+    }
+}
+
+/// What the head of a response depends on besides the response itself: the
+/// version the request was made in, which the status line answers in, and
+/// how many more requests the connection takes
+#[derive(Debug, Clone, Copy)]
+struct Reply {
+    version: HttpVersion,
+    remaining_requests: usize,
+}
+
+impl Reply {
+    /// The last response of a connection, to a request whose version may
+    /// not be known
+    fn last() -> Self {
+        Self {
+            version: HttpVersion::Http11,
+            remaining_requests: 0,
+        }
+    }
+}
+
+/// Which side ended a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionEnd {
+    /// The client closed it or let it idle past the keep-alive timeout, it
+    /// has nothing left to send
+    Client,
+
+    /// The server closed it after a response, the client may still be
+    /// sending requests pipelined behind it
+    Server,
+}
 
 pub struct Server {
+    config: Config,
     resolver: Resolver,
+
+    /// Maps request paths to what is served, the `resolver` unless replaced
+    backend: Box<dyn ResolverBackend>,
+
+    /// Resolvers rooted at each variant directory, keyed by cookie value
+    variant_resolvers: HashMap<String, Resolver>,
+
+    /// Servers for the document roots of the `virtual_hosts`, keyed by
+    /// lowercased host name
+    virtual_hosts: HashMap<String, Server>,
+
+    /// Present when the document root is served from memory
+    snapshot: Option<Arc<Snapshot>>,
+
+    /// Shared with the virtual hosts and the servers of the other listeners,
+    /// see [Snapshots]
+    snapshots: Arc<Snapshots>,
+
+    file_system: Box<dyn FileSystem>,
+
+    /// Present when `max_concurrent_reads` is set, shared by every listener
+    file_reads: Option<Arc<Semaphore>>,
+
+    /// Serializes appends to the form submission files across connections
+    form_submissions: Mutex<()>,
+
+    /// Present when `access_log` is set, shared by every listener
+    access_log: Option<Arc<AccessLogFile>>,
+
+    /// Present when `rate_limit` is set, shared by every listener so a client
+    /// has one budget however many ports it connects to
+    rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// Set by the watchdog while the document root is inaccessible. Shared by
+    /// the listeners serving the same document root, each virtual host has
+    /// its own.
+    maintenance: Arc<AtomicBool>,
+
+    /// Set by SIGINT or SIGTERM, shared by every listener. Connections are no
+    /// longer accepted and the ones open are closed after their current request.
+    shutdown: Arc<AtomicBool>,
+
+    /// When the server was created, reported as uptime by the version endpoint
+    started: Instant,
+
+    compression: CompressionPolicy,
+
+    compressor: Box<dyn Compressor>,
+
+    /// Present when `compressed_cache_max_bytes` is set. Keyed by absolute
+    /// path, so shared with the virtual hosts and every listener.
+    compressed_cache: Option<Arc<CompressedCache>>,
+
+    /// Present when `file_cache_max_bytes` is set, shared like the
+    /// `compressed_cache`
+    file_cache: Option<Arc<FileCache>>,
+
+    /// Present when `sitemap_base_url` is configured
+    sitemap: Option<Sitemap>,
+
+    /// Digests of served files, used when `digest_header` is on. Shared like
+    /// the `compressed_cache`.
+    digests: Arc<DigestCache>,
+
+    /// Transformations of served files, keyed by lowercased file extension
+    transforms: HashMap<String, Transform>,
+
+    /// Present when `substituted_env_vars` are configured
+    env_template: Option<EnvTemplate>,
+
+    /// Content type header and content of the `error_pages`, keyed by status
+    /// code. Read once when the server is created, answering an error never
+    /// waits for the disk.
+    error_pages: HashMap<String, (String, Vec<u8>)>,
+
+    /// Serve nothing but the version and metrics endpoints, see [Listener]
+    admin_only: bool,
+
+    /// Shared with the servers of the other listeners
+    metrics: Arc<Metrics>,
 }
 
 impl Server {
     pub fn new(config: Config) -> Result<Self, ServerError> {
-        let document_root = config.document_root;
-        let canonicalized_document_root = fs::canonicalize(document_root)?;
-        Ok(Self {
-            resolver: Resolver::new(canonicalized_document_root),
-        })
+        Self::build(config, Arc::default())
     }
 
-    pub fn run(&self) -> Result<(), ServerError> {
-        println!("Starting turbine");
+    /// A server for `config` sharing the state of `other`, see
+    /// [Server::share_state_with]. A document root `other` already holds a
+    /// snapshot of is not loaded again.
+    fn sharing_state_with(config: Config, other: &Server) -> Result<Self, ServerError> {
+        let mut server = Self::build(config, Arc::clone(&other.snapshots))?;
+        server.share_state_with(other);
+        Ok(server)
+    }
+
+    fn build(config: Config, snapshots: Arc<Snapshots>) -> Result<Self, ServerError> {
+        let canonicalized_document_root = fs::canonicalize(&config.document_root)?;
+
+        let snapshot = if config.memory_snapshot {
+            Some(snapshots.get_or_load(
+                &canonicalized_document_root,
+                config.memory_snapshot_max_bytes,
+            )?)
+        } else {
+            None
+        };
+
+        let compression = CompressionPolicy::new(
+            config.compressible_types.clone(),
+            config.incompressible_types.clone(),
+        );
+
+        let file_reads = config
+            .max_concurrent_reads
+            .map(|permits| Arc::new(Semaphore::new(permits)));
+
+        let access_log = config
+            .access_log
+            .as_deref()
+            .map(AccessLogFile::open)
+            .transpose()?
+            .map(Arc::new);
+
+        let rate_limiter = config
+            .rate_limit
+            .map(|limit| Arc::new(RateLimiter::new(limit)));
 
-        let listener = TcpListener::bind("0.0.0.0:12345")?;
+        let compressed_cache = (config.compressed_cache_max_bytes > 0)
+            .then(|| Arc::new(CompressedCache::new(config.compressed_cache_max_bytes)));
 
-        for stream in listener.incoming() {
-            println!("#### New connection received");
-            if let Ok(s) = stream {
-                let res = self.serve_file(s);
-                println!("{:?}", res);
+        let file_cache = (config.file_cache_max_bytes > 0)
+            .then(|| Arc::new(FileCache::new(config.file_cache_max_bytes)));
+
+        let sitemap = config
+            .sitemap_base_url
+            .as_ref()
+            .map(|base_url| Sitemap::new(base_url, Duration::from_secs(config.sitemap_cache_secs)));
+
+        let env_template = (!config.substituted_env_vars.is_empty())
+            .then(|| EnvTemplate::from_env(&config.substituted_env_vars));
+
+        let hidden_files = HiddenFiles::new(config.serve_hidden, &config.allowed_hidden_paths);
+        let denied_paths = DeniedPaths::new(&config.deny);
+
+        let mut variant_resolvers = HashMap::new();
+        for (value, directory) in &config.variants {
+            let directory = fs::canonicalize(canonicalized_document_root.join(directory))?;
+            if !directory.starts_with(&canonicalized_document_root) {
+                return Err(ServerError::VariantOutsideDocumentRoot(directory));
             }
+            let resolver = Resolver::new(directory)
+                .with_hidden_files(hidden_files.clone())
+                .with_denied_paths(denied_paths.clone())
+                .with_index_files(config.index_files.clone())
+                .with_max_symlink_depth(config.max_symlink_depth);
+            variant_resolvers.insert(value.clone(), resolver);
+        }
+
+        let resolver = Resolver::new(canonicalized_document_root)
+            .with_hidden_files(hidden_files)
+            .with_denied_paths(denied_paths)
+            .with_index_files(config.index_files.clone())
+            .with_max_symlink_depth(config.max_symlink_depth);
+
+        if resolver.is_empty() {
+            warn!(
+                "Document root {} holds no files, every request will be answered with 404",
+                resolver.document_root().display()
+            );
+        }
+
+        let mut server = Self {
+            config,
+            backend: Box::new(resolver.clone()),
+            resolver,
+            variant_resolvers,
+            virtual_hosts: HashMap::new(),
+            error_pages: HashMap::new(),
+            snapshot,
+            snapshots,
+            file_system: Box::new(OsFileSystem),
+            file_reads,
+            form_submissions: Mutex::new(()),
+            access_log,
+            rate_limiter,
+            maintenance: Arc::default(),
+            shutdown: Arc::default(),
+            started: Instant::now(),
+            compression,
+            compressor: Box::new(Flate2Compressor),
+            compressed_cache,
+            file_cache,
+            sitemap,
+            digests: Arc::default(),
+            transforms: HashMap::new(),
+            env_template,
+            admin_only: false,
+            metrics: Arc::default(),
+        };
+
+        server.error_pages = server.load_error_pages();
+
+        for (host, document_root) in &server.config.virtual_hosts {
+            let mut config = server.config.clone();
+            config.document_root = document_root.clone();
+            config.virtual_hosts.clear();
+
+            let host_server = Server::sharing_state_with(config, &server)?;
+            server
+                .virtual_hosts
+                .insert(host.to_ascii_lowercase(), host_server);
+        }
+
+        Ok(server)
+    }
+
+    /// Makes this server count in the metrics of `other` and share its
+    /// shutdown flag, limits, access log and caches, along with its virtual
+    /// hosts. Their byte budgets are then spent once for all of them. The
+    /// maintenance mode is shared as well when serving the same document root.
+    fn share_state_with(&mut self, other: &Server) {
+        if self.resolver.document_root() == other.resolver.document_root() {
+            self.maintenance = Arc::clone(&other.maintenance);
+        }
+        self.started = other.started;
+        self.metrics = Arc::clone(&other.metrics);
+        self.shutdown = Arc::clone(&other.shutdown);
+        self.file_reads = other.file_reads.clone();
+        self.access_log = other.access_log.clone();
+        self.rate_limiter = other.rate_limiter.clone();
+        self.compressed_cache = other.compressed_cache.clone();
+        self.file_cache = other.file_cache.clone();
+        self.digests = Arc::clone(&other.digests);
+        for (host, host_server) in &mut self.virtual_hosts {
+            host_server.share_state_with(other.virtual_hosts.get(host).unwrap_or(other));
+        }
+    }
+
+    /// Replaces the [ResolverBackend] mapping request paths to resources
+    pub fn with_resolver_backend(mut self, backend: impl ResolverBackend + 'static) -> Self {
+        self.backend = Box::new(backend);
+        self
+    }
+
+    /// Registers a transformation applied to the content of served files with
+    /// the given `extension`, e.g. to minify HTML or inject a script, after the
+    /// file is read and before it is written out.
+    ///
+    /// Transformed responses carry no ETag, the file's one would not describe
+    /// the transformed content
+    pub fn transform(
+        mut self,
+        extension: &str,
+        transform: impl Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    ) -> Self {
+        self.transforms
+            .insert(extension.to_ascii_lowercase(), Box::new(transform));
+        self
+    }
+
+    /// Replaces the [Compressor] used to gzip response bodies
+    #[cfg(test)]
+    pub fn with_compressor(mut self, compressor: impl Compressor + 'static) -> Self {
+        self.compressor = Box::new(compressor);
+        self
+    }
+
+    /// Replaces the [FileSystem] used to open resolved resources
+    #[cfg(test)]
+    pub fn with_file_system(mut self, file_system: impl FileSystem + 'static) -> Self {
+        self.file_system = Box::new(file_system);
+        self
+    }
+
+    /// Binds every configured [Listener] and serves them until one fails, or
+    /// until SIGINT or SIGTERM is received. Then the connections being served
+    /// are finished before returning.
+    pub fn run(self) -> Result<(), ServerError> {
+        info!("{}", version::banner());
+
+        let signals = [SIGINT, SIGTERM]
+            .into_iter()
+            .map(|signal| signal_hook::flag::register(signal, Arc::clone(&self.shutdown)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let result = self
+            .bind()
+            .and_then(|listeners| self.run_listeners(listeners));
+
+        for signal in signals {
+            signal_hook::low_level::unregister(signal);
+        }
+        result
+    }
+
+    /// Serves the bound `listeners` until the shutdown flag is set
+    fn run_listeners(&self, listeners: Vec<(Listener, TcpListener)>) -> Result<(), ServerError> {
+        let has_admin = listeners.iter().any(|(listener, _)| listener.admin_only);
+        let pool = self.worker_pool();
+
+        let mut servers = Vec::new();
+        for (listener, tcp_listener) in listeners {
+            let server = Arc::new(self.for_listener(&listener, has_admin)?);
+            servers.push((listener.bind, server, tcp_listener));
+        }
+
+        if self.config.document_root_watchdog_secs > 0 {
+            let watched = servers
+                .iter()
+                .map(|(_, server, _)| Arc::clone(server))
+                .collect::<Vec<_>>();
+            thread::spawn(move || Server::run_document_root_watchdog(&watched));
+        }
+
+        let mut accepting = Vec::new();
+        for (bind, server, tcp_listener) in servers {
+            let pool = pool.clone();
+            let handle = thread::spawn(move || server.accept(tcp_listener, pool));
+            accepting.push((bind, handle));
+        }
+
+        for (bind, handle) in accepting {
+            handle
+                .join()
+                .map_err(|_| ServerError::ListenerPanicked(bind))??;
         }
 
+        if let Some(pool) = pool {
+            info!("Shutting down, waiting for the open connections");
+            pool.join();
+        }
         Ok(())
     }
 
-    /// Reads the content of the stream until the end of the request is reached
-    /// Acts as a converter from [TcpStream] to [http::Request] to ensure a validated request
-    /// and separation of concerns going forward
-    fn read_stream_content_to_end(
-        &self,
-        stream: &mut TcpStream,
-    ) -> Result<HttpRequest, ParseError> {
-        let mut buffer = [0; 1024]; // Adjust buffer size as needed
-        let mut request = Vec::new();
+    /// Binds the configured listeners, or `listen_addr` without any
+    fn bind(&self) -> Result<Vec<(Listener, TcpListener)>, ServerError> {
+        let listeners = if self.config.listeners.is_empty() {
+            vec![Listener {
+                bind: self.config.listen_addr.to_string(),
+                ..Listener::default()
+            }]
+        } else {
+            self.config.listeners.clone()
+        };
+
+        listeners
+            .into_iter()
+            .map(|listener| {
+                let tcp_listener = TcpListener::bind(&listener.bind)?;
+                Ok((listener, tcp_listener))
+            })
+            .collect()
+    }
+
+    /// A server for `listener`, sharing the metrics of this one. With an admin
+    /// listener among the configured ones, the other listeners do not expose
+    /// the version and metrics endpoints.
+    fn for_listener(&self, listener: &Listener, has_admin: bool) -> Result<Server, ServerError> {
+        let mut config = self.config.clone();
+        if let Some(document_root) = &listener.document_root {
+            config.document_root = document_root.clone();
+        }
+        if has_admin && !listener.admin_only {
+            config.version_endpoint = None;
+            config.metrics_endpoint = None;
+        }
+
+        let mut server = Server::sharing_state_with(config, self)?;
+        server.admin_only = listener.admin_only;
+        Ok(server)
+    }
+
+    /// The workers of the configured [crate::config::ConcurrencyModel], `None` when every
+    /// connection gets its own thread
+    fn worker_pool(&self) -> Option<Pool> {
+        Pool::new(self.config.concurrency_model)
+    }
+
+    /// Accepts connections from `listener` and serves them according to the
+    /// configured [crate::config::ConcurrencyModel]
+    #[cfg(test)]
+    fn serve(self, listener: TcpListener) -> Result<(), ServerError> {
+        let pool = self.worker_pool();
+        Arc::new(self).accept(listener, pool)
+    }
+
+    /// Accepts connections from `listener` and serves them on `pool`, or on a
+    /// thread each without one, until shutting down. The listener is polled so
+    /// the shutdown flag is noticed without a connection coming in. Once it is,
+    /// connections are turned away with a 503 for `shutdown_reject_secs`.
+    ///
+    /// Without a pool the connection threads are waited for before returning,
+    /// the caller waits for the pool.
+    fn accept(
+        self: Arc<Self>,
+        listener: TcpListener,
+        pool: Option<Pool>,
+    ) -> Result<(), ServerError> {
+        let server = self;
+
+        listener.set_nonblocking(true)?;
+        let mut connection_threads = Vec::new();
+        let mut rejecting_until = None;
 
         loop {
-            let bytes_read = stream.read(&mut buffer)?;
+            if server.shutting_down() {
+                let reject_window = Duration::from_secs(server.config.shutdown_reject_secs);
+                let until = *rejecting_until.get_or_insert_with(|| Instant::now() + reject_window);
+                if Instant::now() >= until {
+                    break;
+                }
+            }
 
-            if bytes_read == 0 {
-                break; // Connection was closed
+            let s = match listener.accept() {
+                Ok((s, _)) => s,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(ACCEPT_POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    debug!("Failed to accept a connection: {e}");
+                    continue;
+                }
+            };
+            debug!("New connection received");
+            // Inherited from the listener on some platforms
+            if s.set_nonblocking(false).is_err() {
+                continue;
             }
 
-            request.extend_from_slice(&buffer[..bytes_read]);
+            if server.shutting_down() {
+                server.shed(&s, "the server is shutting down");
+                // The request is read off aside, closing with it unread would
+                // reset the connection before the client got the 503
+                connection_threads.push(thread::spawn(move || drain(&s)));
+                continue;
+            }
+            if let Some(pool) = &pool {
+                if server.is_saturated(pool) {
+                    server.shed(&s, "the worker queue is full");
+                    // Drained aside for the same reason as during shutdown
+                    connection_threads.retain(|thread: &JoinHandle<()>| !thread.is_finished());
+                    connection_threads.push(thread::spawn(move || drain(&s)));
+                    continue;
+                }
+            }
 
-            // Check if the end of the request is reached
-            if request.ends_with(b"\r\n\r\n") {
-                break;
+            let server = Arc::clone(&server);
+            let job = move || {
+                if let Err(e) = server.handle_tcp_connection(s) {
+                    error!("Connection failed: {e}");
+                }
+            };
+
+            match &pool {
+                Some(pool) => pool.execute(job),
+                None => {
+                    connection_threads.retain(|thread: &JoinHandle<()>| !thread.is_finished());
+                    connection_threads.push(thread::spawn(job));
+                }
             }
         }
 
-        let request = String::from_utf8_lossy(&request).to_string();
-        let request = HttpRequest::new(request)?;
+        for thread in connection_threads {
+            let _ = thread.join();
+        }
+        Ok(())
+    }
 
-        Ok(request)
+    fn shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
     }
 
-    /// Parses the request and returns the resource path
-    /// Resource path is the path to the file that should be served
-    /// The path is validated to ensure that it is a file inside the web_resources directory
-    /// It defaults to index.html if the path is a directory
-    fn parse_request(&self, request: &HttpRequest) -> Result<HttpPath, ResolveError> {
-        self.resolver.resolve(request.headers.resource.clone())
+    /// Whether `pool` already holds as many waiting connections as configured
+    fn is_saturated(&self, pool: &Pool) -> bool {
+        self.config
+            .max_queued_connections
+            .is_some_and(|max_queued| pool.queued_count() >= max_queued)
     }
 
-    /// Reads the content of the file specified by the resource path
-    fn get_resource_content(&self, resource: &HttpPath) -> std::io::Result<String> {
-        let file_content = fs::read_to_string(resource)?;
-        Ok(file_content)
+    /// Turns a connection away with a 503 from the accepting thread, used when
+    /// every worker is busy and the queue is full or the server shuts down
+    fn shed(&self, mut stream: &TcpStream, reason: &str) {
+        debug!("Shedding connection, {reason}");
+        let result = stream
+            .set_write_timeout(Some(self.write_timeout()))
+            .map_err(ServerError::from)
+            .and_then(|_| {
+                self.write_error_response(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    SERVICE_UNAVAILABLE_BODY,
+                    Reply::last(),
+                )
+            });
+        if let Err(e) = result {
+            debug!("Failed to shed connection: {e}");
+        }
     }
 
-    /// Serves the file specified by the resource path back to the client
-    fn serve_file(&self, mut stream: TcpStream) -> Result<(), ServerError> {
-        let request = self.read_stream_content_to_end(&mut stream)?;
+    /// Periodically checks the document roots of `servers`, one per listener,
+    /// until shutting down. When one is lost and the configured reaction is
+    /// [DocumentRootLost::Shutdown], the server shuts down like on SIGTERM.
+    fn run_document_root_watchdog(servers: &[Arc<Server>]) {
+        let Some(first) = servers.first() else {
+            return;
+        };
+        let interval = Duration::from_secs(first.config.document_root_watchdog_secs);
+
+        while !first.shutting_down() {
+            thread::sleep(interval);
 
-        let resource = self.parse_request(&request)?;
+            // Every server is checked, each applies its own reaction
+            let lost = servers
+                .iter()
+                .filter(|server| !server.check_document_roots())
+                .count();
+            if lost > 0 {
+                error!("Shutting down because a document root is inaccessible");
+                first.shutdown.store(true, Ordering::SeqCst);
+            }
+        }
+    }
 
-        let resource_content = self.get_resource_content(&resource)?;
-        let content_length = resource_content.len() + END_OF_CONTENT.len();
+    /// Checks the document root of this server and those of its virtual
+    /// hosts, see [Server::check_document_root]
+    fn check_document_roots(&self) -> bool {
+        self.virtual_hosts
+            .values()
+            .fold(self.check_document_root(), |keep_serving, host_server| {
+                host_server.check_document_root() && keep_serving
+            })
+    }
 
-        stream.write_all(HEADER_STATUS.as_bytes())?;
-        stream.write_all(HEADER_CONTENT_TYPE.as_bytes())?;
+    /// Checks that the document root is still an accessible directory and
+    /// applies the configured [DocumentRootLost] reaction when it is not.
+    /// Maintenance mode is lifted as soon as the document root is back.
+    ///
+    /// Returns `false` when the server should shut down
+    fn check_document_root(&self) -> bool {
+        let document_root = &self.config.document_root;
+        let accessible = fs::read_dir(document_root).is_ok();
 
-        let content_length = format!("Content-Length: {}\r\n", content_length);
-        stream.write_all(content_length.as_bytes())?;
+        if accessible {
+            if self.maintenance.swap(false, Ordering::SeqCst) {
+                info!(
+                    "Document root {} is accessible again, leaving maintenance mode",
+                    document_root.display()
+                );
+            }
+            return true;
+        }
 
-        stream.write_all(NEW_LINE.as_bytes())?;
+        match self.config.document_root_lost {
+            DocumentRootLost::Maintenance => {
+                if !self.maintenance.swap(true, Ordering::SeqCst) {
+                    error!(
+                        "Document root {} is inaccessible, answering every request with 503",
+                        document_root.display()
+                    );
+                }
+                true
+            }
+            DocumentRootLost::Shutdown => {
+                error!("Document root {} is inaccessible", document_root.display());
+                false
+            }
+        }
+    }
 
-        stream.write_all(resource_content.as_bytes())?;
-        stream.write_all(END_OF_CONTENT.as_bytes())?;
+    /// Applies the write timeout to the socket before serving it, the read
+    /// timeouts are set as requests come in
+    fn handle_tcp_connection(&self, stream: TcpStream) -> Result<(), ServerError> {
+        stream.set_write_timeout(Some(self.write_timeout()))?;
+        let connection = ConnectionInfo {
+            peer: stream.peer_addr().ok(),
+        };
+        let end = self.handle_connection_from(&stream, connection)?;
 
+        if end == ConnectionEnd::Server
+            && self.config.pipelined_after_close == PipelinedAfterClose::Drain
+        {
+            drain(&stream);
+        }
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::http::*;
+    fn write_timeout(&self) -> Duration {
+        Duration::from_secs(self.config.write_timeout_secs)
+    }
 
-    #[test]
-    pub fn test_parse_headers_fail() {
-        assert!(Headers::new(vec![]).is_err());
-        assert!(Headers::new(vec!["GET", "/"]).is_err());
-        assert!(Headers::new(vec!["GWET", "/", "HTTP/1.1"]).is_err());
+    /// Writes `bytes` to the client, tolerating short and stalled writes up to
+    /// the configured write timeout
+    fn send<S: Write>(&self, stream: &mut S, bytes: &[u8]) -> std::io::Result<()> {
+        transfer::write_fully(stream, bytes, self.write_timeout())
     }
 
-    #[test]
-    pub fn test_parse_headers() {
-        let header = Headers::new(vec!["GET", "/", "HTTP/1.1"]);
-        println!("{header:?}");
+    /// [Server::handle_connection_from] a client at a documentation address,
+    /// `192.0.2.1:50000`
+    #[cfg(test)]
+    fn handle_connection<S: Read + Write + ReadTimeout>(
+        &self,
+        stream: S,
+    ) -> Result<ConnectionEnd, ServerError> {
+        let connection = ConnectionInfo {
+            peer: Some(SocketAddr::from(([192, 0, 2, 1], 50000))),
+        };
+        self.handle_connection_from(stream, connection)
+    }
 
-        assert!(Headers::new(vec!["GET", "/", "HTTP/1.1"]).is_ok());
-        assert!(Headers::new(vec!["POST", "/", "HTTP/1.1"]).is_ok());
-        assert!(Headers::new(vec!["GET", "/foo", "HTTP/1.1"]).is_ok());
+    /// Serves requests from the same connection until the client closes it,
+    /// the idle timeout expires, the keep-alive request budget is spent, the
+    /// connection outlived `keep_alive_max_lifetime_secs` or the server shuts
+    /// down. Tells which side ended it.
+    fn handle_connection_from<S: Read + Write + ReadTimeout>(
+        &self,
+        stream: S,
+        connection: ConnectionInfo,
+    ) -> Result<ConnectionEnd, ServerError> {
+        let expires = (self.config.keep_alive_max_lifetime_secs > 0).then(|| {
+            Instant::now() + Duration::from_secs(self.config.keep_alive_max_lifetime_secs)
+        });
+        self.serve_connection(stream, connection, expires)
     }
 
-    #[test]
-    pub fn try_from_for_http_path() {
-        let document_root = std::env::current_dir().unwrap().join("web_resources");
+    /// [Server::handle_connection_from] for a connection whose lifetime is
+    /// over at `expires`
+    fn serve_connection<S: Read + Write + ReadTimeout>(
+        &self,
+        stream: S,
+        connection: ConnectionInfo,
+        expires: Option<Instant>,
+    ) -> Result<ConnectionEnd, ServerError> {
+        self.metrics.connection_accepted();
+        let mut stream = BufReader::new(stream);
+        let max_requests = if self.config.close_delimited_responses {
+            1
+        } else {
+            self.config.keep_alive_max_requests.max(1)
+        };
 
-        let path = HttpPath::try_from("/index.html".to_string());
-        assert!(path.is_ok());
-        assert_eq!(
-            path.unwrap().as_path(),
-            Path::new(document_root.join("index.html").to_str().unwrap())
-        );
+        for served in 1..=max_requests {
+            stream
+                .get_ref()
+                .set_read_timeout(Duration::from_secs(self.config.keep_alive_timeout_secs))?;
+            let request = match self.read_stream_content_to_end(&mut stream) {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(ConnectionEnd::Client),
+                Err(e) => {
+                    self.write_parse_error(stream.get_mut(), e)?;
+                    return Ok(ConnectionEnd::Server);
+                }
+            };
+            self.metrics.request_received();
 
-        let path = HttpPath::try_from("/".to_string());
-        assert!(path.is_ok());
-        assert_eq!(
-            path.unwrap().as_path(),
-            Path::new(document_root.join("index.html").to_str().unwrap())
-        );
+            // The request that finds the lifetime over is still answered, as
+            // the last one of the connection
+            let expired = expires.is_some_and(|expires| Instant::now() >= expires);
+            let closing = expired || request.headers.wants_close() || self.shutting_down();
+            let remaining = if closing { 0 } else { max_requests - served };
+            let started = Instant::now();
+            // Records what reaches the client, not the body HEAD drops
+            let mut recorder = ResponseRecorder::new(stream.get_mut());
+            let mut head_only;
+            let mut writer: &mut dyn Write = if request.headers.method == Method::Head {
+                head_only = HeadOnly::new(&mut recorder);
+                &mut head_only
+            } else {
+                &mut recorder
+            };
+            let reply = Reply {
+                version: request.headers.version,
+                remaining_requests: remaining,
+            };
+            let served = self.serve_file(&mut writer, &request, &connection, reply);
 
-        let path = HttpPath::try_from("/foo".to_string());
-        assert!(path.is_ok());
-        assert_eq!(
-            path.unwrap().as_path(),
-            Path::new(document_root.join("foo/index.html").to_str().unwrap())
-        );
+            if let Some(status) = recorder.status() {
+                let level = match status {
+                    500.. => Level::Error,
+                    400..=499 => Level::Warn,
+                    _ => Level::Info,
+                };
+                let entry = access_log::Entry {
+                    timestamp: SystemTime::now(),
+                    request: &request,
+                    status,
+                    bytes: recorder.bytes(),
+                    body_bytes: recorder.body_bytes(),
+                    duration: started.elapsed(),
+                    client_ip: connection.client_ip(),
+                };
+                if let Some(access_log) = &self.access_log {
+                    if let Err(e) = access_log.append(&entry) {
+                        warn!("Failed to write to the access log: {e}");
+                    }
+                }
+                if self.config.access_log_json {
+                    log!(target: "access", level, "{}", entry.to_json());
+                } else {
+                    // Encoded again like the access log file, one request
+                    // stays one line whatever its path decodes to
+                    log!(
+                        target: "access",
+                        level,
+                        "{} {} {status} {} bytes",
+                        request.headers.method.as_str(),
+                        percent_encode_path(&request.headers.resource),
+                        recorder.bytes()
+                    );
+                }
+            }
+            served?;
 
-        let path = HttpPath::try_from("/foo/".to_string());
-        assert!(path.is_ok());
-        assert_eq!(
-            path.unwrap().as_path(),
-            Path::new(document_root.join("foo/index.html").to_str().unwrap())
-        );
+            // Requests pipelined behind this one are dropped with the buffer
+            if remaining == 0 {
+                break;
+            }
+        }
 
-        let path = HttpPath::try_from("/foo/bar".to_string());
-        println!("{:?}", path);
-        assert!(path.is_ok());
-        assert_eq!(
-            path.unwrap().as_path(),
-            Path::new(document_root.join("foo/bar/index.html").to_str().unwrap())
-        );
+        Ok(ConnectionEnd::Server)
+    }
 
-        let path = HttpPath::try_from("".to_string());
-        assert!(path.is_err());
+    /// Reads the content of the stream until the end of the request is reached
+    /// Acts as a converter from [TcpStream] to [http::Request] to ensure a validated request
+    /// and separation of concerns going forward
+    ///
+    /// Returns `None` when the client closed the connection, or let it idle past
+    /// the keep-alive timeout, before sending another request. Once the request
+    /// started arriving, a read waiting longer than `read_timeout_secs` fails
+    /// with [ParseError::RequestTimeout].
+    fn read_stream_content_to_end<S: Read + Write + ReadTimeout>(
+        &self,
+        stream: &mut BufReader<S>,
+    ) -> Result<Option<HttpRequest>, ParseError> {
+        let mut request = Vec::new();
 
-        let path = HttpPath::try_from("../index.html".to_string());
-        assert!(path.is_err());
+        match self.read_request_line(stream, &mut request) {
+            Err(ParseError::IO(e))
+                if request.is_empty()
+                    && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                return Ok(None);
+            }
+            result => result.map_err(|e| self.request_timeout(e))?,
+        }
 
-        let path = HttpPath::try_from("/../index.html".to_string());
-        assert!(path.is_err());
+        if !self.config.strict_method_case {
+            let method_end = request
+                .iter()
+                .position(|byte| *byte == b' ')
+                .unwrap_or(request.len());
+            request[..method_end].make_ascii_uppercase();
+        }
 
-        let path = HttpPath::try_from("foo".to_string());
-        assert!(path.is_err());
+        if request.is_empty() {
+            return Ok(None); // Connection was closed
+        }
+        self.read_header_lines(stream, &mut request)
+            .map_err(|e| self.request_timeout(e))?;
+
+        let mut request = HttpRequest::parse(&request)?;
+
+        let content_length = request.headers.content_length()?;
+        if content_length > self.config.max_body_bytes {
+            return Err(ParseError::BodyTooLarge(
+                content_length,
+                self.config.max_body_bytes,
+            ));
+        }
+
+        // The client holds the body back until it is told to go ahead. An
+        // HTTP/1.0 client does not know to wait (RFC 9110 section 10.1.1).
+        if content_length > 0
+            && request.headers.expects_continue()
+            && request.headers.version == HttpVersion::Http11
+        {
+            self.send(stream.get_mut(), b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+
+        // A body sent along a GET or HEAD means nothing to it, but is still read so that
+        // the next request on the connection starts where it should
+        let read = if request.headers.method.is_retrieval() {
+            io::copy(&mut stream.take(content_length as u64), &mut io::sink())
+                .map(|read| read as usize)
+        } else {
+            stream
+                .take(content_length as u64)
+                .read_to_end(&mut request.body)
+        };
+        let read = read.map_err(|e| self.request_timeout(e.into()))?;
+        if read < content_length {
+            return Err(ParseError::IO(ErrorKind::UnexpectedEof.into()));
+        }
+
+        Ok(Some(request))
+    }
+
+    /// Turns a read that timed out in the middle of a request into a
+    /// [ParseError::RequestTimeout], other errors are passed on
+    fn request_timeout(&self, error: ParseError) -> ParseError {
+        match error {
+            ParseError::IO(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                ParseError::RequestTimeout(Duration::from_secs(self.config.read_timeout_secs))
+            }
+            other => other,
+        }
+    }
+
+    /// Reads the request line into `line`, stopping early at the end of the
+    /// stream.
+    ///
+    /// Unlike the header lines, the request line is read chunk by chunk so
+    /// that a client sending it too slowly, or sending too much of it, is
+    /// caught before the line is complete. The clock starts with the first byte,
+    /// an idle keep-alive connection is bounded by the read timeout instead.
+    /// From then on reads wait for up to `read_timeout_secs`.
+    fn read_request_line<S: Read + ReadTimeout>(
+        &self,
+        stream: &mut BufReader<S>,
+        line: &mut Vec<u8>,
+    ) -> Result<(), ParseError> {
+        let max_bytes = self.config.max_request_line_bytes;
+        let timeout = Duration::from_secs(self.config.request_line_timeout_secs);
+        let mut started = None;
+
+        loop {
+            let available = stream.fill_buf()?;
+            if available.is_empty() {
+                return Ok(());
+            }
+            let first_chunk = started.is_none();
+            let started = *started.get_or_insert_with(Instant::now);
+
+            let (chunk, complete) = match available.iter().position(|&byte| byte == b'\n') {
+                Some(end) => (&available[..=end], true),
+                None => (available, false),
+            };
+            line.extend_from_slice(chunk);
+            let consumed = chunk.len();
+            stream.consume(consumed);
+            if first_chunk {
+                stream
+                    .get_ref()
+                    .set_read_timeout(Duration::from_secs(self.config.read_timeout_secs))?;
+            }
+
+            if line.len() > max_bytes {
+                return Err(ParseError::RequestLineTooLong(max_bytes));
+            }
+            if complete {
+                return Ok(());
+            }
+            if started.elapsed() > timeout {
+                return Err(ParseError::RequestLineTimeout(timeout));
+            }
+        }
+    }
+
+    /// Reads the header lines following the request line into `head`, up to
+    /// and including the blank line ending them. What follows in the stream,
+    /// the body or the next request, is left unread.
+    ///
+    /// The end of the stream ends the headers early, more than
+    /// `max_header_bytes` of them is an error.
+    fn read_header_lines<S: Read>(
+        &self,
+        stream: &mut BufReader<S>,
+        head: &mut Vec<u8>,
+    ) -> Result<(), ParseError> {
+        let max_bytes = self.config.max_header_bytes;
+        let headers_start = head.len();
+        let terminator = END_OF_CONTENT.as_bytes();
+
+        loop {
+            let available = stream.fill_buf()?;
+            if available.is_empty() {
+                return Ok(());
+            }
+
+            // The terminator may straddle the previous chunk, or the request
+            // line when there are no headers
+            let search_from = head.len().saturating_sub(terminator.len() - 1);
+            let read = available.len();
+            head.extend_from_slice(available);
+            let end = head[search_from..]
+                .windows(terminator.len())
+                .position(|window| window == terminator)
+                .map(|position| search_from + position + terminator.len());
+
+            let consumed = match end {
+                Some(end) => read - (head.len() - end),
+                None => read,
+            };
+            stream.consume(consumed);
+            if let Some(end) = end {
+                head.truncate(end);
+            }
+
+            if head.len() - headers_start > max_bytes {
+                return Err(ParseError::HeadersTooLarge(max_bytes));
+            }
+            if end.is_some() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parses the request and returns the resource to serve, as mapped by the
+    /// [ResolverBackend]. With the default backend it is a file inside the
+    /// document root, its index file if the path is a directory
+    ///
+    /// When the variant cookie selects a configured variant, a file present in
+    /// the variant directory takes precedence
+    fn parse_request(&self, request: &HttpRequest) -> Result<ResolvedResource, ResolveError> {
+        let variant = self
+            .config
+            .variant_cookie
+            .as_ref()
+            .and_then(|name| request.headers.cookie(name))
+            .and_then(|value| self.variant_resolvers.get(value));
+
+        if let Some(variant) = variant {
+            match variant.resolve(request.headers.resource.clone()) {
+                Ok(path) if path.is_file() => return Ok(ResolvedResource::File(path)),
+                _ => {}
+            }
+        }
+
+        self.backend
+            .resolve(&request.headers.resource, &request.headers)
+    }
+
+    /// Reads the content of the file specified by the resource path, from the
+    /// file cache when enabled and the file is unchanged since it was cached
+    fn get_resource_content(&self, resource: &HttpPath) -> std::io::Result<Arc<Vec<u8>>> {
+        if let Some(cache) = &self.file_cache {
+            // Checked on every request, a file modified on disk is never
+            // served from the cache. The resolver already canonicalized the
+            // path, it is the key as is.
+            let metadata = self.file_system.metadata(resource)?;
+            return cache.get_or_read(resource, metadata.modified()?, metadata.len(), || {
+                self.read_resource(resource)
+            });
+        }
+
+        self.read_resource(resource).map(Arc::new)
+    }
+
+    /// Reads the content of `resource` from disk, once one of the
+    /// `max_concurrent_reads` is free. Waiting for longer than
+    /// `read_slot_timeout_secs` fails with [ErrorKind::TimedOut].
+    fn read_resource(&self, resource: &HttpPath) -> std::io::Result<Vec<u8>> {
+        let _permit = self.read_slot()?;
+        self.file_system.read(resource)
+    }
+
+    /// Waits for one of the `max_concurrent_reads`, when they are bounded
+    fn read_slot(&self) -> std::io::Result<Option<Permit<'_>>> {
+        let Some(file_reads) = &self.file_reads else {
+            return Ok(None);
+        };
+
+        file_reads
+            .acquire(Duration::from_secs(self.config.read_slot_timeout_secs))
+            .map(Some)
+            .ok_or_else(|| ErrorKind::TimedOut.into())
+    }
+
+    /// Builds the connection management headers for a response.
+    /// While requests remain in the keep-alive budget the client is told how long
+    /// the connection stays open and how many more requests it may send on it
+    fn connection_headers(&self, remaining: usize) -> String {
+        if remaining == 0 {
+            return format!("Connection: close{NEW_LINE}");
+        }
+
+        format!(
+            "Connection: keep-alive{NEW_LINE}Keep-Alive: timeout={}, max={}{NEW_LINE}",
+            self.config.keep_alive_timeout_secs, remaining
+        )
+    }
+
+    /// Returns the directory to list when the request points to a directory
+    /// without an index file and falls under one of the configured `listing_paths`
+    fn listing_directory(&self, request: &HttpRequest, resource: &HttpPath) -> Option<PathBuf> {
+        if resource.is_file() {
+            return None;
+        }
+
+        let path = format!("{}/", request.headers.resource.trim_end_matches('/'));
+        let allowed = self.config.listing_paths.iter().any(|prefix| {
+            let prefix = format!("{}/", prefix.trim_end_matches('/'));
+            path.starts_with(&prefix)
+        });
+
+        if !allowed {
+            return None;
+        }
+
+        self.resolver.resolve_directory(&request.headers.resource)
+    }
+
+    /// Looks the requested resource up in the memory snapshot
+    fn get_snapshot_content<'a>(
+        &self,
+        snapshot: &'a Snapshot,
+        request: &HttpRequest,
+    ) -> Result<(&'a Path, &'a [u8]), ServerError> {
+        let resource = &request.headers.resource;
+        snapshot
+            .resolve(resource, &self.config.index_files)
+            .filter(|(path, _)| self.resolver.permits(path))
+            .ok_or_else(|| ServerError::NotInSnapshot(resource.clone()))
+    }
+
+    /// Writes a complete response. `extra_headers` are full header lines,
+    /// each terminated by [NEW_LINE]
+    fn write_response<S: Write>(
+        &self,
+        stream: &mut S,
+        status: &str,
+        extra_headers: &str,
+        body: &[u8],
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        self.write_head(
+            stream,
+            status,
+            extra_headers,
+            Some(body.len() as u64),
+            reply,
+        )?;
+        self.send(stream, body)?;
+
+        Ok(())
+    }
+
+    /// Writes the status line and headers of a response whose body of
+    /// `content_length` bytes follows. A HEAD response may leave the length
+    /// out when it is only known once the body is produced (RFC 9110 section
+    /// 9.3.2).
+    fn write_head<S: Write>(
+        &self,
+        stream: &mut S,
+        status: &str,
+        extra_headers: &str,
+        content_length: Option<u64>,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        // A 204 has no body to announce the length of (RFC 9110 section 8.6)
+        let content_length = match content_length {
+            Some(content_length)
+                if !self.config.close_delimited_responses && !status.starts_with("204") =>
+            {
+                format!("Content-Length: {content_length}{NEW_LINE}")
+            }
+            _ => String::new(),
+        };
+        let fixed_headers = format!(
+            "{content_length}{}",
+            self.connection_headers(reply.remaining_requests)
+        );
+        let extra_headers = self.cap_extra_headers(status, extra_headers, &fixed_headers);
+        let header_lines = self.order_headers(format!("{extra_headers}{fixed_headers}"));
+        let headers = format!(
+            "{} {status}{NEW_LINE}{header_lines}{NEW_LINE}",
+            reply.version.as_str()
+        );
+
+        self.send(stream, headers.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Moves the headers named in `response_header_order` to the front of
+    /// `header_lines`, in that order. The others follow in their usual order.
+    fn order_headers(&self, header_lines: String) -> String {
+        let order = &self.config.response_header_order;
+        if order.is_empty() {
+            return header_lines;
+        }
+
+        let rank = |line: &str| {
+            let name = line.split(':').next().unwrap_or_default();
+            order
+                .iter()
+                .position(|ordered| ordered.eq_ignore_ascii_case(name))
+                .unwrap_or(order.len())
+        };
+
+        let mut lines = header_lines.split_inclusive(NEW_LINE).collect::<Vec<_>>();
+        // Stable, so headers of the same rank keep their relative order
+        lines.sort_by_key(|line| rank(line));
+        lines.concat()
+    }
+
+    /// Checks a response against `max_response_headers`, warning when it goes
+    /// over and, if configured, dropping the last of the `extra_headers`.
+    /// The `fixed_headers`, such as the connection headers, are always sent.
+    fn cap_extra_headers<'a>(
+        &self,
+        status: &str,
+        extra_headers: &'a str,
+        fixed_headers: &str,
+    ) -> &'a str {
+        let max_headers = self.config.max_response_headers;
+        let fixed = fixed_headers.matches(NEW_LINE).count();
+        let count = extra_headers.matches(NEW_LINE).count() + fixed;
+        if count <= max_headers {
+            return extra_headers;
+        }
+
+        warn!("Response {status} has {count} headers, more than the limit of {max_headers}");
+        if !self.config.truncate_response_headers {
+            return extra_headers;
+        }
+
+        let kept = max_headers.saturating_sub(fixed);
+        let end = match kept.checked_sub(1) {
+            Some(last) => extra_headers
+                .match_indices(NEW_LINE)
+                .nth(last)
+                .map_or(extra_headers.len(), |(start, _)| start + NEW_LINE.len()),
+            None => 0,
+        };
+        &extra_headers[..end]
+    }
+
+    /// Writes a complete error response, with the page configured for
+    /// `status` in `error_pages` or else the small HTML `body`
+    fn write_error_response<S: Write>(
+        &self,
+        stream: &mut S,
+        status: &str,
+        body: &str,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        if let Some((content_type, page)) = self.error_page(status) {
+            return self.write_response(stream, status, &content_type, &page, reply);
+        }
+
+        self.write_response(stream, status, HEADER_CONTENT_TYPE, body.as_bytes(), reply)
+    }
+
+    /// Content type header and content of the page configured for `status`
+    /// in `error_pages`, `None` to answer with the built-in page
+    fn error_page(&self, status: &str) -> Option<(String, Vec<u8>)> {
+        let code = status.split(' ').next()?;
+        self.error_pages.get(code).cloned()
+    }
+
+    /// Reads the configured `error_pages` from the document root. A page that
+    /// cannot be read is left out with a warning, its status keeps the
+    /// built-in page.
+    fn load_error_pages(&self) -> HashMap<String, (String, Vec<u8>)> {
+        let mut pages = HashMap::new();
+        for (code, page) in &self.config.error_pages {
+            let resource = format!("/{}", page.to_string_lossy().trim_start_matches('/'));
+            let content = self
+                .resolver
+                .resolve(resource)
+                .map_err(ServerError::from)
+                .and_then(|path| {
+                    let content_type =
+                        format!("Content-Type: {}{NEW_LINE}", self.content_type_for(&path));
+                    Ok((content_type, self.file_system.read(&path)?))
+                });
+
+            match content {
+                Ok(content) => {
+                    pages.insert(code.clone(), content);
+                }
+                Err(e) => warn!(
+                    "Error page {} for {code} cannot be read, using the built-in one: {e}",
+                    page.display()
+                ),
+            }
+        }
+        pages
+    }
+
+    /// Takes a token from the client's bucket when `rate_limit` is set. A
+    /// client whose address is unknown is not limited.
+    fn take_request_token(&self, connection: &ConnectionInfo) -> Result<(), Duration> {
+        match (&self.rate_limiter, connection.client_ip()) {
+            (Some(rate_limiter), Some(ip)) => rate_limiter.check(ip, Instant::now()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Answers a client over its rate limit, telling it in whole seconds when
+    /// to try again
+    fn write_too_many_requests<S: Write>(
+        &self,
+        stream: &mut S,
+        retry_after: Duration,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        let headers = format!("{HEADER_CONTENT_TYPE}Retry-After: {retry_after}{NEW_LINE}");
+        self.write_response(
+            stream,
+            "429 Too Many Requests",
+            &headers,
+            TOO_MANY_REQUESTS_BODY.as_bytes(),
+            reply,
+        )
+    }
+
+    /// Answers a request that could not be read with the status mapped by
+    /// [parse_error_status] and closes the connection, as the rest of the
+    /// stream cannot be trusted to start with the next request
+    fn write_parse_error<S: Write>(
+        &self,
+        stream: &mut S,
+        error: ParseError,
+    ) -> Result<(), ServerError> {
+        let Some(status) = parse_error_status(&error) else {
+            return Err(error.into());
+        };
+
+        info!("Answering {status} to a malformed request: {error}");
+        let (mut headers, body) = self.error_page(status).unwrap_or_else(|| {
+            let body = format!(
+                "<html><body><h1>{status}</h1><p>{}</p></body></html>",
+                listing::escape_html(&error.to_string())
+            );
+            (HEADER_CONTENT_TYPE.to_string(), body.into_bytes())
+        });
+        if let ParseError::UnsupportedMethod(_) = error {
+            headers.push_str(&format!("Allow: {ALLOWED_METHODS}{NEW_LINE}"));
+        }
+        self.write_response(stream, status, &headers, &body, Reply::last())
+    }
+
+    /// Answers a request whose resource could not be resolved: a 404 for a
+    /// missing file or a path that may not be served, a 403 when the file may
+    /// not be read. Other failures are passed on.
+    fn write_resolve_error<S: Write>(
+        &self,
+        stream: &mut S,
+        error: ResolveError,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let (status, body) = match &error {
+            ResolveError::NotFound(_)
+            | ResolveError::PathOutsideDocumentRoot(_)
+            | ResolveError::HttpPathError(ParseError::InvalidPath(_)) => {
+                ("404 Not Found", NOT_FOUND_BODY)
+            }
+            ResolveError::PermissionDenied(_) | ResolveError::TooManySymlinks(..) => {
+                ("403 Forbidden", FORBIDDEN_BODY)
+            }
+            ResolveError::PathShouldStartWithSlash(_) => ("400 Bad Request", BAD_REQUEST_BODY),
+            _ => return Err(error.into()),
+        };
+
+        debug!("Answering {status}: {error}");
+        self.write_error_response(stream, status, body, reply)
+    }
+
+    /// Whether `strict_accept` is on and the client's `Accept` header rules out
+    /// `content_type`
+    fn refuses(&self, request: &HttpRequest, content_type: &str) -> bool {
+        self.config.strict_accept
+            && request
+                .headers
+                .get("accept")
+                .is_some_and(|accept| mime::accept_quality(accept, content_type) == 0.0)
+    }
+
+    /// The 406 depends on the client's `Accept` as much as the response it
+    /// stands in for, so it carries the same `Vary`
+    fn write_not_acceptable<S: Write>(
+        &self,
+        stream: &mut S,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let status = "406 Not Acceptable";
+        let (content_type, body) = self.error_page(status).unwrap_or_else(|| {
+            (
+                HEADER_CONTENT_TYPE.to_string(),
+                NOT_ACCEPTABLE_BODY.as_bytes().to_vec(),
+            )
+        });
+        let headers = format!("{content_type}Vary: Accept{NEW_LINE}");
+
+        self.write_response(stream, status, &headers, &body, reply)
+    }
+
+    /// The `Vary` header line telling caches what the response depends on:
+    /// `Accept` when `strict_accept` may refuse it, `Accept-Encoding` when
+    /// `encoding` was negotiated and `Cookie` when the variant cookie picks
+    /// the file. Empty when none applies.
+    fn vary_header(&self, encoding: Encoding) -> String {
+        let mut varies = Vec::new();
+        if self.config.strict_accept {
+            varies.push("Accept");
+        }
+        if encoding.negotiated() {
+            varies.push("Accept-Encoding");
+        }
+        // Also without the cookie or with a file missing from the variant, as
+        // another cookie value would get another response
+        if self.config.variant_cookie.is_some() && !self.variant_resolvers.is_empty() {
+            varies.push("Cookie");
+        }
+
+        if varies.is_empty() {
+            String::new()
+        } else {
+            format!("Vary: {}{NEW_LINE}", varies.join(", "))
+        }
+    }
+
+    /// Content type of the file at `path`, from its extension or, for a file
+    /// without one, the configured `extensionless_content_type`
+    fn content_type_for<'a>(&'a self, path: &Path) -> &'a str {
+        match path.extension() {
+            Some(_) => mime::mime_for_path(path),
+            None => &self.config.extensionless_content_type,
+        }
+    }
+
+    /// `content` with its environment placeholders substituted, `None` when
+    /// it is not text or holds none
+    fn render_env(&self, content_type: &str, content: &[u8]) -> Option<Vec<u8>> {
+        let template = self.env_template.as_ref()?;
+        if !mime::is_text(content_type) {
+            return None;
+        }
+
+        template.render(content)
+    }
+
+    /// The registered transformation for files with the extension of `path`
+    fn transform_for(&self, path: &Path) -> Option<&Transform> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        self.transforms.get(&extension)
+    }
+
+    /// Returns the configured robots.txt body when it is what `request` asks for
+    fn robots_txt_for(&self, request: &HttpRequest) -> Option<&str> {
+        if request.headers.resource != "/robots.txt" {
+            return None;
+        }
+        self.config.robots_txt.as_deref()
+    }
+
+    /// Returns the sitemap generator when it is what `request` asks for
+    fn sitemap_for(&self, request: &HttpRequest) -> Option<&Sitemap> {
+        if request.headers.resource != "/sitemap.xml" {
+            return None;
+        }
+        self.sitemap.as_ref()
+    }
+
+    /// Returns the form handler configured for a POST to `resource`
+    fn form_for(&self, request: &HttpRequest) -> Option<&FormHandler> {
+        if request.headers.method != Method::Post {
+            return None;
+        }
+
+        self.config
+            .forms
+            .iter()
+            .find(|form| form.path == request.headers.resource)
+    }
+
+    /// Refuses a `CONNECT` request with the configured [ConnectResponse]
+    fn refuse_connect<S: Write>(&self, stream: &mut S, reply: Reply) -> Result<(), ServerError> {
+        match self.config.connect_response {
+            ConnectResponse::MethodNotAllowed => self.write_response(
+                stream,
+                "405 Method Not Allowed",
+                &format!("{HEADER_CONTENT_TYPE}Allow: {ALLOWED_METHODS}{NEW_LINE}"),
+                METHOD_NOT_ALLOWED_BODY.as_bytes(),
+                reply,
+            ),
+            ConnectResponse::NotImplemented => self.write_error_response(
+                stream,
+                "501 Not Implemented",
+                NOT_IMPLEMENTED_BODY,
+                reply,
+            ),
+        }
+    }
+
+    /// Handles a request other than GET targeting a directory, which is
+    /// ambiguous: it goes to the `directory_post_form` when one is configured
+    /// and is refused with a 405 otherwise
+    fn handle_directory_write<S: Write>(
+        &self,
+        stream: &mut S,
+        request: &HttpRequest,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let form = self
+            .config
+            .directory_post_form
+            .as_ref()
+            .and_then(|path| self.config.forms.iter().find(|form| &form.path == path));
+
+        match form {
+            Some(form) => self.handle_form(stream, form, request, reply),
+            None => self.write_response(
+                stream,
+                "405 Method Not Allowed",
+                &format!("{HEADER_CONTENT_TYPE}Allow: GET, HEAD{NEW_LINE}"),
+                METHOD_NOT_ALLOWED_BODY.as_bytes(),
+                reply,
+            ),
+        }
+    }
+
+    /// Appends the submitted body to the form's submissions file and answers
+    /// with the configured redirect or success page
+    fn handle_form<S: Write>(
+        &self,
+        stream: &mut S,
+        form: &FormHandler,
+        request: &HttpRequest,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        if request.body.len() > form.max_body_bytes {
+            return self.write_error_response(
+                stream,
+                "413 Payload Too Large",
+                PAYLOAD_TOO_LARGE_BODY,
+                reply,
+            );
+        }
+
+        {
+            let _guard = self
+                .form_submissions
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            let mut submissions = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&form.submissions_file)?;
+
+            let mut submission = request.body.clone();
+            submission.push(b'\n');
+            submissions.write_all(&submission)?;
+        }
+
+        if let Some(location) = &form.success_redirect {
+            let location = format!("Location: {location}{NEW_LINE}");
+            return self.write_response(stream, "303 See Other", &location, b"", reply);
+        }
+
+        let body = match &form.success_page {
+            Some(page) => self.get_resource_content(&self.resolver.resolve(page.clone())?)?,
+            None => Arc::new(FORM_RECEIVED_BODY.as_bytes().to_vec()),
+        };
+
+        self.write_response(stream, "200 OK", HEADER_CONTENT_TYPE, &body, reply)
+    }
+
+    /// Content type header and body of the version or metrics endpoint, when
+    /// `request` is for one of them
+    fn admin_response(&self, request: &HttpRequest) -> Option<(&'static str, String)> {
+        let resource = Some(&request.headers.resource);
+
+        if self.config.version_endpoint.as_ref() == resource {
+            return Some((
+                HEADER_CONTENT_TYPE_JSON,
+                version::to_json(self.started.elapsed()),
+            ));
+        }
+        if self.config.metrics_endpoint.as_ref() == resource {
+            return Some((HEADER_CONTENT_TYPE_TEXT, self.metrics.render()));
+        }
+
+        None
+    }
+
+    /// Serves the file specified by the resource path back to the client,
+    /// whose address `connection` tells
+    fn serve_file<S: Write>(
+        &self,
+        stream: &mut S,
+        request: &HttpRequest,
+        connection: &ConnectionInfo,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        if !self.virtual_hosts.is_empty() && !self.admin_only {
+            let host = request.headers.host_name();
+            if let Some(host_server) = host.as_ref().and_then(|host| self.virtual_hosts.get(host)) {
+                return host_server.serve_file(stream, request, connection, reply);
+            }
+            if !self.config.virtual_host_fallback {
+                debug!("Rejecting request for unknown host {host:?}");
+                return self.write_error_response(
+                    stream,
+                    "400 Bad Request",
+                    BAD_REQUEST_BODY,
+                    reply,
+                );
+            }
+        }
+
+        debug!(
+            "{} {} from {connection}",
+            request.headers.method.as_str(),
+            request.headers.resource
+        );
+
+        if self.admin_only {
+            return match self.admin_response(request) {
+                Some((content_type, body)) => {
+                    self.write_response(stream, "200 OK", content_type, body.as_bytes(), reply)
+                }
+                None => self.write_error_response(stream, "404 Not Found", NOT_FOUND_BODY, reply),
+            };
+        }
+
+        if let Err(retry_after) = self.take_request_token(connection) {
+            return self.write_too_many_requests(stream, retry_after, reply);
+        }
+
+        if self.maintenance.load(Ordering::SeqCst) {
+            return self.write_error_response(
+                stream,
+                "503 Service Unavailable",
+                SERVICE_UNAVAILABLE_BODY,
+                reply,
+            );
+        }
+
+        if request.headers.method == Method::Connect {
+            return self.refuse_connect(stream, reply);
+        }
+
+        if self.config.require_host && request.headers.missing_host() {
+            debug!("Rejecting HTTP/1.1 request without Host");
+            return self.write_error_response(stream, "400 Bad Request", BAD_REQUEST_BODY, reply);
+        }
+
+        if let Some(expect) = request.headers.unsupported_expectation() {
+            debug!("Rejecting request with unsupported expectation {expect:?}");
+            return self.write_error_response(
+                stream,
+                "417 Expectation Failed",
+                EXPECTATION_FAILED_BODY,
+                reply,
+            );
+        }
+
+        if let Err(e) = request.headers.query_params(self.config.max_query_params) {
+            debug!("Rejecting request for {}: {e}", request.headers.resource);
+            return self.write_error_response(stream, "400 Bad Request", BAD_REQUEST_BODY, reply);
+        }
+
+        if let Some((rule, location)) = redirect::find(
+            &self.config.redirects,
+            request.headers.host_name().as_deref(),
+            &request.headers.resource,
+            request.headers.query.as_deref(),
+        ) {
+            let status = if rule.permanent {
+                "301 Moved Permanently"
+            } else {
+                "302 Found"
+            };
+            return self.write_response(
+                stream,
+                status,
+                &format!("Location: {location}{NEW_LINE}"),
+                b"",
+                reply,
+            );
+        }
+
+        // Relative links in the directory's index would otherwise resolve
+        // against its parent
+        if request.headers.method.is_retrieval()
+            && self
+                .resolver
+                .lacks_trailing_slash(&request.headers.resource)
+        {
+            let location = redirect::directory_location(
+                &request.headers.resource,
+                request.headers.query.as_deref(),
+            );
+            return self.write_response(
+                stream,
+                "301 Moved Permanently",
+                &format!("Location: {location}{NEW_LINE}"),
+                b"",
+                reply,
+            );
+        }
+
+        if let Some((content_type, body)) = self.admin_response(request) {
+            return self.write_response(stream, "200 OK", content_type, body.as_bytes(), reply);
+        }
+
+        if request.headers.method.is_retrieval() {
+            if let Some(robots_txt) = self.robots_txt_for(request) {
+                return self.write_response(
+                    stream,
+                    "200 OK",
+                    HEADER_CONTENT_TYPE_TEXT,
+                    robots_txt.as_bytes(),
+                    reply,
+                );
+            }
+
+            if let Some(sitemap) = self.sitemap_for(request) {
+                let sitemap = sitemap.get(&self.resolver)?;
+                return self.write_response(
+                    stream,
+                    "200 OK",
+                    HEADER_CONTENT_TYPE_XML,
+                    sitemap.as_bytes(),
+                    reply,
+                );
+            }
+        }
+
+        if let Some(form) = self.form_for(request) {
+            return self.handle_form(stream, form, request, reply);
+        }
+
+        if let Some(snapshot) = &self.snapshot {
+            let (path, resource_content) = match self.get_snapshot_content(snapshot, request) {
+                Ok(found) => found,
+                Err(e) => {
+                    debug!("{e}");
+                    return self.write_error_response(
+                        stream,
+                        "404 Not Found",
+                        NOT_FOUND_BODY,
+                        reply,
+                    );
+                }
+            };
+            let transformed;
+            let resource_content = match self.transform_for(path) {
+                Some(transform) => {
+                    transformed = transform(resource_content);
+                    &transformed
+                }
+                None => resource_content,
+            };
+            let content_type = self.content_type_for(path);
+            if self.refuses(request, content_type) {
+                return self.write_not_acceptable(stream, reply);
+            }
+            let rendered = self.render_env(content_type, resource_content);
+            let resource_content = rendered.as_deref().unwrap_or(resource_content);
+            let encoding = self.negotiate_encoding(request, content_type);
+            return self.write_file_response(
+                stream,
+                content_type,
+                resource_content,
+                None,
+                encoding,
+                None,
+                reply,
+            );
+        }
+
+        if !request.headers.method.is_retrieval()
+            && self
+                .resolver
+                .resolve_directory(&request.headers.resource)
+                .is_some()
+        {
+            return self.handle_directory_write(stream, request, reply);
+        }
+
+        let resource = match self.parse_request(request) {
+            Ok(ResolvedResource::File(path)) => path,
+            Ok(ResolvedResource::Content {
+                content_type,
+                content,
+            }) => {
+                let encoding = self.negotiate_encoding(request, &content_type);
+                return self.write_file_response(
+                    stream,
+                    &content_type,
+                    &content,
+                    None,
+                    encoding,
+                    None,
+                    reply,
+                );
+            }
+            Err(e) => return self.write_resolve_error(stream, e, reply),
+        };
+
+        if let Some(directory) = self.listing_directory(request, &resource) {
+            return self.serve_listing(stream, request, &directory, reply);
+        }
+
+        if !resource.is_file()
+            && self
+                .resolver
+                .resolve_directory(&request.headers.resource)
+                .is_some()
+        {
+            if self.config.empty_root_landing_page
+                && request.headers.resource.trim_start_matches('/').is_empty()
+                && self.resolver.is_empty()
+            {
+                return self.write_response(
+                    stream,
+                    "200 OK",
+                    HEADER_CONTENT_TYPE,
+                    LANDING_PAGE_BODY.as_bytes(),
+                    reply,
+                );
+            }
+
+            let (status, body) = match self.config.directory_no_index_status {
+                NoIndexStatus::NotFound => ("404 Not Found", NOT_FOUND_BODY),
+                NoIndexStatus::Forbidden => ("403 Forbidden", FORBIDDEN_BODY),
+            };
+            return self.write_error_response(stream, status, body, reply);
+        }
+
+        if self.refuses(request, self.content_type_for(&resource)) {
+            return self.write_not_acceptable(stream, reply);
+        }
+
+        if let Some(metadata) = self.streamable(request, &resource) {
+            return self.stream_file(stream, request, &resource, &metadata, reply);
+        }
+
+        let (resource_content, etag) =
+            match self.get_resource_content(&resource).and_then(|content| {
+                let etag = self.etag_for(&resource, &content)?;
+                Ok((content, etag))
+            }) {
+                Ok(found) => found,
+                Err(e) => return self.write_read_error(stream, &resource, e, reply),
+            };
+
+        let (resource_content, etag, source) = match self.transform_for(&resource) {
+            Some(transform) => (Arc::new(transform(&resource_content)), None, None),
+            None => (resource_content, etag, Some(resource.as_path())),
+        };
+
+        let content_type = self.content_type_for(&resource);
+
+        // The tag and digest of the file on disk do not describe the
+        // substituted content
+        let (resource_content, etag, source) =
+            match self.render_env(content_type, &resource_content) {
+                Some(rendered) => {
+                    let etag = etag.map(|_| etag::weak_from_content(&rendered));
+                    (Arc::new(rendered), etag, None)
+                }
+                None => (resource_content, etag, source),
+            };
+        let encoding = self.negotiate_encoding(request, content_type);
+        let identity_etag = etag;
+        let etag = identity_etag.clone().map(|etag| encoding.etag(etag));
+        let last_modified = source.and_then(|path| self.last_modified(path));
+
+        if not_modified(request, etag.as_deref(), last_modified) {
+            return self.write_not_modified(
+                stream,
+                etag.as_deref(),
+                last_modified,
+                encoding,
+                reply,
+            );
+        }
+
+        // Ranges are of the content as is, never of the gzipped body
+        let range = requested_range(
+            request,
+            identity_etag.as_deref(),
+            last_modified,
+            resource_content.len(),
+        );
+        if range != ByteRange::Ignored {
+            return self.write_partial_response(
+                stream,
+                content_type,
+                &resource_content,
+                range,
+                identity_etag.as_deref(),
+                last_modified,
+                encoding,
+                reply,
+            );
+        }
+
+        self.write_file_response(
+            stream,
+            content_type,
+            &resource_content,
+            source,
+            encoding,
+            etag.as_deref(),
+            reply,
+        )
+    }
+
+    /// Picks the content coding of a response from its content type and the
+    /// client's `Accept-Encoding`
+    fn negotiate_encoding(&self, request: &HttpRequest, content_type: &str) -> Encoding {
+        if !self.config.compression || !self.compression.is_compressible(content_type) {
+            return Encoding::Identity;
+        }
+
+        let accepts_gzip = request
+            .headers
+            .get("accept-encoding")
+            .is_some_and(compression::accepts_gzip);
+
+        if accepts_gzip {
+            Encoding::Gzip
+        } else {
+            Encoding::NegotiatedIdentity
+        }
+    }
+
+    /// Writes the generated listing of `directory`, or a 304 when the client's
+    /// cached copy still matches the directory's synthetic ETag. JSON or HTML,
+    /// as negotiated with the client's `Accept` header.
+    fn serve_listing<S: Write>(
+        &self,
+        stream: &mut S,
+        request: &HttpRequest,
+        directory: &Path,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let mut entries = listing::entries(directory)?;
+        // Entries that would not be served are not even named
+        entries.retain(|entry| self.resolver.permits_entry(&directory.join(&entry.name)));
+        let format = match ListingFormat::negotiate(
+            request.headers.get("accept"),
+            self.config.listing_format,
+        ) {
+            Some(format) => format,
+            None if self.config.strict_accept => return self.write_not_acceptable(stream, reply),
+            None => self.config.listing_format,
+        };
+
+        let etag =
+            (self.config.etag_mode != etag::EtagMode::Off).then(|| listing::etag(&entries, format));
+
+        if let (Some(etag), Some(if_none_match)) = (&etag, request.headers.get("if-none-match")) {
+            if etag::if_none_match(if_none_match, etag) {
+                return self.write_not_modified(
+                    stream,
+                    Some(etag),
+                    None,
+                    Encoding::Identity,
+                    reply,
+                );
+            }
+        }
+
+        let (page, content_type) = match format {
+            ListingFormat::Html => (
+                listing::render(
+                    &entries,
+                    &request.headers.resource,
+                    self.config.max_listing_entries,
+                ),
+                HEADER_CONTENT_TYPE,
+            ),
+            ListingFormat::Json => (
+                listing::render_json(&entries, self.config.max_listing_entries),
+                HEADER_CONTENT_TYPE_JSON,
+            ),
+        };
+
+        let mut headers = format!("{content_type}Vary: Accept{NEW_LINE}");
+        if let Some(etag) = etag {
+            headers.push_str(&format!("ETag: {etag}{NEW_LINE}"));
+        }
+
+        self.write_response(stream, "200 OK", &headers, page.as_bytes(), reply)
+    }
+
+    /// Computes the entity tag of a file according to the configured [etag::EtagMode]
+    fn etag_for(&self, resource: &HttpPath, content: &[u8]) -> std::io::Result<Option<String>> {
+        if self.config.etag_mode == etag::EtagMode::Off {
+            return Ok(None);
+        }
+
+        let metadata = self.file_system.metadata(resource)?;
+        Ok(etag::generate(
+            self.config.etag_mode,
+            &self.config.etag_salt,
+            &metadata,
+            content,
+        ))
+    }
+
+    /// Answers a request whose file could not be read because of `error`
+    fn write_read_error<S: Write>(
+        &self,
+        stream: &mut S,
+        resource: &Path,
+        error: std::io::Error,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        match error.kind() {
+            ErrorKind::NotFound => {
+                // The resolver saw the file, but it is gone by the time we
+                // open it, e.g. deleted by a concurrent deploy
+                debug!(
+                    "{} disappeared between resolution and open: {error}",
+                    resource.display()
+                );
+                self.write_error_response(stream, "404 Not Found", NOT_FOUND_BODY, reply)
+            }
+            ErrorKind::TimedOut => {
+                debug!("Reading {} timed out: {error}", resource.display());
+                self.write_error_response(
+                    stream,
+                    "503 Service Unavailable",
+                    SERVICE_UNAVAILABLE_BODY,
+                    reply,
+                )
+            }
+            _ => Err(error.into()),
+        }
+    }
+
+    /// Metadata of `resource` when it is at least `stream_threshold_bytes`
+    /// large and nothing about the response needs its whole content. A HEAD
+    /// response has no body to stream, its headers are taken from the
+    /// metadata whenever they can be, whatever the size. A gzipped HEAD needs
+    /// the length of the compressed body, so only gets here when it is cached.
+    fn streamable(&self, request: &HttpRequest, resource: &Path) -> Option<Metadata> {
+        let head = request.headers.method == Method::Head;
+        let content_type = self.content_type_for(resource);
+        let needs_content = self.config.etag_mode == etag::EtagMode::Strong
+            || self.config.digest_header
+            || self.transform_for(resource).is_some()
+            || (self.env_template.is_some() && mime::is_text(content_type));
+        if needs_content {
+            return None;
+        }
+
+        // Empty files are left to `empty_file_as_204`
+        let threshold = if head {
+            1
+        } else {
+            self.config.stream_threshold_bytes.max(1)
+        };
+        let metadata = self.file_system.metadata(resource).ok()?;
+        if metadata.len() < threshold {
+            return None;
+        }
+
+        if self.negotiate_encoding(request, content_type) == Encoding::Gzip
+            && (!head || self.cached_gzip(resource, &metadata).is_none())
+        {
+            return None;
+        }
+
+        Some(metadata)
+    }
+
+    /// The gzipped body of the file at `resource` from the compressed cache,
+    /// if cached since the file was last modified
+    fn cached_gzip(&self, resource: &Path, metadata: &Metadata) -> Option<Arc<Vec<u8>>> {
+        let cache = self.compressed_cache.as_ref()?;
+        cache.get(resource, metadata.modified().ok()?, Encoding::Gzip)
+    }
+
+    /// Sends the file at `resource`, or the part of it a `Range` asks for, as
+    /// it is read, one [STREAM_CHUNK_BYTES] chunk at a time, with the length
+    /// and validators taken from `metadata`.
+    ///
+    /// One of the `max_concurrent_reads` is only held while reading, not while
+    /// a slow client takes its time receiving a chunk.
+    fn stream_file<S: Write>(
+        &self,
+        stream: &mut S,
+        request: &HttpRequest,
+        resource: &Path,
+        metadata: &Metadata,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let content_type = self.content_type_for(resource);
+        let encoding = self.negotiate_encoding(request, content_type);
+        // Only weak tags get here, they are made from the metadata alone
+        let identity_etag =
+            etag::generate(self.config.etag_mode, &self.config.etag_salt, metadata, &[]);
+        let etag = identity_etag.clone().map(|etag| encoding.etag(etag));
+        let last_modified = metadata.modified().ok();
+
+        if not_modified(request, etag.as_deref(), last_modified) {
+            return self.write_not_modified(
+                stream,
+                etag.as_deref(),
+                last_modified,
+                encoding,
+                reply,
+            );
+        }
+
+        let file_len = metadata.len() as usize;
+        // Only a HEAD gets here gzipped, ranges are never of the gzipped body
+        let (status, headers, part, gzipped) =
+            match requested_range(request, identity_etag.as_deref(), last_modified, file_len) {
+                ByteRange::Unsatisfiable => {
+                    return self.write_range_not_satisfiable(stream, file_len, reply)
+                }
+                ByteRange::Satisfiable(range) => {
+                    let headers = self.partial_headers(
+                        content_type,
+                        &range,
+                        file_len,
+                        identity_etag.as_deref(),
+                        last_modified,
+                        encoding,
+                    );
+                    ("206 Partial Content", headers, range, false)
+                }
+                ByteRange::Ignored => {
+                    let mut headers = format!("Content-Type: {content_type}{NEW_LINE}");
+                    headers.push_str(&self.vary_header(encoding));
+                    let gzipped = encoding == Encoding::Gzip;
+                    if gzipped {
+                        headers.push_str(&format!("Content-Encoding: gzip{NEW_LINE}"));
+                    }
+                    if let Some(etag) = &etag {
+                        headers.push_str(&format!("ETag: {etag}{NEW_LINE}"));
+                    }
+                    if let Some(last_modified) = last_modified {
+                        headers.push_str(&last_modified_header(last_modified));
+                    }
+                    headers.push_str(&format!("Accept-Ranges: bytes{NEW_LINE}"));
+                    ("200 OK", headers, 0..file_len, gzipped)
+                }
+            };
+
+        let length = part.len() as u64;
+        if request.headers.method == Method::Head {
+            // The gzipped length is that of the cached body, compressed again
+            // like for a GET if evicted since `streamable` found it
+            let length = if gzipped {
+                match self.cached_gzip(resource, metadata) {
+                    Some(gzipped) => gzipped.len() as u64,
+                    None => {
+                        let content = {
+                            let _permit = self.read_slot()?;
+                            self.file_system.read(resource)?
+                        };
+                        self.gzip(&content, Some(resource))?.len() as u64
+                    }
+                }
+            } else {
+                length
+            };
+            return self.write_head(stream, status, &headers, Some(length), reply);
+        }
+
+        let file = {
+            let _permit = match self.read_slot() {
+                Ok(permit) => permit,
+                Err(e) => return self.write_read_error(stream, resource, e, reply),
+            };
+            match self.open_at(resource, part.start as u64) {
+                Ok(file) => file,
+                Err(e) => return self.write_read_error(stream, resource, e, reply),
+            }
+        };
+
+        self.write_head(stream, status, &headers, Some(length), reply)?;
+
+        // Whatever was appended since the metadata was taken is left out, as
+        // the announced length is already sent
+        let mut file = file.take(length);
+        let mut chunk = vec![0; STREAM_CHUNK_BYTES];
+        let mut sent = 0;
+        loop {
+            let result = {
+                let _permit = self.read_slot()?;
+                file.read(&mut chunk)
+            };
+            let read = match result {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.send(stream, &chunk[..read])?;
+            sent += read as u64;
+        }
+
+        if sent < length {
+            // Too late for an error response, the client sees the connection
+            // end before the announced length
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("{} shrank while being sent", resource.display()),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Opens the file at `path` to be read from `offset` on
+    fn open_at(&self, path: &Path, offset: u64) -> std::io::Result<Box<dyn SeekableRead>> {
+        let mut file = self.file_system.open(path)?;
+        if offset > 0 {
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(file)
+    }
+
+    /// Modification time of the file at `path`, `None` when the file system
+    /// does not record it
+    fn last_modified(&self, path: &Path) -> Option<SystemTime> {
+        self.file_system.metadata(path).ok()?.modified().ok()
+    }
+
+    /// Tells the client its cached copy, identified by `etag` or
+    /// `last_modified`, is still current. Carries the same `Vary` as the full
+    /// response would for `encoding`.
+    fn write_not_modified<S: Write>(
+        &self,
+        stream: &mut S,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+        encoding: Encoding,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let mut extra_headers = String::new();
+        if let Some(etag) = etag {
+            extra_headers.push_str(&format!("ETag: {etag}{NEW_LINE}"));
+        }
+        if let Some(last_modified) = last_modified {
+            extra_headers.push_str(&last_modified_header(last_modified));
+        }
+        extra_headers.push_str(&self.vary_header(encoding));
+
+        self.write_head(stream, "304 Not Modified", &extra_headers, None, reply)
+    }
+
+    /// Answers a `Range` request with the part of `content` in `range`, or a
+    /// 416 when it is unsatisfiable. `encoding` only decides `Vary`, a part is
+    /// never compressed.
+    #[allow(clippy::too_many_arguments)]
+    fn write_partial_response<S: Write>(
+        &self,
+        stream: &mut S,
+        content_type: &str,
+        content: &[u8],
+        range: ByteRange,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+        encoding: Encoding,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let ByteRange::Satisfiable(range) = range else {
+            return self.write_range_not_satisfiable(stream, content.len(), reply);
+        };
+
+        let headers = self.partial_headers(
+            content_type,
+            &range,
+            content.len(),
+            etag,
+            last_modified,
+            encoding,
+        );
+        self.write_response(
+            stream,
+            "206 Partial Content",
+            &headers,
+            &content[range],
+            reply,
+        )
+    }
+
+    /// Header lines of a 206 carrying `range` of a representation `len` bytes long
+    fn partial_headers(
+        &self,
+        content_type: &str,
+        range: &std::ops::Range<usize>,
+        len: usize,
+        etag: Option<&str>,
+        last_modified: Option<SystemTime>,
+        encoding: Encoding,
+    ) -> String {
+        let mut headers = format!(
+            "Content-Type: {content_type}{NEW_LINE}{}Content-Range: bytes {}-{}/{len}{NEW_LINE}",
+            self.vary_header(encoding),
+            range.start,
+            range.end - 1,
+        );
+        if let Some(etag) = etag {
+            headers.push_str(&format!("ETag: {etag}{NEW_LINE}"));
+        }
+        if let Some(last_modified) = last_modified {
+            headers.push_str(&last_modified_header(last_modified));
+        }
+        headers
+    }
+
+    /// Answers a range starting past the end of a representation `len` bytes long
+    fn write_range_not_satisfiable<S: Write>(
+        &self,
+        stream: &mut S,
+        len: usize,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        let headers = format!("{HEADER_CONTENT_TYPE}Content-Range: bytes */{len}{NEW_LINE}");
+        self.write_response(
+            stream,
+            "416 Range Not Satisfiable",
+            &headers,
+            RANGE_NOT_SATISFIABLE_BODY.as_bytes(),
+            reply,
+        )
+    }
+
+    /// Gzips `content`, the content of the file at `source` if any. With the
+    /// compressed cache on, a file is only compressed again once modified.
+    fn gzip(&self, content: &[u8], source: Option<&Path>) -> std::io::Result<Arc<Vec<u8>>> {
+        let (Some(cache), Some(path)) = (&self.compressed_cache, source) else {
+            return self.compressor.gzip(content).map(Arc::new);
+        };
+
+        let modified = self.file_system.metadata(path)?.modified()?;
+        cache.get_or_compress(path, modified, Encoding::Gzip, || {
+            self.compressor.gzip(content)
+        })
+    }
+
+    /// Writes a 200 response carrying the content of a served file, gzipped
+    /// when that is the negotiated `encoding`. `source` is the file on disk the
+    /// content was read from, if any, its digest is cached.
+    #[allow(clippy::too_many_arguments)]
+    fn write_file_response<S: Write>(
+        &self,
+        stream: &mut S,
+        content_type: &str,
+        resource_content: &[u8],
+        source: Option<&Path>,
+        encoding: Encoding,
+        etag: Option<&str>,
+        reply: Reply,
+    ) -> Result<(), ServerError> {
+        if resource_content.is_empty() && self.config.empty_file_as_204 {
+            let mut headers = self.vary_header(encoding);
+            if let Some(etag) = etag {
+                headers.push_str(&format!("ETag: {etag}{NEW_LINE}"));
+            }
+            return self.write_response(stream, "204 No Content", &headers, b"", reply);
+        }
+
+        // A failed compression is no reason to fail the request, the content
+        // is sent as is. Without a tag, as `etag` is that of the gzipped body.
+        let mut compressed = None;
+        let (encoding, etag) = match encoding {
+            Encoding::Gzip => match self.gzip(resource_content, source) {
+                Ok(gzipped) => {
+                    compressed = Some(gzipped);
+                    (encoding, etag)
+                }
+                Err(e) => {
+                    warn!("Sending the response uncompressed, gzip failed: {e}");
+                    (Encoding::NegotiatedIdentity, None)
+                }
+            },
+            _ => (encoding, etag),
+        };
+
+        let mut headers = format!("Content-Type: {content_type}{NEW_LINE}");
+        headers.push_str(&self.vary_header(encoding));
+
+        let body = match &compressed {
+            Some(gzipped) => {
+                headers.push_str(&format!("Content-Encoding: gzip{NEW_LINE}"));
+                gzipped
+            }
+            None => resource_content,
+        };
+
+        if let Some(etag) = etag {
+            headers.push_str(&format!("ETag: {etag}{NEW_LINE}"));
+        }
+        if let Some(last_modified) = source.and_then(|path| self.last_modified(path)) {
+            headers.push_str(&last_modified_header(last_modified));
+        }
+        headers.push_str(&format!("Accept-Ranges: bytes{NEW_LINE}"));
+
+        if self.config.digest_header {
+            let gzipped = encoding == Encoding::Gzip;
+            let digest = match source {
+                Some(path) => {
+                    let modified = self.file_system.metadata(path)?.modified()?;
+                    self.digests.get(path, modified, gzipped, body)
+                }
+                None => digest::sha256(body),
+            };
+            headers.push_str(&format!("Digest: {digest}{NEW_LINE}"));
+        }
+
+        self.write_response(stream, "200 OK", &headers, body, reply)
+    }
+}
+
+/// Stops sending on `stream` and drops what the client still sends, until it
+/// closes its side or [DRAIN_TIMEOUT] passed. Closing a socket with unread data
+/// resets the connection, which can destroy a response the client has not read yet.
+fn drain(mut stream: &TcpStream) {
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    if stream.shutdown(Shutdown::Write).is_err() {
+        return;
+    }
+
+    let mut buffer = [0; 4096];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        if left.is_zero() || stream.set_read_timeout(Some(left)).is_err() {
+            return;
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// Status answering a request that could not be read because of `error`,
+/// `None` when the connection itself failed and nothing can be answered
+fn parse_error_status(error: &ParseError) -> Option<&'static str> {
+    match error {
+        ParseError::IO(_) => None,
+        ParseError::BodyTooLarge(..) => Some("413 Payload Too Large"),
+        ParseError::HeadersTooLarge(_) => Some("431 Request Header Fields Too Large"),
+        ParseError::RequestTimeout(_) => Some("408 Request Timeout"),
+        ParseError::UnsupportedMethod(_) => Some("405 Method Not Allowed"),
+        ParseError::UnsupportedVersion(_) => Some("505 HTTP Version Not Supported"),
+        ParseError::EmptyRequest
+        | ParseError::InvalidHeaders
+        | ParseError::InvalidMethod(_)
+        | ParseError::InvalidVersion(_)
+        | ParseError::InvalidPath(_)
+        | ParseError::InvalidHeaderLine(_)
+        | ParseError::InvalidContentLength(_)
+        | ParseError::TooManyQueryParams(_)
+        | ParseError::InvalidRequestLine(_)
+        | ParseError::InvalidPercentEncoding(_)
+        | ParseError::RequestLineTooLong(_)
+        | ParseError::RequestLineTimeout(_) => Some("400 Bad Request"),
+    }
+}
+
+/// Whether the client's cached copy is current, going by `If-None-Match` when
+/// sent and by `If-Modified-Since` otherwise (RFC 9110 section 13.2.2). A
+/// malformed date makes the request unconditional.
+fn not_modified(
+    request: &HttpRequest,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    if let Some(if_none_match) = request.headers.get("if-none-match") {
+        return etag.is_some_and(|etag| etag::if_none_match(if_none_match, etag));
+    }
+
+    let since = request
+        .headers
+        .get("if-modified-since")
+        .and_then(http_date::parse);
+    match (last_modified, since) {
+        // Dates only have a precision of seconds
+        (Some(modified), Some(since)) => {
+            let seconds = |time: SystemTime| {
+                time.duration_since(UNIX_EPOCH)
+                    .map_or(0, |since_epoch| since_epoch.as_secs())
+            };
+            seconds(modified) <= seconds(since)
+        }
+        _ => false,
+    }
+}
+
+/// The part of a representation `len` bytes long the request's `Range`
+/// asks for. Ranges are only defined for GET, and only apply while `If-Range`
+/// matches the current `etag` or `last_modified`.
+fn requested_range(
+    request: &HttpRequest,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+    len: usize,
+) -> ByteRange {
+    request
+        .headers
+        .get("range")
+        .filter(|_| request.headers.method == Method::Get)
+        .filter(|_| if_range(request, etag, last_modified))
+        .map_or(ByteRange::Ignored, |range| range::parse(range, len))
+}
+
+/// Whether a `Range` request may be answered with a part: without `If-Range`,
+/// or when it names the current strong `etag` or exact `last_modified` date.
+/// Otherwise the client's part is outdated and the whole content is sent.
+fn if_range(request: &HttpRequest, etag: Option<&str>, last_modified: Option<SystemTime>) -> bool {
+    let Some(if_range) = request.headers.get("if-range") else {
+        return true;
+    };
+
+    if if_range.starts_with('"') {
+        return etag.is_some_and(|etag| etag == if_range);
+    }
+    last_modified.is_some_and(|modified| http_date::format(modified) == if_range)
+}
+
+fn last_modified_header(last_modified: SystemTime) -> String {
+    format!(
+        "Last-Modified: {}{NEW_LINE}",
+        http_date::format(last_modified)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConcurrencyModel, RateLimit, Redirect};
+    use crate::http::*;
+
+    use std::io::Cursor;
+    use std::net::SocketAddr;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Records the warnings logged by the server, see [logged_warnings]
+    struct WarningLogger;
+
+    thread_local! {
+        /// Per thread, as every test runs on a thread of its own and must not
+        /// see the warnings of the others
+        static WARNINGS: std::cell::RefCell<Vec<String>> = const {
+            std::cell::RefCell::new(Vec::new())
+        };
+    }
+
+    impl log::Log for WarningLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with_borrow_mut(|warnings| warnings.push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Installs [WarningLogger], once for the whole test binary, and returns
+    /// the warnings the calling test logged so far
+    fn logged_warnings() -> Vec<String> {
+        if log::set_logger(&WarningLogger).is_ok() {
+            log::set_max_level(log::LevelFilter::Warn);
+        }
+        WARNINGS.with_borrow(Vec::clone)
+    }
+
+    /// In-memory connection: reads come from `input`, writes land in `output`
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &str) -> Self {
+            MockStream {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: Vec::new(),
+            }
+        }
+
+        fn output(&self) -> String {
+            String::from_utf8_lossy(&self.output).to_string()
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl ReadTimeout for MockStream {
+        fn set_read_timeout(&self, _timeout: Duration) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Hands out `input` one byte per read, pausing before each byte, and then
+    /// keeps sending `filler` forever, like a client dribbling a request line
+    struct DribblingStream {
+        input: Cursor<Vec<u8>>,
+        filler: u8,
+        pause: Duration,
+        bytes_read: usize,
+        output: Vec<u8>,
+    }
+
+    impl Read for DribblingStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            thread::sleep(self.pause);
+            self.bytes_read += 1;
+            if self.input.read(&mut buf[..1])? == 0 {
+                buf[0] = self.filler;
+            }
+            Ok(1)
+        }
+    }
+
+    impl ReadTimeout for DribblingStream {
+        fn set_read_timeout(&self, _timeout: Duration) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for DribblingStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_parse_headers_fail() {
+        assert!(Headers::new(vec![]).is_err());
+        assert!(Headers::new(vec!["GET", "/"]).is_err());
+        assert!(Headers::new(vec!["GWET", "/", "HTTP/1.1"]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_headers() {
+        let header = Headers::new(vec!["GET", "/", "HTTP/1.1"]);
+        println!("{header:?}");
+
+        assert!(Headers::new(vec!["GET", "/", "HTTP/1.1"]).is_ok());
+        assert!(Headers::new(vec!["POST", "/", "HTTP/1.1"]).is_ok());
+        assert!(Headers::new(vec!["GET", "/foo", "HTTP/1.1"]).is_ok());
+    }
+
+    #[test]
+    pub fn try_from_for_http_path() {
+        let document_root = std::env::current_dir().unwrap().join("web_resources");
+        let resolver = Resolver::new(fs::canonicalize(&document_root).unwrap());
+
+        let path = resolver.resolve("/index.html".to_string());
+        assert!(path.is_ok());
+        assert_eq!(
+            path.unwrap().as_path(),
+            Path::new(document_root.join("index.html").to_str().unwrap())
+        );
+
+        let path = resolver.resolve("/".to_string());
+        assert!(path.is_ok());
+        assert_eq!(
+            path.unwrap().as_path(),
+            Path::new(document_root.join("index.html").to_str().unwrap())
+        );
+
+        let path = resolver.resolve("/foo".to_string());
+        assert!(path.is_ok());
+        assert_eq!(
+            path.unwrap().as_path(),
+            Path::new(document_root.join("foo/index.html").to_str().unwrap())
+        );
+
+        let path = resolver.resolve("/foo/".to_string());
+        assert!(path.is_ok());
+        assert_eq!(
+            path.unwrap().as_path(),
+            Path::new(document_root.join("foo/index.html").to_str().unwrap())
+        );
+
+        let path = resolver.resolve("/foo/bar".to_string());
+        println!("{:?}", path);
+        assert!(path.is_ok());
+        assert_eq!(
+            path.unwrap().as_path(),
+            Path::new(document_root.join("foo/bar/index.html").to_str().unwrap())
+        );
+
+        let path = resolver.resolve("".to_string());
+        assert!(path.is_err());
+
+        let path = resolver.resolve("../index.html".to_string());
+        assert!(path.is_err());
+
+        let path = resolver.resolve("/../index.html".to_string());
+        assert!(path.is_err());
+
+        let path = resolver.resolve("foo".to_string());
+        assert!(path.is_err());
+    }
+
+    /// Creates an empty directory under the system temp dir, unique per test
+    fn temp_document_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("turbine-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    pub fn keep_alive_header_matches_config() {
+        let config = Config {
+            keep_alive_timeout_secs: 7,
+            keep_alive_max_requests: 3,
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream =
+            MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 3);
+        assert!(output.contains("Connection: keep-alive\r\nKeep-Alive: timeout=7, max=2\r\n"));
+        assert!(output.contains("Connection: keep-alive\r\nKeep-Alive: timeout=7, max=1\r\n"));
+        assert_eq!(output.matches("Connection: close\r\n").count(), 1);
+    }
+
+    #[test]
+    pub fn memory_snapshot_ignores_later_disk_changes() {
+        let root = temp_document_root("snapshot");
+        fs::write(root.join("index.html"), "original").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            memory_snapshot: true,
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        fs::write(root.join("index.html"), "modified").unwrap();
+        fs::write(root.join("new.html"), "new").unwrap();
+
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().contains("original"));
+        assert!(!stream.output().contains("modified"));
+
+        let mut stream = MockStream::new("GET /new.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn memory_snapshot_respects_size_limit() {
+        let root = temp_document_root("snapshot-limit");
+        fs::write(root.join("index.html"), "0123456789").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            memory_snapshot: true,
+            memory_snapshot_max_bytes: 5,
+            ..Config::default()
+        };
+        assert!(matches!(
+            Server::new(config),
+            Err(ServerError::Snapshot(SnapshotError::TooLarge(5)))
+        ));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn listing_only_under_configured_paths() {
+        let root = temp_document_root("listing-paths");
+        fs::create_dir_all(root.join("downloads/nested")).unwrap();
+        fs::create_dir_all(root.join("private")).unwrap();
+        fs::write(root.join("downloads/release.tar.gz"), "release").unwrap();
+        fs::write(root.join("downloads/nested/notes.txt"), "notes").unwrap();
+        fs::write(root.join("private/secret.txt"), "secret").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            listing_paths: vec!["/downloads/".to_string()],
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream = MockStream::new("GET /downloads/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().contains("HTTP/1.1 200 OK"));
+        assert!(stream.output().contains("release.tar.gz"));
+
+        let mut stream =
+            MockStream::new("GET /downloads/nested/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().contains("notes.txt"));
+
+        let mut stream = MockStream::new("GET /private/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(!stream.output().contains("secret.txt"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Shim simulating a file deleted after the resolver has seen it
+    struct VanishingFileSystem;
+
+    impl FileSystem for VanishingFileSystem {
+        fn read(&self, _path: &Path) -> std::io::Result<Vec<u8>> {
+            Err(std::io::Error::from(ErrorKind::NotFound))
+        }
+
+        fn open(&self, _path: &Path) -> std::io::Result<Box<dyn SeekableRead>> {
+            Err(std::io::Error::from(ErrorKind::NotFound))
+        }
+
+        fn metadata(&self, _path: &Path) -> std::io::Result<std::fs::Metadata> {
+            Err(std::io::Error::from(ErrorKind::NotFound))
+        }
+    }
+
+    #[test]
+    pub fn file_vanishing_after_resolution_is_not_found() {
+        let server = Server::new(Config::default())
+            .unwrap()
+            .with_file_system(VanishingFileSystem);
+
+        let mut stream = MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(output.contains(&format!("Content-Length: {}\r\n", NOT_FOUND_BODY.len())));
+        assert!(output.ends_with(NOT_FOUND_BODY));
+    }
+
+    #[test]
+    pub fn form_submission_is_persisted() {
+        let root = temp_document_root("forms");
+        fs::write(root.join("thanks.html"), "thanks!").unwrap();
+        let submissions_file = root.join("submissions.log");
+
+        let form = FormHandler {
+            path: "/contact".to_string(),
+            submissions_file: submissions_file.clone(),
+            max_body_bytes: 64,
+            success_redirect: None,
+            success_page: Some("/thanks.html".to_string()),
+        };
+        let config = Config {
+            document_root: root.clone(),
+            forms: vec![form.clone()],
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let body = "name=Ada&message=hello+there";
+        let mut stream = MockStream::new(&format!(
+            "POST /contact HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        ));
+        server.handle_connection(&mut stream).unwrap();
+
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(stream.output().ends_with("thanks!"));
+        assert_eq!(
+            fs::read_to_string(&submissions_file).unwrap(),
+            format!("{body}\n")
+        );
+
+        let config = Config {
+            document_root: root.clone(),
+            forms: vec![FormHandler {
+                success_redirect: Some("/thanks.html".to_string()),
+                ..form
+            }],
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let oversized = "x".repeat(65);
+        let mut stream = MockStream::new(&format!(
+            "POST /contact HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhelloPOST /contact HTTP/1.1\r\nHost: localhost\r\nContent-Length: 65\r\n\r\n{oversized}"
+        ));
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 303 See Other\r\nLocation: /thanks.html\r\n"));
+        assert!(output.contains("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert_eq!(
+            fs::read_to_string(&submissions_file).unwrap(),
+            format!("{body}\nhello\n")
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Extracts the value of the first `name` header from a raw response
+    fn response_header<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+        let head = response.split("\r\n\r\n").next()?;
+        head.lines()
+            .find_map(|line| line.strip_prefix(&format!("{name}: ")))
+    }
+
+    #[test]
+    pub fn etag_modes_and_if_none_match() {
+        let request = "GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        for mode in [etag::EtagMode::Weak, etag::EtagMode::Strong] {
+            let config = Config {
+                etag_mode: mode,
+                ..Config::default()
+            };
+            let server = Server::new(config).unwrap();
+
+            let mut stream = MockStream::new(request);
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            let etag = response_header(&output, "ETag").unwrap().to_string();
+            assert_eq!(etag.starts_with("W/"), mode == etag::EtagMode::Weak);
+
+            // Stable while the file is unchanged
+            let mut stream = MockStream::new(request);
+            server.handle_connection(&mut stream).unwrap();
+            assert_eq!(
+                response_header(&stream.output(), "ETag"),
+                Some(etag.as_str())
+            );
+
+            // Both a weak and a strong form of the tag match If-None-Match
+            let opaque = etag.trim_start_matches("W/");
+            for candidate in [opaque.to_string(), format!("W/{opaque}")] {
+                let mut stream = MockStream::new(&format!(
+                    "GET /index.html HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {candidate}\r\n\r\n"
+                ));
+                server.handle_connection(&mut stream).unwrap();
+                let output = stream.output();
+                assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+                assert_eq!(response_header(&output, "ETag"), Some(etag.as_str()));
+                assert!(output.ends_with("\r\n\r\n"));
+            }
+
+            let mut stream = MockStream::new(
+                "GET /index.html HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: \"stale\"\r\n\r\n",
+            );
+            server.handle_connection(&mut stream).unwrap();
+            assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+
+        let config = Config {
+            etag_mode: etag::EtagMode::Off,
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+        let mut stream = MockStream::new(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: *\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response_header(&stream.output(), "ETag"), None);
+    }
+
+    #[test]
+    pub fn document_root_loss_triggers_configured_reaction() {
+        let root = temp_document_root("watchdog");
+        fs::write(root.join("index.html"), "up").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            ..Config::default()
+        };
+        let maintenance = Server::new(config.clone()).unwrap();
+        let shutdown = Server::new(Config {
+            document_root_lost: DocumentRootLost::Shutdown,
+            ..config
+        })
+        .unwrap();
+
+        assert!(maintenance.check_document_root());
+        assert!(shutdown.check_document_root());
+
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(maintenance.check_document_root());
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        maintenance.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        assert!(!shutdown.check_document_root());
+
+        // Maintenance mode ends once the document root comes back
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.html"), "up").unwrap();
+        assert!(maintenance.check_document_root());
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        maintenance.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn listing_conditional_requests() {
+        let root = temp_document_root("listing-etag");
+        fs::create_dir_all(root.join("downloads")).unwrap();
+        fs::write(root.join("downloads/a.txt"), "a").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            listing_paths: vec!["/downloads/".to_string()],
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream = MockStream::new("GET /downloads/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        let etag = response_header(&output, "ETag").unwrap().to_string();
+
+        let conditional =
+            format!("GET /downloads/ HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {etag}\r\n\r\n");
+
+        let mut stream = MockStream::new(&conditional);
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        // Answered in the version asked in
+        let mut stream = MockStream::new(&conditional.replacen("HTTP/1.1", "HTTP/1.0", 1));
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.0 304 Not Modified\r\n"));
+
+        fs::write(root.join("downloads/b.txt"), "b").unwrap();
+
+        let mut stream = MockStream::new(&conditional);
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("b.txt"));
+        assert_ne!(response_header(&output, "ETag"), Some(etag.as_str()));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn version_endpoint_reports_version() {
+        let config = Config {
+            version_endpoint: Some("/_turbine/version".to_string()),
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream =
+            MockStream::new("GET /_turbine/version HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            response_header(&output, "Content-Type"),
+            Some("application/json")
+        );
+        assert!(output.contains(&format!("\"version\":\"{}\"", env!("CARGO_PKG_VERSION"))));
+        assert!(output.contains(&format!("\"git_hash\":\"{}\"", version::GIT_HASH)));
+        assert!(output.contains("\"uptime_secs\":"));
+
+        // Without the endpoint configured the path is resolved like any other
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream =
+            MockStream::new("GET /_turbine/version HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    pub fn each_concurrency_model_serves_requests() {
+        for model in [
+            ConcurrencyModel::ThreadPool { size: 2 },
+            ConcurrencyModel::ElasticPool {
+                min: 0,
+                max: 2,
+                idle_timeout_secs: 1,
+            },
+            ConcurrencyModel::ThreadPerConnection,
+        ] {
+            let config = Config {
+                concurrency_model: model,
+                ..Config::default()
+            };
+            let server = Server::new(config).unwrap();
+
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            thread::spawn(move || server.serve(listener));
+
+            let mut client = TcpStream::connect(address).unwrap();
+            client
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            client.shutdown(std::net::Shutdown::Write).unwrap();
+
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{model:?}");
+        }
+    }
+
+    #[test]
+    pub fn saturated_pool_sheds_connections() {
+        let config = Config {
+            concurrency_model: ConcurrencyModel::ThreadPool { size: 1 },
+            max_queued_connections: Some(1),
+            ..Config::default()
+        };
+        let server = Arc::new(Server::new(config).unwrap());
+        let pool = server.worker_pool().unwrap();
+
+        // One job occupies the only worker and another waits in the queue
+        // until the sender is dropped
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for _ in 0..2 {
+            let released = Arc::clone(&released);
+            pool.execute(move || {
+                let _ = released.lock().unwrap().recv();
+            });
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let accepting = pool.clone();
+        thread::spawn(move || server.accept(listener, Some(accepting)));
+
+        let connect = || {
+            let client = TcpStream::connect(address).unwrap();
+            // Only there to fail rather than hang should the connection queue
+            client
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            client
+        };
+
+        // The unread request does not reset the connection before the 503
+        let mut shed = connect();
+        shed.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        shed.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        drop(release);
+        pool.join();
+        let mut served = connect();
+        served
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        served.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    /// Splits a raw response into its head and body
+    fn split_response(output: &[u8]) -> (String, &[u8]) {
+        let end = output
+            .windows(4)
+            .position(|window| window == END_OF_CONTENT.as_bytes())
+            .unwrap();
+        (
+            String::from_utf8_lossy(&output[..end]).to_string(),
+            &output[end + 4..],
+        )
+    }
+
+    #[test]
+    pub fn compressible_types_are_configurable() {
+        let root = temp_document_root("compressible-types");
+        let svg = "<svg xmlns=\"http://www.w3.org/2000/svg\"></svg>".repeat(20);
+        let csv = "a,b,c\n".repeat(50);
+        fs::write(root.join("logo.svg"), &svg).unwrap();
+        fs::write(root.join("data.txt"), &csv).unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            compressible_types: vec!["image/svg+xml".to_string()],
+            incompressible_types: vec!["text/plain".to_string()],
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream = MockStream::new(
+            "GET /logo.svg HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip, deflate\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        assert_eq!(response_header(&head, "Content-Encoding"), Some("gzip"));
+        assert_eq!(response_header(&head, "Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            response_header(&head, "Content-Length"),
+            Some(body.len().to_string().as_str())
+        );
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(body)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, svg);
+
+        let mut stream = MockStream::new(
+            "GET /data.txt HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        assert_eq!(response_header(&head, "Content-Encoding"), None);
+        assert_eq!(response_header(&head, "Vary"), None);
+        assert_eq!(body, csv.as_bytes());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn text_assets_are_gzipped_only_when_accepted() {
+        let root = temp_document_root("gzip-text-assets");
+        let text = "body { margin: 0 }\n".repeat(50);
+        for name in ["page.html", "style.css", "app.js", "data.json", "notes.txt"] {
+            fs::write(root.join(name), &text).unwrap();
+        }
+        fs::write(root.join("image.png"), &text).unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        let get = |path: &str, accept_encoding: &str| {
+            let mut stream = MockStream::new(&format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\n{accept_encoding}\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            let (head, body) = split_response(&stream.output);
+            (head, body.to_vec())
+        };
+
+        for path in [
+            "/page.html",
+            "/style.css",
+            "/app.js",
+            "/data.json",
+            "/notes.txt",
+        ] {
+            let (head, body) = get(path, "Accept-Encoding: gzip\r\n");
+            assert_eq!(
+                response_header(&head, "Content-Encoding"),
+                Some("gzip"),
+                "{path}"
+            );
+            assert_eq!(response_header(&head, "Vary"), Some("Accept-Encoding"));
+            assert_eq!(
+                response_header(&head, "Content-Length"),
+                Some(body.len().to_string().as_str())
+            );
+            assert!(body.len() < text.len());
+
+            let (head, body) = get(path, "");
+            assert_eq!(response_header(&head, "Content-Encoding"), None, "{path}");
+            assert_eq!(response_header(&head, "Vary"), Some("Accept-Encoding"));
+            assert_eq!(body, text.as_bytes());
+        }
+
+        // Not compressible, whatever the client accepts
+        let (head, body) = get("/image.png", "Accept-Encoding: gzip\r\n");
+        assert_eq!(response_header(&head, "Content-Encoding"), None);
+        assert_eq!(response_header(&head, "Vary"), None);
+        assert_eq!(body, text.as_bytes());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn gzip_etag_round_trip() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream = MockStream::new(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        let etag = response_header(&output, "ETag").unwrap().to_string();
+        assert!(etag.ends_with("-gzip\""));
+
+        let mut stream = MockStream::new(&format!(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nIf-None-Match: {etag}\r\n\r\n"
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        // The identity representation does not match the gzip tag
+        let mut stream = MockStream::new(&format!(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nIf-None-Match: {etag}\r\n\r\n"
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    pub fn too_many_query_params_is_bad_request() {
+        let config = Config {
+            max_query_params: 3,
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream =
+            MockStream::new("GET /index.html?a=1&b=2&c=3 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let mut stream =
+            MockStream::new("GET /index.html?a=1&b=2&c=3&d=4 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    pub fn query_string_is_split_from_path() {
+        let headers = Headers::new(vec!["GET", "/page.html?x=1&y=2&flag", "HTTP/1.1"]).unwrap();
+        assert_eq!(headers.resource, "/page.html");
+        assert_eq!(headers.query.as_deref(), Some("x=1&y=2&flag"));
+
+        let params = headers.query_params(10).unwrap();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["x"], "1");
+        assert_eq!(params["y"], "2");
+        assert_eq!(params["flag"], "");
+
+        assert!(matches!(
+            headers.query_params(2),
+            Err(ParseError::TooManyQueryParams(2))
+        ));
+
+        let headers = Headers::new(vec!["GET", "/?q=a+b%26c&%zz=1", "HTTP/1.1"]).unwrap();
+        let params = headers.query_params(10).unwrap();
+        assert_eq!(params["q"], "a b&c");
+        assert_eq!(params["%zz"], "1");
+
+        // Only the path is resolved
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream =
+            MockStream::new("GET /index.html?x=1&y=2 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with(&fs::read_to_string("web_resources/index.html").unwrap()));
+    }
+
+    #[test]
+    pub fn cookie_selects_variant() {
+        let root = temp_document_root("variants");
+        fs::create_dir_all(root.join("variants/b")).unwrap();
+        fs::write(root.join("index.html"), "default home").unwrap();
+        fs::write(root.join("about.html"), "default about").unwrap();
+        fs::write(root.join("variants/b/index.html"), "variant b home").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            variant_cookie: Some("variant".to_string()),
+            variants: HashMap::from([("b".to_string(), PathBuf::from("variants/b"))]),
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let get = |request: &str| {
+            let mut stream = MockStream::new(request);
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            // Whichever tree served it, caches must tell the variants apart
+            assert!(
+                response_header(&output, "Vary")
+                    .is_some_and(|vary| vary.split(", ").any(|header| header == "Cookie")),
+                "{request}"
+            );
+            output
+        };
+
+        let cookie = "Cookie: theme=dark; variant=b\r\n";
+        assert!(get(&format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n{cookie}\r\n"
+        ))
+        .ends_with("variant b home"));
+        // Files missing from the variant fall back to the default tree
+        assert!(get(&format!(
+            "GET /about.html HTTP/1.1\r\nHost: localhost\r\n{cookie}\r\n"
+        ))
+        .ends_with("default about"));
+
+        assert!(get("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").ends_with("default home"));
+        assert!(
+            get("GET / HTTP/1.1\r\nHost: localhost\r\nCookie: variant=c\r\n\r\n")
+                .ends_with("default home")
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn variant_must_stay_inside_document_root() {
+        let config = Config {
+            variants: HashMap::from([("b".to_string(), PathBuf::from(".."))]),
+            ..Config::default()
+        };
+        assert!(matches!(
+            Server::new(config),
+            Err(ServerError::VariantOutsideDocumentRoot(_))
+        ));
+    }
+
+    #[test]
+    pub fn post_to_directory() {
+        let request = "POST /foo/ HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\na=1";
+
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream = MockStream::new(request);
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert_eq!(response_header(&output, "Allow"), Some("GET, HEAD"));
+
+        // GET keeps serving the directory index
+        let mut stream = MockStream::new("GET /foo/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let root = temp_document_root("directory-post");
+        let submissions_file = root.join("submissions.log");
+        let config = Config {
+            forms: vec![FormHandler {
+                path: "/contact".to_string(),
+                submissions_file: submissions_file.clone(),
+                max_body_bytes: 64,
+                success_redirect: None,
+                success_page: None,
+            }],
+            directory_post_form: Some("/contact".to_string()),
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+        let mut stream = MockStream::new(request);
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(fs::read_to_string(&submissions_file).unwrap(), "a=1\n");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn incomplete_request_line_is_cut_short() {
+        let config = Config {
+            max_request_line_bytes: 64,
+            request_line_timeout_secs: 1,
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        // Too many bytes without a line break
+        let mut stream = MockStream::new(&format!(
+            "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "a".repeat(100)
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+
+        // Few bytes, too slowly
+        let mut stream = DribblingStream {
+            input: Cursor::new(b"GET /".to_vec()),
+            filler: b'a',
+            pause: Duration::from_millis(50),
+            bytes_read: 0,
+            output: Vec::new(),
+        };
+        let started = Instant::now();
+        server.handle_connection(&mut stream).unwrap();
+        let output = String::from_utf8_lossy(&stream.output).to_string();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(stream.bytes_read < 64);
+    }
+
+    #[test]
+    pub fn robots_txt_and_sitemap() {
+        let config = Config {
+            robots_txt: Some("User-agent: *\nDisallow: /private/\n".to_string()),
+            sitemap_base_url: Some("https://example.com".to_string()),
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream = MockStream::new("GET /robots.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nUser-agent: *\nDisallow: /private/\n"));
+
+        let mut stream = MockStream::new("GET /sitemap.xml HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            response_header(&output, "Content-Type"),
+            Some("application/xml")
+        );
+        assert!(output.contains("<loc>https://example.com/foo/</loc>"));
+
+        // Both are off by default
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream = MockStream::new("GET /sitemap.xml HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    pub fn extensionless_files_use_configured_content_type() {
+        let root = temp_document_root("extensionless");
+        fs::write(root.join("about"), "<html><body>About</body></html>").unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new("GET /about HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            response_header(&output, "Content-Type"),
+            Some("text/html; charset=UTF-8")
+        );
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            extensionless_content_type: "text/plain".to_string(),
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new("GET /about HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert_eq!(
+            response_header(&stream.output(), "Content-Type"),
+            Some("text/plain")
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn response_header_cap() {
+        logged_warnings();
+        let extra_headers = (0..5)
+            .map(|i| format!("X-Header-{i}: {i}{NEW_LINE}"))
+            .collect::<String>();
+
+        let server = Server::new(Config {
+            max_response_headers: 4,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new("");
+        server
+            .write_response(
+                &mut stream,
+                "299 Crowded",
+                &extra_headers,
+                b"",
+                Reply::last(),
+            )
+            .unwrap();
+        assert!(stream.output().contains("X-Header-4: 4\r\n"));
+        assert!(logged_warnings().iter().any(
+            |warning| warning == "Response 299 Crowded has 7 headers, more than the limit of 4"
+        ));
+
+        let server = Server::new(Config {
+            max_response_headers: 4,
+            truncate_response_headers: true,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new("");
+        server
+            .write_response(
+                &mut stream,
+                "299 Crowded",
+                &extra_headers,
+                b"",
+                Reply::last(),
+            )
+            .unwrap();
+        let output = stream.output();
+        assert!(output.contains("X-Header-1: 1\r\n"));
+        assert!(!output.contains("X-Header-2"));
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+        assert_eq!(response_header(&output, "Content-Length"), Some("0"));
+
+        // Not modified responses are capped as well
+        let server = Server::new(Config {
+            max_response_headers: 2,
+            truncate_response_headers: true,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new("");
+        server
+            .write_not_modified(
+                &mut stream,
+                Some("\"tag\""),
+                Some(SystemTime::UNIX_EPOCH),
+                Encoding::NegotiatedIdentity,
+                Reply::last(),
+            )
+            .unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(response_header(&output, "ETag"), Some("\"tag\""));
+        assert_eq!(response_header(&output, "Last-Modified"), None);
+        assert_eq!(response_header(&output, "Vary"), None);
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+    }
+
+    #[test]
+    pub fn negotiated_responses_vary_on_accept_encoding() {
+        let server = Server::new(Config::default()).unwrap();
+
+        for accept_encoding in ["Accept-Encoding: gzip\r\n", "Accept-Encoding: br\r\n", ""] {
+            let mut stream = MockStream::new(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\n{accept_encoding}\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            assert_eq!(
+                response_header(&output, "Vary"),
+                Some("Accept-Encoding"),
+                "{accept_encoding:?}"
+            );
+
+            let etag = response_header(&output, "ETag").unwrap().to_string();
+            let mut stream = MockStream::new(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\n{accept_encoding}If-None-Match: {etag}\r\n\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+            assert_eq!(response_header(&output, "Vary"), Some("Accept-Encoding"));
+        }
+    }
+
+    #[test]
+    pub fn prefix_redirect_preserves_tail_and_query() {
+        let config = Config {
+            redirects: vec![Redirect {
+                from: "/old/".to_string(),
+                to: "/".to_string(),
+                permanent: true,
+                prefix_rewrite: true,
+                host_pattern: None,
+            }],
+            ..Config::default()
+        };
+        let server = Server::new(config).unwrap();
+
+        let mut stream = MockStream::new("GET /old/foo/?a=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert_eq!(response_header(&output, "Location"), Some("/foo/?a=1"));
+        assert_eq!(response_header(&output, "Content-Length"), Some("0"));
+    }
+
+    #[test]
+    pub fn close_delimited_responses() {
+        let server = Server::new(Config {
+            close_delimited_responses: true,
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut stream = MockStream::new(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response_header(&output, "Content-Length"), None);
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+        // Only the first request is answered, the body runs until the close
+        assert_eq!(output.matches("HTTP/1.1 200 OK").count(), 1);
+        let (_, body) = split_response(&stream.output);
+        assert_eq!(body, fs::read("web_resources/index.html").unwrap());
+    }
+
+    #[test]
+    pub fn expect_header() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream =
+            MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\nExpect: something-else\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 417 Expectation Failed\r\n"));
+
+        let root = temp_document_root("expect-continue");
+        let submissions_file = root.join("submissions.log");
+        let server = Server::new(Config {
+            forms: vec![FormHandler {
+                path: "/contact".to_string(),
+                submissions_file: submissions_file.clone(),
+                max_body_bytes: 64,
+                success_redirect: None,
+                success_page: None,
+            }],
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new(
+            "POST /contact HTTP/1.1\r\nHost: localhost\r\nExpect: 100-Continue\r\nContent-Length: 3\r\n\r\na=1",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert_eq!(fs::read_to_string(&submissions_file).unwrap(), "a=1\n");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn custom_resolver_backend() {
+        /// Serves a single virtual page, without any file behind it
+        struct VirtualPages;
+
+        impl ResolverBackend for VirtualPages {
+            fn resolve(
+                &self,
+                path: &str,
+                headers: &Headers,
+            ) -> Result<ResolvedResource, ResolveError> {
+                match path {
+                    "/virtual" => Ok(ResolvedResource::Content {
+                        content_type: "text/plain".to_string(),
+                        content: format!("Hello {}", headers.get("host").unwrap_or("?")).into(),
+                    }),
+                    _ => Err(ResolveError::NotFound(PathBuf::from(path))),
+                }
+            }
+        }
+
+        let server = Server::new(Config::default())
+            .unwrap()
+            .with_resolver_backend(VirtualPages);
+
+        let mut stream = MockStream::new("GET /virtual HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response_header(&output, "Content-Type"), Some("text/plain"));
+        assert!(output.ends_with("\r\n\r\nHello example.com"));
+
+        // The document root is no longer consulted
+        let mut stream = MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    pub fn well_known_is_served_despite_dotfile_blocking() {
+        let root = temp_document_root("well-known");
+        fs::create_dir_all(root.join(".well-known/acme-challenge")).unwrap();
+        fs::write(root.join(".well-known/acme-challenge/token"), "proof").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+
+        for memory_snapshot in [false, true] {
+            let server = Server::new(Config {
+                document_root: root.clone(),
+                memory_snapshot,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let mut stream = MockStream::new(
+                "GET /.well-known/acme-challenge/token HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            );
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            assert!(
+                output.starts_with("HTTP/1.1 200 OK\r\n"),
+                "{memory_snapshot}"
+            );
+            assert!(output.ends_with("\r\n\r\nproof"));
+
+            for path in ["/.env", "/./.env", "/.well-known/../.env"] {
+                let mut stream =
+                    MockStream::new(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+                server.handle_connection(&mut stream).unwrap();
+                let output = stream.output();
+                assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"), "{path}");
+                assert!(!output.contains("SECRET"));
+            }
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn digest_header_matches_content() {
+        let server = Server::new(Config {
+            digest_header: true,
+            ..Config::default()
+        })
+        .unwrap();
+        let index = fs::read("web_resources/index.html").unwrap();
+
+        for _ in 0..2 {
+            let mut stream = MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            let (head, body) = split_response(&stream.output);
+            assert_eq!(body, index);
+            assert_eq!(
+                response_header(&head, "Digest"),
+                Some(digest::sha256(&index).as_str())
+            );
+        }
+
+        // The digest covers the gzipped bytes when the body is sent gzipped
+        let mut stream = MockStream::new(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        assert_eq!(response_header(&head, "Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response_header(&head, "Digest"),
+            Some(digest::sha256(body).as_str())
+        );
+
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream = MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert_eq!(response_header(&stream.output(), "Digest"), None);
+    }
+
+    #[test]
+    pub fn host_is_required_on_http_1_1() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let mut stream = MockStream::new("GET / HTTP/1.0\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.0 200 OK\r\n"));
+
+        let server = Server::new(Config {
+            require_host: false,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    pub fn transforms_rewrite_served_files() {
+        let root = temp_document_root("transform");
+        fs::write(root.join("notes.txt"), "shout this").unwrap();
+        fs::write(root.join("page.html"), "leave this").unwrap();
+
+        for memory_snapshot in [false, true] {
+            let server = Server::new(Config {
+                document_root: root.clone(),
+                memory_snapshot,
+                compression: false,
+                ..Config::default()
+            })
+            .unwrap()
+            .transform("TXT", |content| content.to_ascii_uppercase());
+
+            let mut stream = MockStream::new("GET /notes.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            let (head, body) = split_response(&stream.output);
+            assert_eq!(body, b"SHOUT THIS");
+            assert_eq!(response_header(&head, "Content-Length"), Some("10"));
+            assert_eq!(response_header(&head, "ETag"), None);
+
+            let mut stream = MockStream::new("GET /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            let (_, body) = split_response(&stream.output);
+            assert_eq!(body, b"leave this");
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn connection_lifetime_is_capped() {
+        let server = Server::new(Config::default()).unwrap();
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let serve = |expires| {
+            let mut stream = MockStream::new(&request.repeat(2));
+            server
+                .serve_connection(&mut stream, ConnectionInfo::default(), expires)
+                .unwrap();
+            stream.output()
+        };
+        let responses = |output: &str| {
+            output
+                .split("HTTP/1.1 200 OK\r\n")
+                .skip(1)
+                .map(|response| response_header(response, "Connection").unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let far_off = Instant::now() + Duration::from_secs(3600);
+        assert_eq!(
+            responses(&serve(Some(far_off))),
+            ["keep-alive", "keep-alive"]
+        );
+
+        // The request that finds the lifetime over is answered, as the last
+        assert_eq!(responses(&serve(Some(Instant::now()))), ["close"]);
+    }
+
+    #[test]
+    pub fn index_json_is_served_as_json() {
+        let root = temp_document_root("index-json");
+        fs::create_dir_all(root.join("api/users")).unwrap();
+        fs::write(root.join("api/users/index.json"), "[\"ada\"]").unwrap();
+
+        for memory_snapshot in [false, true] {
+            let server = Server::new(Config {
+                document_root: root.clone(),
+                index_files: vec!["index.html".to_string(), "index.json".to_string()],
+                memory_snapshot,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let mut stream = MockStream::new("GET /api/users/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            assert!(
+                output.starts_with("HTTP/1.1 200 OK\r\n"),
+                "{memory_snapshot}"
+            );
+            assert_eq!(
+                response_header(&output, "Content-Type"),
+                Some("application/json")
+            );
+            assert!(output.ends_with("\r\n\r\n[\"ada\"]"));
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn malformed_request_line_is_bad_request() {
+        let server = Server::new(Config::default()).unwrap();
+
+        for path in ["/a\0b", "/a\rb", "/caf\u{e9}"] {
+            let mut stream =
+                MockStream::new(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            let output = stream.output();
+            assert!(
+                output.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{path:?}"
+            );
+            assert_eq!(response_header(&output, "Connection"), Some("close"));
+        }
+    }
+
+    #[test]
+    pub fn connect_is_refused() {
+        let request = "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n";
+
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream = MockStream::new(request);
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert_eq!(response_header(&output, "Allow"), Some("GET, HEAD, POST"));
+
+        let server = Server::new(Config {
+            connect_response: ConnectResponse::NotImplemented,
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new(request);
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+    }
+
+    #[test]
+    pub fn directory_without_index_status() {
+        let root = temp_document_root("no-index");
+        fs::create_dir_all(root.join("assets")).unwrap();
+        fs::write(root.join("assets/app.js"), "run()").unwrap();
+
+        for (status, expected) in [
+            (NoIndexStatus::NotFound, "HTTP/1.1 404 Not Found\r\n"),
+            (NoIndexStatus::Forbidden, "HTTP/1.1 403 Forbidden\r\n"),
+        ] {
+            let server = Server::new(Config {
+                document_root: root.clone(),
+                directory_no_index_status: status,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let mut stream = MockStream::new("GET /assets/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            assert!(stream.output().starts_with(expected), "{status:?}");
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn get_body_is_discarded_before_the_next_request() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream = MockStream::new(concat!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 14\r\n\r\n",
+            "GET /nope HTTP",
+            "GET /foo/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        ));
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(output.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(output.ends_with(&fs::read_to_string("web_resources/foo/index.html").unwrap()));
+    }
+
+    #[test]
+    pub fn empty_document_root() {
+        let root = temp_document_root("empty-root");
+        fs::create_dir_all(root.join("assets")).unwrap();
+
+        logged_warnings();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            empty_root_landing_page: true,
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(logged_warnings()
+            .iter()
+            .any(|warning| warning.contains("holds no files")));
+
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(stream.output().ends_with(LANDING_PAGE_BODY));
+
+        // Only while the root is empty
+        fs::write(root.join("assets/app.js"), "run()").unwrap();
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn lowercase_method_case_handling() {
+        for (strict, expected) in [
+            (true, "HTTP/1.1 400 Bad Request\r\n"),
+            (false, "HTTP/1.1 200 OK\r\n"),
+        ] {
+            let server = Server::new(Config {
+                strict_method_case: strict,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let mut stream = MockStream::new("get / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            assert!(stream.output().starts_with(expected), "strict: {strict}");
+        }
+    }
+
+    #[test]
+    pub fn binary_files_are_served_byte_for_byte() {
+        let root = temp_document_root("binary");
+        let content = (0..=255u8).rev().collect::<Vec<_>>();
+        fs::write(root.join("logo.png"), &content).unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut stream = MockStream::new("GET /logo.png HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+
+        let (head, body) = split_response(&stream.output);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Length: 256\r\n"));
+        assert_eq!(body, content);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn environment_placeholders_are_substituted() {
+        let root = temp_document_root("env-template");
+        fs::write(
+            root.join("config.js"),
+            "window.API = '${TURBINE_ENV_TEST_API_URL}';",
+        )
+        .unwrap();
+        fs::write(root.join("data.bin"), "${TURBINE_ENV_TEST_API_URL}").unwrap();
+        std::env::set_var("TURBINE_ENV_TEST_API_URL", "https://api.example.com");
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            substituted_env_vars: vec!["TURBINE_ENV_TEST_API_URL".to_string()],
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut stream = MockStream::new("GET /config.js HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        let expected = "window.API = 'https://api.example.com';";
+        assert_eq!(body, expected.as_bytes());
+        assert!(head.contains(&format!("Content-Length: {}\r\n", expected.len())));
+
+        // Binary files are left alone
+        let mut stream = MockStream::new("GET /data.bin HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert_eq!(
+            split_response(&stream.output).1,
+            b"${TURBINE_ENV_TEST_API_URL}"
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn missing_files_are_answered_with_404() {
+        let server = Server::new(Config::default()).unwrap();
+
+        for path in ["/does-not-exist.html", "/../Cargo.toml", "/foo/missing/"] {
+            let mut stream =
+                MockStream::new(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+
+            let (head, body) = split_response(&stream.output);
+            assert!(head.starts_with("HTTP/1.1 404 Not Found\r\n"), "{path}");
+            assert_eq!(body, NOT_FOUND_BODY.as_bytes());
+            assert_eq!(
+                response_header(&head, "Content-Length"),
+                Some(NOT_FOUND_BODY.len().to_string().as_str())
+            );
+        }
+    }
+
+    #[test]
+    pub fn admin_listener_serves_only_admin_endpoints() {
+        let server = Server::new(Config {
+            version_endpoint: Some("/_turbine/version".to_string()),
+            metrics_endpoint: Some("/metrics".to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        let admin = Listener {
+            bind: "127.0.0.1:0".to_string(),
+            admin_only: true,
+            document_root: None,
+        };
+        let public = server.for_listener(&Listener::default(), true).unwrap();
+        let admin = server.for_listener(&admin, true).unwrap();
+
+        let request = |server: &Server, path: &str| {
+            let mut stream =
+                MockStream::new(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        assert!(request(&public, "/").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(request(&public, "/metrics").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let metrics = request(&admin, "/metrics");
+        assert!(metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+        // Counted across listeners, this connection included
+        assert!(metrics.contains("\nturbine_connections_total 3\n"));
+        assert!(request(&admin, "/_turbine/version").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(request(&admin, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(request(&admin, "/index.html").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    pub fn malformed_requests_are_answered() {
+        let server = Server::new(Config {
+            max_body_bytes: 4,
+            ..Config::default()
+        })
+        .unwrap();
+
+        for (request, status) in [
+            ("GET /\r\n\r\n", "400 Bad Request"),
+            (
+                "GET / HTTP/1.1\r\nHost localhost\r\n\r\n",
+                "400 Bad Request",
+            ),
+            (
+                "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: x\r\n\r\n",
+                "400 Bad Request",
+            ),
+            (
+                "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+                "413 Payload Too Large",
+            ),
+        ] {
+            let mut stream = MockStream::new(request);
+            server.handle_connection(&mut stream).unwrap();
+
+            let output = stream.output();
+            assert!(
+                output.starts_with(&format!("HTTP/1.1 {status}\r\n")),
+                "{request:?}"
+            );
+            assert_eq!(response_header(&output, "Connection"), Some("close"));
+            assert!(output.contains("</h1><p>"));
+        }
+    }
+
+    #[test]
+    pub fn failed_compression_falls_back_to_identity() {
+        struct FailingCompressor;
+
+        impl Compressor for FailingCompressor {
+            fn gzip(&self, _content: &[u8]) -> std::io::Result<Vec<u8>> {
+                Err(std::io::Error::new(
+                    ErrorKind::OutOfMemory,
+                    "allocation failed",
+                ))
+            }
+        }
+
+        let server = Server::new(Config {
+            compression: true,
+            ..Config::default()
+        })
+        .unwrap()
+        .with_compressor(FailingCompressor);
+
+        let mut stream =
+            MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+
+        let (head, body) = split_response(&stream.output);
+        let content = fs::read("web_resources/index.html").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response_header(&head, "Content-Encoding"), None);
+        assert_eq!(response_header(&head, "ETag"), None);
+        assert_eq!(response_header(&head, "Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            response_header(&head, "Content-Length"),
+            Some(content.len().to_string().as_str())
+        );
+        assert_eq!(body, content);
+    }
+
+    #[test]
+    pub fn unsupported_methods_are_not_allowed() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream = MockStream::new("DELETE / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert_eq!(response_header(&output, "Allow"), Some("GET, HEAD, POST"));
+
+        // A token that is no HTTP method at all is a malformed request
+        let mut stream = MockStream::new("FROB / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(response_header(&output, "Allow"), None);
+    }
+
+    #[test]
+    pub fn binds_configured_listen_addr() {
+        let free_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listen_addr = SocketAddr::from(([127, 0, 0, 1], free_port));
+
+        let server = Server::new(Config {
+            listen_addr,
+            ..Config::default()
+        })
+        .unwrap();
+
+        let listeners = server.bind().unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].1.local_addr().unwrap(), listen_addr);
+    }
+
+    #[test]
+    pub fn empty_files_optionally_answered_with_204() {
+        let root = temp_document_root("empty-file");
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("empty.txt"), "").unwrap();
+
+        for (empty_file_as_204, status) in [(false, "200 OK"), (true, "204 No Content")] {
+            let server = Server::new(Config {
+                document_root: root.clone(),
+                empty_file_as_204,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let mut stream = MockStream::new("GET /empty.txt HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            let (head, body) = split_response(&stream.output);
+            assert!(head.starts_with(&format!("HTTP/1.1 {status}\r\n")));
+            assert!(body.is_empty());
+            let content_length = response_header(&head, "Content-Length");
+            assert_eq!(content_length, (!empty_file_as_204).then_some("0"));
+
+            // Files with content are unaffected
+            let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn head_sends_the_headers_of_a_get() {
+        let server = Server::new(Config::default()).unwrap();
+        let size = fs::metadata("web_resources/index.html").unwrap().len();
+
+        let mut stream = MockStream::new(concat!(
+            "HEAD /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        ));
+        server.handle_connection(&mut stream).unwrap();
+
+        let (head, rest) = split_response(&stream.output);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            response_header(&head, "Content-Length"),
+            Some(size.to_string().as_str())
+        );
+        assert_eq!(
+            response_header(&head, "Content-Type"),
+            Some("text/html; charset=UTF-8")
+        );
+
+        // No body follows, the next bytes are the response to the GET
+        let (get_head, body) = split_response(rest);
+        assert!(get_head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body.len() as u64, size);
+    }
+
+    #[test]
+    pub fn head_answers_from_the_metadata() {
+        let root = temp_document_root("head-metadata");
+        fs::write(root.join("page.html"), "<p>hello</p>".repeat(100)).unwrap();
+        // Files are never read into memory, but can be opened to stream
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap()
+        .with_file_system(StreamOnlyFileSystem);
+        let head = |headers: &str| {
+            let mut stream = MockStream::new(&format!(
+                "HEAD /page.html HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        let output = head("");
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+        assert_eq!(response_header(&output, "Content-Length"), Some("1200"));
+        assert!(output.ends_with("\r\n\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn gzipped_head_has_the_length_of_the_get() {
+        let root = temp_document_root("head-gzip");
+        fs::write(root.join("page.html"), "<p>hello</p>".repeat(100)).unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            compressed_cache_max_bytes: 1024 * 1024,
+            ..Config::default()
+        })
+        .unwrap();
+        let request = |server: &Server, method: &str| {
+            let mut stream = MockStream::new(&format!(
+                "{method} /page.html HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            let (head, _) = split_response(&stream.output);
+            assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+            assert_eq!(response_header(&head, "Content-Encoding"), Some("gzip"));
+            response_header(&head, "Content-Length")
+                .unwrap()
+                .to_string()
+        };
+
+        // Compressed like for the GET while the cache is cold
+        let head = request(&server, "HEAD");
+        let get = request(&server, "GET");
+        assert_eq!(head, get);
+
+        // Then answered from the cached body, without reading the file
+        let server = server.with_file_system(StreamOnlyFileSystem);
+        assert_eq!(request(&server, "HEAD"), get);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn response_header_order_is_configurable() {
+        let header_names = |server: &Server| {
+            let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            let (head, _) = split_response(&stream.output);
+            head.lines()
+                .skip(1)
+                .map(|line| line.split(':').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let server = Server::new(Config::default()).unwrap();
+        let default_order = header_names(&server);
+        assert_eq!(default_order[0], "Content-Type");
+        for _ in 0..5 {
+            assert_eq!(header_names(&server), default_order);
+        }
+
+        let server = Server::new(Config {
+            response_header_order: vec![
+                "content-length".to_string(),
+                "Keep-Alive".to_string(),
+                "Content-Type".to_string(),
+            ],
+            ..Config::default()
+        })
+        .unwrap();
+        let order = header_names(&server);
+        assert_eq!(order[..3], ["Content-Length", "Keep-Alive", "Content-Type"]);
+        assert_eq!(order.len(), default_order.len());
+    }
+
+    #[test]
+    pub fn compressed_bodies_are_cached_until_modified() {
+        struct CountingCompressor(Arc<AtomicUsize>);
+
+        impl Compressor for CountingCompressor {
+            fn gzip(&self, content: &[u8]) -> std::io::Result<Vec<u8>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                compression::gzip(content)
+            }
+        }
+
+        let root = temp_document_root("compressed-cache");
+        let page = root.join("index.html");
+        fs::write(&page, "<p>hello</p>".repeat(50)).unwrap();
+
+        let compressions = Arc::new(AtomicUsize::new(0));
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            compression: true,
+            compressed_cache_max_bytes: 1024 * 1024,
+            ..Config::default()
+        })
+        .unwrap()
+        .with_compressor(CountingCompressor(Arc::clone(&compressions)));
+
+        let request = || {
+            let mut stream = MockStream::new(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\n",
+            );
+            server.handle_connection(&mut stream).unwrap();
+            split_response(&stream.output).1.to_vec()
+        };
+
+        let first = request();
+        assert_eq!(request(), first);
+        assert_eq!(compressions.load(Ordering::SeqCst), 1);
+
+        let modified = fs::metadata(&page).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&page)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        request();
+        assert_eq!(compressions.load(Ordering::SeqCst), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn post_body_is_read_by_content_length() {
+        let server = Server::new(Config::default()).unwrap();
+        let head = "POST /contact HTTP/1.1\r\nHost: localhost\r\nContent-Length: 16\r\n\r\n";
+        let body = "name=turbine&x=1";
+
+        let mut stream = BufReader::new(MockStream::new(&format!("{head}{body}GET")));
+        let request = server
+            .read_stream_content_to_end(&mut stream)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.body, body.as_bytes());
+
+        // Arriving a byte at a time
+        let mut stream = BufReader::new(DribblingStream {
+            input: Cursor::new(format!("{head}{body}").into_bytes()),
+            filler: b'x',
+            pause: Duration::ZERO,
+            bytes_read: 0,
+            output: Vec::new(),
+        });
+        let request = server
+            .read_stream_content_to_end(&mut stream)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.body, body.as_bytes());
+
+        // Fewer bytes than announced, then the client hangs up
+        let mut stream = BufReader::new(MockStream::new(&format!("{head}name")));
+        assert!(matches!(
+            server.read_stream_content_to_end(&mut stream),
+            Err(ParseError::IO(e)) if e.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    pub fn short_post_body_times_out() {
+        let server = Server::new(Config {
+            read_timeout_secs: 1,
+            ..Config::default()
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc")
+            .unwrap();
+
+        // The client keeps the connection open without sending the rest
+        let (accepted, _) = listener.accept().unwrap();
+        let mut stream = BufReader::new(accepted);
+        assert!(matches!(
+            server.read_stream_content_to_end(&mut stream),
+            Err(ParseError::RequestTimeout(timeout)) if timeout == Duration::from_secs(1)
+        ));
+        drop(client);
+    }
+
+    #[test]
+    pub fn stalled_request_is_answered_with_408() {
+        let server = Server::new(Config {
+            keep_alive_timeout_secs: 60,
+            read_timeout_secs: 1,
+            ..Config::default()
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+
+        // The headers are never finished
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n")
+            .unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+
+        let started = Instant::now();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert_eq!(response_header(&response, "Connection"), Some("close"));
+        // Well before the keep-alive timeout
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    pub fn pipelined_requests_after_close_are_not_served() {
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream = MockStream::new(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n\
+             GET /foo/ HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert_eq!(
+            server.handle_connection(&mut stream).unwrap(),
+            ConnectionEnd::Server
+        );
+
+        let output = stream.output();
+        assert_eq!(output.matches("HTTP/1.1 ").count(), 1);
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+
+        // Nothing is left to drain once the client stopped sending
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(
+            server.handle_connection(&mut stream).unwrap(),
+            ConnectionEnd::Client
+        );
+
+        // The client may keep sending after the close, the response is intact
+        let server = Server::new(Config {
+            pipelined_after_close: PipelinedAfterClose::Drain,
+            ..Config::default()
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+
+        let mut client = TcpStream::connect(address).unwrap();
+        let pipelined = "GET /foo/ HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(100);
+        client
+            .write_all(
+                format!(
+                    "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n{pipelined}"
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1);
+    }
+
+    #[test]
+    pub fn headers_split_across_reads() {
+        let server = Server::new(Config::default()).unwrap();
+        let head = "GET /foo/ HTTP/1.1\r\nHost: localhost\r\nAccept: text/html\r\n\r\n";
+        let mut stream = BufReader::new(DribblingStream {
+            input: Cursor::new(format!("{head}GET / HTTP/1.1\r\n").into_bytes()),
+            filler: b'x',
+            pause: Duration::ZERO,
+            bytes_read: 0,
+            output: Vec::new(),
+        });
+
+        let request = server
+            .read_stream_content_to_end(&mut stream)
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.headers.get("accept"), Some("text/html"));
+        assert!(request.body.is_empty());
+
+        // The next request is left in the stream
+        let mut next = String::new();
+        stream.read_line(&mut next).unwrap();
+        assert_eq!(next, "GET / HTTP/1.1\r\n");
+    }
+
+    #[test]
+    pub fn oversized_headers_are_rejected() {
+        let server = Server::new(Config {
+            max_header_bytes: 64,
+            ..Config::default()
+        })
+        .unwrap();
+        let cookie = "a".repeat(64);
+        let mut stream = MockStream::new(&format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {cookie}\r\n\r\n"
+        ));
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+
+        // A client that never ends its headers is cut off as well
+        let mut stream = BufReader::new(DribblingStream {
+            input: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n".to_vec()),
+            filler: b'x',
+            pause: Duration::ZERO,
+            bytes_read: 0,
+            output: Vec::new(),
+        });
+        assert!(matches!(
+            server.read_stream_content_to_end(&mut stream),
+            Err(ParseError::HeadersTooLarge(64))
+        ));
+    }
+
+    #[test]
+    pub fn listing_as_json() {
+        let root = temp_document_root("listing-json");
+        fs::create_dir_all(root.join("downloads/docs")).unwrap();
+        fs::write(root.join("downloads/a \"b\".txt"), "abc").unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            listing_paths: vec!["/downloads/".to_string()],
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new(
+            "GET /downloads/ HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+
+        let output = stream.output();
+        let (head, body) = split_response(&stream.output);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            response_header(&output, "Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(response_header(&output, "Vary"), Some("Accept"));
+
+        let body = std::str::from_utf8(body).unwrap();
+        assert!(body.starts_with(
+            "[{\"name\":\"a \\\"b\\\".txt\",\"size\":3,\"is_dir\":false,\"modified\":\""
+        ));
+        assert!(body.contains("},{\"name\":\"docs\",\"size\":"));
+        assert!(body.contains("\"is_dir\":true,\"modified\":\""));
+        assert!(body.ends_with("Z\"}]"));
+
+        // HTML stays the default
+        let mut stream = MockStream::new("GET /downloads/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .contains("<title>Index of /downloads/</title>"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn run_returns_after_shutdown() {
+        let server = Server::new(Config {
+            keep_alive_timeout_secs: 1,
+            shutdown_reject_secs: 0,
+            ..Config::default()
+        })
+        .unwrap();
+        let shutdown = Arc::clone(&server.shutdown);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let listeners = vec![(Listener::default(), listener)];
+            sender.send(server.run_listeners(listeners).is_ok())
+        });
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = [0; 16];
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"HTTP/1.1 200 OK\r");
+
+        // What the SIGINT and SIGTERM handlers do
+        shutdown.store(true, Ordering::SeqCst);
+        // The open keep-alive connection is waited for until it idles out
+        assert!(receiver.recv_timeout(Duration::from_secs(10)).unwrap());
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    pub fn nothing_acceptable() {
+        let varies_on_accept = |output: &str| {
+            response_header(output, "Vary")
+                .is_some_and(|vary| vary.split(", ").any(|header| header == "Accept"))
+        };
+        let root = temp_document_root("not-acceptable");
+        fs::create_dir_all(root.join("downloads")).unwrap();
+        fs::write(root.join("page.html"), "<p>page</p>").unwrap();
+
+        for strict_accept in [false, true] {
+            let server = Server::new(Config {
+                document_root: root.clone(),
+                listing_paths: vec!["/downloads/".to_string()],
+                strict_accept,
+                ..Config::default()
+            })
+            .unwrap();
+
+            for path in ["/page.html", "/downloads/"] {
+                let mut stream = MockStream::new(&format!(
+                    "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: application/xml, text/html;q=0\r\n\r\n"
+                ));
+                server.handle_connection(&mut stream).unwrap();
+
+                let expected = if strict_accept {
+                    "HTTP/1.1 406 Not Acceptable\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\n"
+                };
+                assert!(
+                    stream.output().starts_with(expected),
+                    "{path} {strict_accept}"
+                );
+                // Refused or not, the response depends on `Accept`
+                if strict_accept {
+                    assert!(varies_on_accept(&stream.output()), "{path}");
+                }
+            }
+
+            // A wildcard accepts anything
+            let mut stream = MockStream::new(
+                "GET /page.html HTTP/1.1\r\nHost: localhost\r\nAccept: application/xml, */*;q=0.1\r\n\r\n",
+            );
+            server.handle_connection(&mut stream).unwrap();
+            assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+            assert_eq!(varies_on_accept(&stream.output()), strict_accept);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn connections_during_shutdown_are_rejected() {
+        let server = Server::new(Config {
+            shutdown_reject_secs: 1,
+            ..Config::default()
+        })
+        .unwrap();
+        let shutdown = Arc::clone(&server.shutdown);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || sender.send(server.serve(listener).is_ok()));
+
+        shutdown.store(true, Ordering::SeqCst);
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert_eq!(response_header(&response, "Connection"), Some("close"));
+
+        // The listener closes once the window is over
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    pub fn percent_encoded_paths() {
+        let root = temp_document_root("percent-encoded");
+        fs::create_dir_all(root.join("site/my docs")).unwrap();
+        fs::write(root.join("site/my file.html"), "spaced").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.join("site"),
+            listing_paths: vec!["/my docs/".to_string()],
+            ..Config::default()
+        })
+        .unwrap();
+        let get = |target: &str| {
+            let mut stream =
+                MockStream::new(&format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        let output = get("/my%20file.html");
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.ends_with("\r\n\r\nspaced"));
+
+        // Listing paths match the decoded path
+        assert!(get("/my%20docs/").contains("<title>Index of /my docs/</title>"));
+
+        for traversal in ["/%2E%2E/secret.txt", "/%2e%2e%2fsecret.txt"] {
+            let output = get(traversal);
+            assert!(
+                output.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{traversal}"
+            );
+            assert!(!output.contains("secret"), "{traversal}");
+        }
+        assert!(get("/%zz").starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Shim counting how many reads overlap, each read taking a while
+    #[derive(Clone, Default)]
+    struct SlowFileSystem {
+        reading: Arc<AtomicUsize>,
+        most_reading: Arc<AtomicUsize>,
+    }
+
+    impl FileSystem for SlowFileSystem {
+        fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            let reading = self.reading.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_reading.fetch_max(reading, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            self.reading.fetch_sub(1, Ordering::SeqCst);
+            fs::read(path)
+        }
+
+        fn open(&self, path: &Path) -> std::io::Result<Box<dyn SeekableRead>> {
+            Ok(Box::new(fs::File::open(path)?))
+        }
+
+        fn metadata(&self, path: &Path) -> std::io::Result<std::fs::Metadata> {
+            fs::metadata(path)
+        }
+    }
+
+    #[test]
+    pub fn concurrent_reads_are_bounded() {
+        let file_system = SlowFileSystem::default();
+        let server = Arc::new(
+            Server::new(Config {
+                max_concurrent_reads: Some(1),
+                ..Config::default()
+            })
+            .unwrap()
+            .with_file_system(file_system.clone()),
+        );
+
+        let requests = (0..4)
+            .map(|_| {
+                let server = Arc::clone(&server);
+                thread::spawn(move || {
+                    let mut stream =
+                        MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+                    server.handle_connection(&mut stream).unwrap();
+                    stream.output()
+                })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            assert!(request.join().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+        assert_eq!(file_system.most_reading.load(Ordering::SeqCst), 1);
+
+        // No slot freeing up in time is a 503
+        let server = Server::new(Config {
+            max_concurrent_reads: Some(1),
+            read_slot_timeout_secs: 0,
+            ..Config::default()
+        })
+        .unwrap();
+        let _taken = server.file_reads.as_ref().unwrap().acquire(Duration::ZERO);
+        let mut stream = MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    }
+
+    #[test]
+    pub fn if_modified_since() {
+        let root = temp_document_root("if-modified-since");
+        fs::write(root.join("page.html"), "page").unwrap();
+        let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        fs::File::options()
+            .write(true)
+            .open(root.join("page.html"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        let get = |if_modified_since: &str| {
+            let mut stream = MockStream::new(&format!(
+                "GET /page.html HTTP/1.1\r\nHost: localhost\r\n{if_modified_since}\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        let output = get("");
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        let last_modified = response_header(&output, "Last-Modified").unwrap();
+        assert_eq!(last_modified, "Tue, 14 Nov 2023 22:13:20 GMT");
+
+        // The same second, although the file is half a second younger
+        let output = get(&format!("If-Modified-Since: {last_modified}\r\n"));
+        assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(
+            response_header(&output, "Last-Modified"),
+            Some(last_modified)
+        );
+        assert!(output.ends_with("\r\n\r\n"));
+        let output = get("If-Modified-Since: Wed, 15 Nov 2023 00:00:00 GMT\r\n");
+        assert!(output.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+
+        for stale in [
+            "If-Modified-Since: Tue, 14 Nov 2023 22:13:19 GMT\r\n",
+            "If-Modified-Since: last tuesday\r\n",
+            // A tag that does not match wins over a current date
+            "If-Modified-Since: Wed, 15 Nov 2023 00:00:00 GMT\r\nIf-None-Match: \"other\"\r\n",
+        ] {
+            let output = get(stale);
+            assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{stale}");
+            assert!(output.ends_with("page"));
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn range_requests() {
+        let root = temp_document_root("ranges");
+        let content = (0..100).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(root.join("video.bin"), &content).unwrap();
+        fs::write(root.join("page.html"), "0123456789").unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        let get = |path: &str, headers: &str| {
+            let mut stream = MockStream::new(&format!(
+                "GET {path} HTTP/1.1\r\nHost: localhost\r\n{headers}\r\n"
+            ));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output
+        };
+
+        let output = get("/video.bin", "Range: bytes=-10\r\n");
+        let (head, body) = split_response(&output);
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(
+            response_header(&head, "Content-Range"),
+            Some("bytes 90-99/100")
+        );
+        assert_eq!(response_header(&head, "Content-Length"), Some("10"));
+        assert_eq!(body, &content[90..]);
+
+        let output = get("/video.bin", "Range: bytes=100-200\r\n");
+        let (head, _) = split_response(&output);
+        assert!(head.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert_eq!(response_header(&head, "Content-Range"), Some("bytes */100"));
+
+        // Several ranges are answered with the whole file
+        let output = get("/video.bin", "Range: bytes=0-1,5-6\r\n");
+        let (head, body) = split_response(&output);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(response_header(&head, "Accept-Ranges"), Some("bytes"));
+        assert_eq!(body, content);
+
+        // A compressible file is cut uncompressed
+        let output = get(
+            "/page.html",
+            "Range: bytes=2-4\r\nAccept-Encoding: gzip\r\n",
+        );
+        let (head, body) = split_response(&output);
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(response_header(&head, "Content-Encoding"), None);
+        assert_eq!(body, b"234");
+
+        // An outdated If-Range gets the whole file, as does a weak tag
+        let etag = response_header(&head, "ETag").unwrap().to_string();
+        let output = get("/page.html", "Range: bytes=2-4\r\nIf-Range: \"old\"\r\n");
+        assert!(split_response(&output).0.starts_with("HTTP/1.1 200 OK\r\n"));
+        let output = get(
+            "/page.html",
+            &format!("Range: bytes=2-4\r\nIf-Range: {etag}\r\n"),
+        );
+        assert!(split_response(&output).0.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Shim refusing to read whole files, so only streamed responses succeed
+    struct StreamOnlyFileSystem;
+
+    impl FileSystem for StreamOnlyFileSystem {
+        fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            Err(std::io::Error::other(format!(
+                "{} read into memory",
+                path.display()
+            )))
+        }
+
+        fn open(&self, path: &Path) -> std::io::Result<Box<dyn SeekableRead>> {
+            Ok(Box::new(fs::File::open(path)?))
+        }
+
+        fn metadata(&self, path: &Path) -> std::io::Result<std::fs::Metadata> {
+            fs::metadata(path)
+        }
+    }
+
+    #[test]
+    pub fn large_files_are_streamed() {
+        let root = temp_document_root("stream");
+        let content = (0..4 * 1024 * 1024)
+            .map(|i: u32| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(root.join("video.bin"), &content).unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap()
+        .with_file_system(StreamOnlyFileSystem);
+
+        let mut stream = MockStream::new("GET /video.bin HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", content.len())));
+        assert!(head.contains("ETag: W/\""));
+        assert!(body == content, "streamed body differs from the file");
+
+        let mut stream = MockStream::new("HEAD /video.bin HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", content.len())));
+        assert!(body.is_empty());
+
+        // Ranges are streamed from their offset
+        let mut stream = MockStream::new(
+            "GET /video.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes=1000000-1000009\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let (head, body) = split_response(&stream.output);
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert_eq!(
+            response_header(&head, "Content-Range"),
+            Some(format!("bytes 1000000-1000009/{}", content.len()).as_str())
+        );
+        assert_eq!(body, &content[1_000_000..1_000_010]);
+
+        let mut stream = MockStream::new(&format!(
+            "GET /video.bin HTTP/1.1\r\nHost: localhost\r\nRange: bytes={}-\r\n\r\n",
+            content.len()
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn pipelined_requests_share_the_connection() {
+        let server = Server::new(Config::default()).unwrap();
+        let get = |path: &str, version: &str, connection: &str| {
+            format!("GET {path} {version}\r\nHost: localhost\r\n{connection}\r\n")
+        };
+
+        let mut stream = MockStream::new(&format!(
+            "{}{}",
+            get("/", "HTTP/1.1", ""),
+            get("/foo/", "HTTP/1.1", "")
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        let responses = output
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .collect::<Vec<_>>();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            response_header(responses[0], "Connection"),
+            Some("keep-alive")
+        );
+
+        // HTTP/1.0 closes after one response unless it asks to keep the connection
+        let mut stream = MockStream::new(&format!(
+            "{}{}",
+            get("/", "HTTP/1.0", ""),
+            get("/foo/", "HTTP/1.0", "")
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert_eq!(output.matches("HTTP/1.0 200 OK\r\n").count(), 1);
+        assert_eq!(response_header(&output, "Connection"), Some("close"));
+
+        let mut stream = MockStream::new(&format!(
+            "{}{}",
+            get("/", "HTTP/1.0", "Connection: keep-alive\r\n"),
+            get("/foo/", "HTTP/1.0", "Connection: keep-alive\r\n")
+        ));
+        server.handle_connection(&mut stream).unwrap();
+        assert_eq!(stream.output().matches("HTTP/1.0 200 OK\r\n").count(), 2);
+    }
+
+    #[test]
+    pub fn listing_of_the_whole_root() {
+        let root = temp_document_root("listing-root");
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::create_dir_all(root.join("site")).unwrap();
+        fs::write(root.join("my report.pdf"), "report").unwrap();
+        fs::write(root.join("notes.txt"), "notes").unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::write(root.join("site/index.html"), "site").unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            listing_paths: vec!["/".to_string()],
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut stream = MockStream::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(output.contains("<a href=\"/my%20report.pdf\">my report.pdf</a>"));
+        assert!(output.contains("<a href=\"/notes.txt\">notes.txt</a>"));
+        assert!(output.contains("<a href=\"/sub%20dir/\">sub dir/</a>"));
+        assert!(!output.contains(".env"));
+
+        // A directory with an index file is served as before
+        let mut stream = MockStream::new("GET /site/ HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().ends_with("\r\n\r\nsite"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn dotfiles_and_denied_paths_are_not_found() {
+        let root = temp_document_root("deny");
+        fs::create_dir_all(root.join("private")).unwrap();
+        fs::create_dir_all(root.join("public")).unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::write(root.join("private/report.pdf"), "report").unwrap();
+        fs::write(root.join("private/draft.html"), "draft").unwrap();
+        fs::write(root.join("public/page.html"), "page").unwrap();
+        fs::write(root.join("public/page.html.bak"), "old page").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            root.join("private/report.pdf"),
+            root.join("public/report.pdf"),
+        )
+        .unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            deny: vec!["/private/".to_string(), "*.bak".to_string()],
+            listing_paths: vec!["/public/".to_string()],
+            sitemap_base_url: Some("https://example.com".to_string()),
+            ..Config::default()
+        })
+        .unwrap();
+        let get = |path: &str| {
+            let mut stream =
+                MockStream::new(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        for path in [
+            "/.env",
+            "/%2eenv",
+            "/./.env",
+            "/public/../.env",
+            "/private/report.pdf",
+            "/PRIVATE/../private/report.pdf",
+            "/public/page.html.bak",
+            "/public/page.html%2ebak",
+        ] {
+            let output = get(path);
+            assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"), "{path}");
+            assert!(!output.contains("SECRET") && !output.contains("report\r\n"));
+        }
+        #[cfg(unix)]
+        assert!(get("/public/report.pdf").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        assert!(get("/public/page.html").ends_with("\r\n\r\npage"));
+        let listing = get("/public/");
+        assert!(listing.contains("page.html"));
+        assert!(!listing.contains("page.html.bak") && !listing.contains("report.pdf"));
+
+        // Denied pages are not advertised either
+        let sitemap = get("/sitemap.xml");
+        assert!(sitemap.contains("<loc>https://example.com/public/page.html</loc>"));
+        assert!(!sitemap.contains("/private/"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn client_errors_are_logged_as_warnings() {
+        logged_warnings();
+        let root = temp_document_root("logged-warnings");
+        fs::write(root.join("logged-page.html"), "page").unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut stream = MockStream::new(
+            "GET /logged-missing.html HTTP/1.1\r\nHost: localhost\r\n\r\n\
+             GET /logged-page.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+
+        let missing = format!(
+            "GET /logged-missing.html 404 {} bytes",
+            stream.output().find("HTTP/1.1 200").unwrap()
+        );
+        let warnings = logged_warnings();
+        assert!(warnings.contains(&missing), "{warnings:?}");
+        // A 200 is logged at info, below the warnings
+        assert!(!warnings
+            .iter()
+            .any(|warning| warning.starts_with("GET /logged-page.html ")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn access_log_in_common_log_format() {
+        let root = temp_document_root("access-log");
+        fs::write(root.join("page.html"), "page").unwrap();
+        let log_path =
+            std::env::temp_dir().join(format!("turbine-{}-access.log", std::process::id()));
+        let _ = fs::remove_file(&log_path);
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            access_log: Some(log_path.clone()),
+            ..Config::default()
+        })
+        .unwrap();
+        let mut stream = MockStream::new(
+            "GET /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n\
+             HEAD /missing.html HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        server
+            .handle_connection_from(
+                &mut stream,
+                ConnectionInfo {
+                    peer: Some("198.51.100.4:41000".parse().unwrap()),
+                },
+            )
+            .unwrap();
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for (line, request, status) in [
+            (lines[0], "\"GET /page.html HTTP/1.1\"", "200 4"),
+            (lines[1], "\"HEAD /missing.html HTTP/1.1\"", "404 -"),
+        ] {
+            let (prefix, rest) = line.split_once(" [").unwrap();
+            assert_eq!(prefix, "198.51.100.4 - -");
+            let (date, rest) = rest.split_once("] ").unwrap();
+            assert!(date.ends_with(" +0000") && date.len() == 26, "{date}");
+            assert_eq!(rest, format!("{request} {status}"));
+        }
+
+        fs::remove_file(log_path).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn peer_address_reaches_the_access_log() {
+        let log_path =
+            std::env::temp_dir().join(format!("turbine-{}-peer-access.log", std::process::id()));
+        let _ = fs::remove_file(&log_path);
+        let server = Server::new(Config {
+            access_log: Some(log_path.clone()),
+            ..Config::default()
+        })
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert!(log.starts_with("127.0.0.1 - - ["), "{log}");
+        fs::remove_file(log_path).unwrap();
+    }
+
+    #[test]
+    pub fn clients_over_their_rate_limit_are_refused() {
+        let root = temp_document_root("rate-limit");
+        fs::write(root.join("page.html"), "page").unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            rate_limit: Some(RateLimit {
+                requests_per_sec: 0.5,
+                burst: 2,
+            }),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let request = "GET /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut stream = MockStream::new(&request.repeat(3));
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert_eq!(output.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        let (_, refused) = output
+            .split_once("HTTP/1.1 429 Too Many Requests\r\n")
+            .unwrap();
+        assert!(refused.contains("Retry-After: 2\r\n"), "{refused}");
+        assert!(refused.ends_with(TOO_MANY_REQUESTS_BODY));
+
+        // Another client still has its whole burst
+        let mut stream = MockStream::new(request);
+        server
+            .handle_connection_from(
+                &mut stream,
+                ConnectionInfo {
+                    peer: Some("198.51.100.4:41000".parse().unwrap()),
+                },
+            )
+            .unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 200 OK\r\n"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn responses_in_the_request_version() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream = MockStream::new(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+             HEAD /missing HTTP/1.0\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.0 200 OK\r\n"), "{output}");
+        assert!(output.contains("Connection: keep-alive\r\n"));
+        assert!(output.contains("HTTP/1.0 404 Not Found\r\n"));
+    }
+
+    #[test]
+    pub fn unsupported_version_is_refused() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut stream = MockStream::new("GET / HTTP/2.0\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream
+            .output()
+            .starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+
+        let mut stream = MockStream::new("GET / HTTP/one\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert!(stream.output().starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    pub fn virtual_hosts_have_document_roots_of_their_own() {
+        let root = temp_document_root("vhost-default");
+        let blog = temp_document_root("vhost-blog");
+        let shop = temp_document_root("vhost-shop");
+        for (directory, content) in [(&root, "default"), (&blog, "blog"), (&shop, "shop")] {
+            fs::write(directory.join("page.html"), content).unwrap();
+        }
+
+        let config = Config {
+            document_root: root.clone(),
+            virtual_hosts: HashMap::from([
+                ("blog.example.com".to_string(), blog.clone()),
+                ("Shop.Example.com".to_string(), shop.clone()),
+            ]),
+            ..Config::default()
+        };
+        let server = Server::new(config.clone()).unwrap();
+        let get = |server: &Server, host: &str| {
+            let mut stream =
+                MockStream::new(&format!("GET /page.html HTTP/1.1\r\nHost: {host}\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        assert!(get(&server, "blog.example.com").ends_with("\r\n\r\nblog"));
+        assert!(get(&server, "shop.example.COM:8080").ends_with("\r\n\r\nshop"));
+        assert!(get(&server, "localhost").ends_with("\r\n\r\ndefault"));
+
+        let server = Server::new(Config {
+            virtual_host_fallback: false,
+            ..config
+        })
+        .unwrap();
+        assert!(get(&server, "blog.example.com").ends_with("\r\n\r\nblog"));
+        assert!(get(&server, "localhost").starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        for directory in [root, blog, shop] {
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn exact_redirects_by_permanence() {
+        let rule = |from: &str, to: &str, permanent| Redirect {
+            from: from.to_string(),
+            to: to.to_string(),
+            permanent,
+            prefix_rewrite: false,
+            host_pattern: None,
+        };
+        let server = Server::new(Config {
+            redirects: vec![
+                rule("/old.html", "/new.html", true),
+                rule("/sale/", "https://shop.example.com/", false),
+            ],
+            ..Config::default()
+        })
+        .unwrap();
+        let get = |path: &str| {
+            let mut stream =
+                MockStream::new(&format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        let output = get("/old.html");
+        assert!(output.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert_eq!(response_header(&output, "Location"), Some("/new.html"));
+        assert!(output.ends_with("\r\n\r\n"));
+
+        let output = get("/sale/");
+        assert!(output.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert_eq!(
+            response_header(&output, "Location"),
+            Some("https://shop.example.com/")
+        );
+
+        // The trailing slash is part of the exact path
+        assert!(get("/sale").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    pub fn directories_are_redirected_to_their_trailing_slash() {
+        let root = temp_document_root("trailing-slash");
+        fs::create_dir_all(root.join("sub dir")).unwrap();
+        fs::write(root.join("sub dir/index.html"), "index").unwrap();
+        fs::write(root.join("page.html"), "page").unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        let request = |head: &str| {
+            let mut stream = MockStream::new(&format!("{head}\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        for (head, location) in [
+            ("GET /sub%20dir HTTP/1.1", "/sub%20dir/"),
+            ("HEAD /sub%20dir?lang=en HTTP/1.1", "/sub%20dir/?lang=en"),
+        ] {
+            let output = request(head);
+            assert!(
+                output.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+                "{output}"
+            );
+            assert_eq!(response_header(&output, "Location"), Some(location));
+        }
+
+        assert!(request("GET /sub%20dir/ HTTP/1.1").ends_with("\r\n\r\nindex"));
+        assert!(request("GET /page.html HTTP/1.1").ends_with("\r\n\r\npage"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn configured_error_pages() {
+        let root = temp_document_root("error-pages");
+        fs::create_dir_all(root.join("errors")).unwrap();
+        fs::write(root.join("errors/404.html"), "<p>Lost?</p>").unwrap();
+        fs::write(root.join("50x.html"), "<p>Down</p>").unwrap();
+        let config = Config {
+            document_root: root.clone(),
+            error_pages: HashMap::from([
+                ("404".to_string(), PathBuf::from("errors/404.html")),
+                ("505".to_string(), PathBuf::from("50x.html")),
+            ]),
+            ..Config::default()
+        };
+        let server = Server::new(config.clone()).unwrap();
+        let request = |server: &Server, request_line: &str| {
+            let mut stream = MockStream::new(&format!("{request_line}\r\nHost: localhost\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        let output = request(&server, "GET /missing.html HTTP/1.1");
+        assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert_eq!(
+            response_header(&output, "Content-Type"),
+            Some("text/html; charset=UTF-8")
+        );
+        assert!(output.ends_with("\r\n\r\n<p>Lost?</p>"));
+
+        let output = request(&server, "GET / HTTP/2.0");
+        assert!(output.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        assert!(output.ends_with("\r\n\r\n<p>Down</p>"));
+
+        // Pages are read once, a page that cannot be read when the server
+        // starts leaves the built-in one in place
+        fs::remove_file(root.join("errors/404.html")).unwrap();
+        assert!(request(&server, "GET /missing.html HTTP/1.1").ends_with("<p>Lost?</p>"));
+        let server = Server::new(config).unwrap();
+        let output = request(&server, "GET /missing.html HTTP/1.1");
+        assert!(output.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(output.ends_with(NOT_FOUND_BODY));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Shim counting the files read into memory
+    struct CountingFileSystem {
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl FileSystem for CountingFileSystem {
+        fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            fs::read(path)
+        }
+
+        fn open(&self, path: &Path) -> std::io::Result<Box<dyn SeekableRead>> {
+            Ok(Box::new(fs::File::open(path)?))
+        }
+
+        fn metadata(&self, path: &Path) -> std::io::Result<std::fs::Metadata> {
+            fs::metadata(path)
+        }
+    }
+
+    #[test]
+    pub fn file_cache_serves_unchanged_files_from_memory() {
+        let root = temp_document_root("file-cache");
+        let page = root.join("page.html");
+        fs::write(&page, "first").unwrap();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            file_cache_max_bytes: 1024,
+            ..Config::default()
+        })
+        .unwrap()
+        .with_file_system(CountingFileSystem {
+            reads: Arc::clone(&reads),
+        });
+        let get = || {
+            let mut stream = MockStream::new("GET /page.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+
+        assert!(get().ends_with("\r\n\r\nfirst"));
+        assert!(get().ends_with("\r\n\r\nfirst"));
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        let modified = fs::metadata(&page).unwrap().modified().unwrap();
+        fs::write(&page, "second").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&page)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        assert!(get().ends_with("\r\n\r\nsecond"));
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn encoded_line_breaks_cannot_split_a_redirect() {
+        let server = Server::new(Config {
+            redirects: vec![Redirect {
+                from: "/old/".to_string(),
+                to: "/".to_string(),
+                permanent: true,
+                prefix_rewrite: true,
+                host_pattern: None,
+            }],
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut stream = MockStream::new(
+            "GET /old/x%0D%0ASet-Cookie:%20pwn=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(
+            output.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+            "{output}"
+        );
+        assert_eq!(response_header(&output, "Set-Cookie"), None);
+
+        let mut stream = MockStream::new("GET /old/my%20file HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        assert_eq!(
+            response_header(&stream.output(), "Location"),
+            Some("/my%20file")
+        );
+    }
+
+    #[test]
+    pub fn access_lines_carry_the_encoded_path() {
+        logged_warnings();
+        let server = Server::new(Config::default()).unwrap();
+        let mut stream =
+            MockStream::new("GET /access%20line/missing.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+
+        assert!(logged_warnings()
+            .iter()
+            .any(|warning| warning.starts_with("GET /access%20line/missing.html 404 ")));
+    }
+
+    #[test]
+    pub fn error_page_does_not_wait_for_a_read_slot() {
+        let root = temp_document_root("error-page-read-slot");
+        fs::write(root.join("index.html"), "index").unwrap();
+        fs::write(root.join("busy.html"), "<p>Busy</p>").unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            max_concurrent_reads: Some(1),
+            read_slot_timeout_secs: 0,
+            error_pages: HashMap::from([("503".to_string(), PathBuf::from("busy.html"))]),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let _taken = server.file_reads.as_ref().unwrap().acquire(Duration::ZERO);
+        let mut stream = MockStream::new("GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        server.handle_connection(&mut stream).unwrap();
+        let output = stream.output();
+        assert!(output.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        // Served from memory, not from a read slot that is still taken
+        assert!(output.ends_with("\r\n\r\n<p>Busy</p>"), "{output}");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Writer checking that no read slot is taken while a response is written
+    struct SlotCheckingStream {
+        file_reads: Arc<Semaphore>,
+        output: Vec<u8>,
+    }
+
+    impl Write for SlotCheckingStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            assert!(
+                self.file_reads.acquire(Duration::ZERO).is_some(),
+                "the read slot is held while writing"
+            );
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn streaming_holds_the_read_slot_only_while_reading() {
+        let root = temp_document_root("stream-read-slot");
+        let content = vec![b'x'; 3 * STREAM_CHUNK_BYTES];
+        fs::write(root.join("video.bin"), &content).unwrap();
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            stream_threshold_bytes: 1,
+            max_concurrent_reads: Some(1),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let request =
+            HttpRequest::parse(b"GET /video.bin HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let resource = root.join("video.bin");
+        let metadata = fs::metadata(&resource).unwrap();
+        let mut stream = SlotCheckingStream {
+            file_reads: Arc::clone(server.file_reads.as_ref().unwrap()),
+            output: Vec::new(),
+        };
+        server
+            .stream_file(&mut stream, &request, &resource, &metadata, Reply::last())
+            .unwrap();
+        assert!(stream.output.ends_with(&content));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn virtual_hosts_share_caches_budgets_and_the_watchdog() {
+        let root = temp_document_root("vhost-shared-default");
+        let blog = temp_document_root("vhost-shared-blog");
+        fs::write(root.join("page.html"), "root").unwrap();
+        fs::write(blog.join("page.html"), "blog").unwrap();
+
+        let config = Config {
+            document_root: root.clone(),
+            virtual_hosts: HashMap::from([("blog.example.com".to_string(), blog.clone())]),
+            memory_snapshot: true,
+            memory_snapshot_max_bytes: 8,
+            file_cache_max_bytes: 1024,
+            ..Config::default()
+        };
+        let server = Server::new(config.clone()).unwrap();
+        let blog_server = &server.virtual_hosts["blog.example.com"];
+        assert!(Arc::ptr_eq(
+            server.file_cache.as_ref().unwrap(),
+            blog_server.file_cache.as_ref().unwrap()
+        ));
+        assert!(Arc::ptr_eq(&server.digests, &blog_server.digests));
+
+        // Both snapshots together have to fit the budget
+        assert!(matches!(
+            Server::new(Config {
+                memory_snapshot_max_bytes: 7,
+                ..config.clone()
+            }),
+            Err(ServerError::Snapshot(SnapshotError::TooLarge(7)))
+        ));
+
+        // The watchdog looks after the document roots of the virtual hosts
+        let server = Server::new(Config {
+            memory_snapshot: false,
+            ..config
+        })
+        .unwrap();
+        fs::remove_dir_all(&blog).unwrap();
+        assert!(server.check_document_roots());
+        let get = |host: &str| {
+            let mut stream =
+                MockStream::new(&format!("GET /page.html HTTP/1.1\r\nHost: {host}\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            stream.output()
+        };
+        assert!(get("blog.example.com").starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(get("localhost").ends_with("\r\n\r\nroot"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    pub fn listeners_share_snapshots_and_maintenance_per_document_root() {
+        let root = temp_document_root("listener-shared-default");
+        let other = temp_document_root("listener-shared-other");
+        fs::write(root.join("page.html"), "root").unwrap();
+        fs::write(other.join("page.html"), "other").unwrap();
+
+        let server = Server::new(Config {
+            document_root: root.clone(),
+            memory_snapshot: true,
+            ..Config::default()
+        })
+        .unwrap();
+        let same = server.for_listener(&Listener::default(), false).unwrap();
+        let elsewhere = Listener {
+            document_root: Some(other.clone()),
+            ..Listener::default()
+        };
+        let elsewhere = server.for_listener(&elsewhere, false).unwrap();
+
+        // The document root is loaded once
+        assert!(Arc::ptr_eq(
+            server.snapshot.as_ref().unwrap(),
+            same.snapshot.as_ref().unwrap()
+        ));
+        assert!(!Arc::ptr_eq(
+            server.snapshot.as_ref().unwrap(),
+            elsewhere.snapshot.as_ref().unwrap()
+        ));
+
+        // Losing it puts every listener serving it in maintenance
+        fs::remove_dir_all(&root).unwrap();
+        assert!(same.check_document_roots());
+        assert!(server.maintenance.load(Ordering::SeqCst));
+        assert!(!elsewhere.maintenance.load(Ordering::SeqCst));
+
+        fs::remove_dir_all(other).unwrap();
+    }
+
+    #[test]
+    pub fn redirects_for_ipv6_hosts() {
+        let server = Server::new(Config {
+            redirects: vec![Redirect {
+                from: "/".to_string(),
+                to: "https://example.com/".to_string(),
+                permanent: true,
+                prefix_rewrite: true,
+                host_pattern: Some("[::1]".to_string()),
+            }],
+            ..Config::default()
+        })
+        .unwrap();
+        for host in ["[::1]", "[::1]:8080"] {
+            let mut stream =
+                MockStream::new(&format!("GET /a.html HTTP/1.1\r\nHost: {host}\r\n\r\n"));
+            server.handle_connection(&mut stream).unwrap();
+            assert_eq!(
+                response_header(&stream.output(), "Location"),
+                Some("https://example.com/a.html"),
+                "{host}"
+            );
+        }
     }
 }
@@ -0,0 +1,167 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::http::percent_encode;
+use crate::listing::escape_html;
+use crate::resolver::Resolver;
+
+/// A `sitemap.xml` of the HTML pages under the document root, regenerated at
+/// most once per `ttl`
+pub struct Sitemap {
+    /// Scheme and host the page paths are appended to, e.g. `https://example.com`
+    base_url: String,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, String)>>,
+}
+
+impl Sitemap {
+    pub fn new(base_url: &str, ttl: Duration) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the sitemap of the pages `resolver` serves, walking its
+    /// document root only when the cached one is older than the TTL
+    pub fn get(&self, resolver: &Resolver) -> std::io::Result<String> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some((generated, sitemap)) = cached.as_ref() {
+            if generated.elapsed() < self.ttl {
+                return Ok(sitemap.clone());
+            }
+        }
+
+        let mut pages = Vec::new();
+        html_pages(resolver, Path::new(""), "/", &mut pages)?;
+        pages.sort();
+
+        let sitemap = render(&self.base_url, &pages);
+        *cached = Some((Instant::now(), sitemap.clone()));
+
+        Ok(sitemap)
+    }
+}
+
+/// Collects the request paths of the HTML files under `relative`, a
+/// directory of the document root served at `request_path`. An `index.html`
+/// is listed as its directory.
+///
+/// Entries the resolver would not serve, hidden or denied, are left out.
+/// Symlinked directories are not walked, which keeps the walk inside the
+/// document root and free of cycles. A symlinked file is listed when what it
+/// points to may be served.
+fn html_pages(
+    resolver: &Resolver,
+    relative: &Path,
+    request_path: &str,
+    pages: &mut Vec<String>,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(resolver.document_root().join(relative))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative: PathBuf = relative.join(&name);
+
+        // Not following symlinks, unlike `Path::is_dir`
+        let file_type = entry.file_type()?;
+        let permitted = if file_type.is_symlink() {
+            entry.path().is_file() && resolver.permits_entry(&entry.path())
+        } else {
+            resolver.permits(&relative)
+        };
+        if !permitted {
+            continue;
+        }
+
+        let path = entry.path();
+        if file_type.is_dir() {
+            let request_path = format!("{request_path}{}/", percent_encode(&name));
+            html_pages(resolver, &relative, &request_path, pages)?;
+        } else if name == "index.html" {
+            pages.push(request_path.to_string());
+        } else if matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("html" | "htm")
+        ) {
+            pages.push(format!("{request_path}{}", percent_encode(&name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders the sitemap XML listing every one of the `pages` under `base_url`
+pub fn render(base_url: &str, pages: &[String]) -> String {
+    let mut sitemap = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+
+    for page in pages {
+        sitemap.push_str(&format!(
+            "<url><loc>{}</loc></url>\n",
+            escape_html(&format!("{base_url}{page}"))
+        ));
+    }
+
+    sitemap.push_str("</urlset>\n");
+
+    sitemap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn render_escapes_locations() {
+        let sitemap = render("https://example.com", &["/a&b.html".to_string()]);
+
+        assert!(sitemap.contains("<loc>https://example.com/a&amp;b.html</loc>"));
+    }
+
+    #[test]
+    pub fn pages_of_document_root() {
+        let sitemap = Sitemap::new("https://example.com/", Duration::from_secs(60));
+        let resolver = Resolver::new(fs::canonicalize("web_resources").unwrap());
+        let sitemap = sitemap.get(&resolver).unwrap();
+
+        assert!(sitemap.contains("<loc>https://example.com/</loc>"));
+        assert!(sitemap.contains("<loc>https://example.com/foo/bar/</loc>"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    pub fn symlinks_stay_inside_the_document_root() {
+        use std::os::unix::fs::symlink;
+
+        let base = std::env::temp_dir().join(format!("turbine-{}-sitemap", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(base.join("outside")).unwrap();
+        fs::write(root.join("docs/page.html"), "page").unwrap();
+        fs::write(base.join("outside/secret.html"), "secret").unwrap();
+        fs::write(root.join(".draft.html"), "draft").unwrap();
+        symlink(&root, root.join("docs/loop")).unwrap();
+        symlink(base.join("outside"), root.join("outside")).unwrap();
+        symlink(base.join("outside/secret.html"), root.join("secret.html")).unwrap();
+        symlink(root.join("docs/page.html"), root.join("alias.html")).unwrap();
+
+        let resolver = Resolver::new(fs::canonicalize(&root).unwrap());
+        let sitemap = Sitemap::new("https://example.com", Duration::from_secs(60))
+            .get(&resolver)
+            .unwrap();
+
+        assert!(sitemap.contains("<loc>https://example.com/docs/page.html</loc>"));
+        assert!(sitemap.contains("<loc>https://example.com/alias.html</loc>"));
+        assert!(!sitemap.contains("secret") && !sitemap.contains("loop"));
+        assert!(!sitemap.contains("draft"));
+
+        fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+use thiserror::Error;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Errors that can occur when loading the document root into memory
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+
+    #[error("Document root exceeds the snapshot limit of {0} bytes")]
+    TooLarge(u64),
+}
+
+/// Read-only, in-memory copy of the document root taken at startup.
+///
+/// Once loaded, requests are resolved and served from memory only, so changes
+/// made to the files on disk afterwards are never observed.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// File contents keyed by their path relative to the document root
+    files: HashMap<PathBuf, Vec<u8>>,
+
+    /// Every directory in the tree, relative to the document root
+    directories: HashSet<PathBuf>,
+
+    /// Total size of the files
+    bytes: u64,
+}
+
+/// Snapshots of every document root served, by a server along with its
+/// virtual hosts and listeners. A root is loaded once however many of them
+/// serve it, and all the snapshots together stay within one byte budget.
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    loaded: Mutex<HashMap<PathBuf, Arc<Snapshot>>>,
+}
+
+impl Snapshots {
+    /// The snapshot of the (canonicalized) `document_root`, loaded unless it
+    /// already is
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read or if the files go beyond
+    /// what the snapshots already loaded leave of `max_bytes`
+    pub fn get_or_load(
+        &self,
+        document_root: &Path,
+        max_bytes: u64,
+    ) -> Result<Arc<Snapshot>, SnapshotError> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(snapshot) = loaded.get(document_root) {
+            return Ok(Arc::clone(snapshot));
+        }
+
+        let used = loaded.values().map(|snapshot| snapshot.bytes).sum::<u64>();
+        let snapshot =
+            Snapshot::load(document_root, max_bytes.saturating_sub(used)).map_err(|e| match e {
+                SnapshotError::TooLarge(_) => SnapshotError::TooLarge(max_bytes),
+                e => e,
+            })?;
+        let snapshot = Arc::new(snapshot);
+        loaded.insert(document_root.to_path_buf(), Arc::clone(&snapshot));
+
+        Ok(snapshot)
+    }
+}
+
+impl Snapshot {
+    /// Walks the (canonicalized) document root and reads every file into memory
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a file cannot be read or if the total size of the
+    /// files goes beyond `max_bytes`
+    pub fn load(document_root: &Path, max_bytes: u64) -> Result<Self, SnapshotError> {
+        let mut snapshot = Snapshot::default();
+        let mut total_bytes = 0;
+        let mut pending = vec![document_root.to_path_buf()];
+
+        snapshot.directories.insert(PathBuf::new());
+
+        while let Some(directory) = pending.pop() {
+            for entry in fs::read_dir(directory)? {
+                // Follow symlinks, but only keep what still lives inside the root
+                let path = fs::canonicalize(entry?.path())?;
+                let Ok(relative) = path.strip_prefix(document_root) else {
+                    continue;
+                };
+                let relative = relative.to_path_buf();
+
+                if path.is_dir() {
+                    if snapshot.directories.insert(relative) {
+                        pending.push(path);
+                    }
+                    continue;
+                }
+
+                let content = fs::read(&path)?;
+                total_bytes += content.len() as u64;
+                if total_bytes > max_bytes {
+                    return Err(SnapshotError::TooLarge(max_bytes));
+                }
+
+                snapshot.files.insert(relative, content);
+            }
+        }
+
+        snapshot.bytes = total_bytes;
+        Ok(snapshot)
+    }
+
+    /// Looks up the content for a request resource such as `/foo/index.html`,
+    /// along with the path of the file relative to the document root
+    ///
+    /// Mirrors the filesystem resolver: the path must start with a slash, may
+    /// not climb above the document root and directories map to the first of
+    /// their `index_files` present
+    pub fn resolve(&self, resource: &str, index_files: &[String]) -> Option<(&Path, &[u8])> {
+        if !resource.starts_with('/') {
+            return None;
+        }
+
+        let mut relative = PathBuf::new();
+        for component in Path::new(resource).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::ParentDir if !relative.pop() => return None,
+                _ => {}
+            }
+        }
+
+        if self.directories.contains(&relative) {
+            let index = index_files
+                .iter()
+                .map(|name| relative.join(name))
+                .find(|index| self.files.contains_key(index))?;
+            relative = index;
+        }
+
+        self.files
+            .get_key_value(&relative)
+            .map(|(path, content)| (path.as_path(), content.as_slice()))
+    }
+}
@@ -0,0 +1,91 @@
+use log::warn;
+
+use std::collections::HashMap;
+use std::env;
+
+/// Replaces `${NAME}` placeholders in served text files by the value of the
+/// environment variable `NAME`, e.g. `${TURBINE_ENV_API_URL}`, so a frontend
+/// can be configured at deploy time. Only allowlisted variables are
+/// substituted, any other placeholder is left as is.
+#[derive(Debug, Clone, Default)]
+pub struct EnvTemplate {
+    values: HashMap<String, String>,
+}
+
+impl EnvTemplate {
+    pub fn new(values: HashMap<String, String>) -> Self {
+        Self { values }
+    }
+
+    /// Reads the `allowed` variables from the environment once, a variable
+    /// that is not set is logged and its placeholders left untouched
+    pub fn from_env(allowed: &[String]) -> Self {
+        let values = allowed
+            .iter()
+            .filter_map(|name| match env::var(name) {
+                Ok(value) => Some((name.clone(), value)),
+                Err(e) => {
+                    warn!("Environment variable {name} is not substituted: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        Self::new(values)
+    }
+
+    /// The content with every known placeholder substituted, `None` when it
+    /// holds none so the original content, and what is cached about it, can
+    /// be used as is
+    pub fn render(&self, content: &[u8]) -> Option<Vec<u8>> {
+        let mut rendered = Vec::with_capacity(content.len());
+        let mut substituted = false;
+        let mut rest = content;
+
+        while let Some(start) = rest.windows(2).position(|window| window == b"${") {
+            let after = &rest[start + 2..];
+            let value = after.iter().position(|byte| *byte == b'}').and_then(|end| {
+                let name = std::str::from_utf8(&after[..end]).ok()?;
+                Some((end, self.values.get(name)?))
+            });
+
+            match value {
+                Some((end, value)) => {
+                    rendered.extend_from_slice(&rest[..start]);
+                    rendered.extend_from_slice(value.as_bytes());
+                    rest = &after[end + 1..];
+                    substituted = true;
+                }
+                None => {
+                    rendered.extend_from_slice(&rest[..start + 2]);
+                    rest = after;
+                }
+            }
+        }
+
+        rendered.extend_from_slice(rest);
+        substituted.then_some(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn only_known_placeholders_are_substituted() {
+        let template = EnvTemplate::new(HashMap::from([(
+            "TURBINE_ENV_API_URL".to_string(),
+            "https://api.example.com".to_string(),
+        )]));
+
+        assert_eq!(
+            template
+                .render(b"fetch('${TURBINE_ENV_API_URL}/users'); const s = `${SECRET}` + '${'")
+                .unwrap(),
+            b"fetch('https://api.example.com/users'); const s = `${SECRET}` + '${'"
+        );
+        assert!(template.render(b"no ${PLACEHOLDER} here").is_none());
+        assert!(template.render(b"").is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::{self, ErrorKind, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Pause before retrying a write the peer was not ready for
+const WOULD_BLOCK_BACKOFF: Duration = Duration::from_millis(5);
+
+/// Writes the whole of `buf`, coping with writers that accept it piecemeal.
+///
+/// Unlike [Write::write_all], a write that fails with [ErrorKind::WouldBlock]
+/// (a slow reader on a socket with a write timeout, or a non-blocking socket)
+/// is retried until `timeout` has passed without any progress, at which point
+/// [ErrorKind::TimedOut] is returned. Interrupted writes are retried right away.
+pub fn write_fully<W: Write + ?Sized>(
+    writer: &mut W,
+    mut buf: &[u8],
+    timeout: Duration,
+) -> io::Result<()> {
+    let mut last_progress = Instant::now();
+
+    while !buf.is_empty() {
+        match writer.write(buf) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => {
+                buf = &buf[written..];
+                last_progress = Instant::now();
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if last_progress.elapsed() >= timeout {
+                    return Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        "peer stopped reading the response",
+                    ));
+                }
+                thread::sleep(WOULD_BLOCK_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// A connection whose read timeout can change while it is served, so that an
+/// idle keep-alive connection and a request being read get different limits
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, Some(timeout))
+    }
+}
+
+impl<T: ReadTimeout + ?Sized> ReadTimeout for &T {
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+impl<T: ReadTimeout + ?Sized> ReadTimeout for &mut T {
+    fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+/// End of the head of a response, the blank line after the last header
+const END_OF_HEAD: u32 = u32::from_be_bytes(*b"\r\n\r\n");
+
+/// Feeds `bytes` through `window`, the last four bytes of a response seen so
+/// far, up to the end of its head. Returns the index of the byte completing
+/// the blank line after the last header, if `bytes` hold it.
+pub fn find_end_of_head(window: &mut u32, bytes: &[u8]) -> Option<usize> {
+    bytes.iter().position(|byte| {
+        *window = (*window << 8) | u32::from(*byte);
+        *window == END_OF_HEAD
+    })
+}
+
+/// Passes the head of a response on to `inner` and drops its body, so a
+/// HEAD request is answered with the very headers a GET would get
+pub struct HeadOnly<W> {
+    inner: W,
+
+    /// The last four bytes passed on
+    window: u32,
+    head_done: bool,
+}
+
+impl<W: Write> HeadOnly<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            window: 0,
+            head_done: false,
+        }
+    }
+}
+
+impl<W: Write> Write for HeadOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.head_done {
+            return Ok(buf.len());
+        }
+
+        // Look for the end of the head without consuming anything yet, the
+        // inner writer may accept fewer bytes
+        let mut window = self.window;
+        let head_len = find_end_of_head(&mut window, buf).map_or(buf.len(), |end| end + 1);
+
+        let written = self.inner.write(&buf[..head_len])?;
+        find_end_of_head(&mut self.window, &buf[..written]);
+
+        if self.window == END_OF_HEAD {
+            self.head_done = true;
+            return Ok(buf.len());
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts at most 3 bytes per write and stalls on every other call,
+    /// alternating between interruptions and "not ready" errors
+    struct TrickleWriter {
+        received: Vec<u8>,
+        calls: usize,
+    }
+
+    impl Write for TrickleWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            match self.calls % 4 {
+                1 => Err(ErrorKind::Interrupted.into()),
+                3 => Err(ErrorKind::WouldBlock.into()),
+                _ => {
+                    let accepted = buf.len().min(3);
+                    self.received.extend_from_slice(&buf[..accepted]);
+                    Ok(accepted)
+                }
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Never accepts anything
+    struct StalledWriter;
+
+    impl Write for StalledWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(ErrorKind::WouldBlock.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn trickling_writer_receives_everything() {
+        let body: Vec<u8> = (0..=255).cycle().take(100).collect();
+        let mut writer = TrickleWriter {
+            received: Vec::new(),
+            calls: 0,
+        };
+
+        write_fully(&mut writer, &body, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(writer.received, body);
+    }
+
+    #[test]
+    pub fn stalled_writer_times_out() {
+        let result = write_fully(&mut StalledWriter, b"body", Duration::from_millis(20));
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    pub fn head_only_drops_the_body() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nbody";
+        let mut writer = TrickleWriter {
+            received: Vec::new(),
+            calls: 0,
+        };
+
+        let mut head_only = HeadOnly::new(&mut writer);
+        write_fully(&mut head_only, response, Duration::from_secs(1)).unwrap();
+        write_fully(&mut head_only, b"more body", Duration::from_secs(1)).unwrap();
+
+        assert_eq!(writer.received, &response[..response.len() - 4]);
+    }
+}
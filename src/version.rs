@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+/// Version of the turbine crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Commit turbine was built from, as reported by the build script
+pub const GIT_HASH: &str = env!("TURBINE_GIT_HASH");
+
+/// One line summary logged when the server starts
+pub fn banner() -> String {
+    format!("Starting turbine {VERSION} ({GIT_HASH})")
+}
+
+/// JSON document served by the version endpoint
+pub fn to_json(uptime: Duration) -> String {
+    format!(
+        "{{\"version\":\"{VERSION}\",\"git_hash\":\"{GIT_HASH}\",\"uptime_secs\":{}}}",
+        uptime.as_secs()
+    )
+}
@@ -0,0 +1,44 @@
+/// Whether `text` matches `pattern` as a whole, where `*` in the pattern
+/// stands for any run of characters, `/` included. The comparison is
+/// case-sensitive.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(start) => rest = &rest[start + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn wildcards() {
+        assert!(matches("*.bak", "notes.txt.bak"));
+        assert!(matches("*.bak", "/backups/db.bak"));
+        assert!(matches("/backups/*.sql", "/backups/2024/db.sql"));
+        assert!(matches("exact", "exact"));
+        assert!(matches("a*b*c", "a-b-b-c"));
+
+        assert!(!matches("exact", "exact-not"));
+        assert!(!matches("*.bak", "notes.bak.txt"));
+        assert!(!matches("/backups/*.sql", "/other/db.sql"));
+        assert!(!matches("a*ab", "ab"));
+    }
+}